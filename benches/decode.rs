@@ -0,0 +1,57 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Compares serial and parallel decoding of large synthetic animations.
+//!
+//! Run with `cargo bench --features rayon`.
+
+#[macro_use] extern crate criterion;
+extern crate ilda;
+
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use ilda::SimplePoint;
+use ilda::animation::Animation;
+use ilda::animation::Frame;
+
+/// Build an animation with the given number of frames and points per frame,
+/// returning its ILDA bytes.
+fn synthetic_file(frames: usize, points: usize) -> Vec<u8> {
+  let frames = (0..frames)
+      .map(|f| {
+        let points = (0..points)
+            .map(|p| SimplePoint {
+              x: (f * 7 + p * 13) as i16,
+              y: (f * 11 + p * 3) as i16,
+              r: p as u8,
+              g: f as u8,
+              b: (p + f) as u8,
+              is_blank: p % 16 == 0,
+            })
+            .collect();
+        Frame::new(points)
+      })
+      .collect();
+  Animation::new(frames).write_bytes().unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+  let mut group = c.benchmark_group("decode");
+  group.sample_size(10);
+
+  for &(frames, points) in &[(1_000, 500), (20_000, 200)] {
+    let bytes = synthetic_file(frames, points);
+    let label = format!("{}x{}", frames, points);
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+    group.bench_with_input(BenchmarkId::new("serial", &label), &bytes,
+        |b, bytes| b.iter(|| Animation::read_bytes(bytes).unwrap()));
+    group.bench_with_input(BenchmarkId::new("parallel", &label), &bytes,
+        |b, bytes| b.iter(|| Animation::read_bytes_parallel(bytes).unwrap()));
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
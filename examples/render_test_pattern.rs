@@ -36,9 +36,9 @@ pub fn main() {
     }
   }
 
-  let ref mut fout = File::create(&Path::new("output.png"))
+  let mut fout = File::create(Path::new("output.png"))
     .unwrap();
-  let _ = image::ImageRgb8(imgbuf).save(fout, image::PNG);
+  let _ = image::ImageRgb8(imgbuf).save(&mut fout, image::PNG);
 }
 
 /// Transform x-coordinate.
@@ -46,15 +46,15 @@ fn t_x(x : i16, img_width: u32) -> u32 {
   // FIXME: This is abhorrent.
   let ix = (x as i32).saturating_add(limit::MAX_X as i32);
   let scale = (img_width as f64) / (limit::WIDTH as f64);
-  ((ix as f64 * scale) as i32).abs() as u32
+  ((ix as f64 * scale) as i32).unsigned_abs()
 }
 
 /// Transform y-coordinate.
 fn t_y(y : i16, img_height: u32) -> u32 {
   // FIXME: This is abhorrent.
   // NB: Have to invert y since the vertical coordinate system transforms.
-  let iy = ((y * -1) as i32).saturating_add(limit::MAX_Y as i32);
+  let iy = (-(y as i32)).saturating_add(limit::MAX_Y as i32);
   let scale = (img_height as f64) / (limit::HEIGHT as f64);
-  ((iy as f64 * scale) as i32).abs() as u32
+  ((iy as f64 * scale) as i32).unsigned_abs()
 }
 
@@ -5,14 +5,42 @@
 //! simple representation that doesn't expose color palettes, indexed colors,
 //! and so forth.
 
-use color::default_color_index;
+use data::BLANK_BIT;
+use data::Format;
+use data::Header;
 use data::IldaEntry;
+use data::IndexedPoint2d;
+use data::LAST_POINT_BIT;
+use data::TrueColorPoint2d;
 use error::IldaError;
+use palette::Palette;
 use parser::read_bytes;
 use parser::read_file;
 use point::SimplePoint;
+use quantize::Quantization;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use writer;
+
+#[cfg(feature = "rayon")]
+use data::ColorPalette;
+#[cfg(feature = "rayon")]
+use data::HEADER_SIZE;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "rayon")]
+use std::fs::File;
+#[cfg(feature = "rayon")]
+use std::io::Read;
+#[cfg(feature = "rayon")]
+use view::IldaView;
+#[cfg(feature = "rayon")]
+use view::SectionView;
 
 /// An animation is comprised of one or more frames.
+///
+/// With the `serde` feature, the animation's palettes are serialized once in
+/// a `palettes` list, and each frame refers to its palette by index.
 #[derive(Clone)]
 pub struct Animation {
   frames: Vec<Frame>,
@@ -20,13 +48,28 @@ pub struct Animation {
 
 /// A single frame of animation, comprised of many points.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Frame {
+  #[cfg_attr(feature = "serde", serde(with = "serde_points"))]
   points: Vec<SimplePoint>,
+  #[cfg_attr(feature = "serde", serde(default))]
   frame_name: Option<String>,
+  #[cfg_attr(feature = "serde", serde(default))]
   company_name: Option<String>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  projector_number: u8,
+  #[cfg_attr(feature = "serde", serde(default))]
+  palette: Option<Arc<Palette>>,
+  #[cfg_attr(feature = "serde", serde(default))]
+  status_codes: Option<Vec<u8>>,
 }
 
 impl Animation {
+  /// Create an animation from the given frames.
+  pub fn new(frames: Vec<Frame>) -> Animation {
+    Animation { frames }
+  }
+
   /// Read an animation from an ILDA file.
   ///
   /// ```
@@ -38,13 +81,62 @@ impl Animation {
   /// ```
   pub fn read_file(filename: &str) -> Result<Animation, IldaError> {
     let entries = read_file(filename)?;
-    Animation::process_entries(entries)
+    Animation::from_entries(entries)
   }
 
   /// Read an animation from raw ILDA bytes.
   pub fn read_bytes(ilda_bytes: &[u8]) -> Result<Animation, IldaError> {
     let entries = read_bytes(ilda_bytes)?;
-    Animation::process_entries(entries)
+    Animation::from_entries(entries)
+  }
+
+  /// Read an animation from an ILDA file, decoding frames in parallel.
+  #[cfg(feature = "rayon")]
+  pub fn read_file_parallel(filename: &str) -> Result<Animation, IldaError> {
+    let mut contents = Vec::new();
+    File::open(filename)?.read_to_end(&mut contents)?;
+    Animation::read_bytes_parallel(&contents)
+  }
+
+  /// Read an animation from raw ILDA bytes, decoding frames in parallel.
+  ///
+  /// Sections are located first, and each frame is then decoded on its own.
+  /// The result is identical to `read_bytes`.
+  #[cfg(feature = "rayon")]
+  pub fn read_bytes_parallel(ilda_bytes: &[u8])
+      -> Result<Animation, IldaError> {
+    if ilda_bytes.len() < HEADER_SIZE {
+      return Err(IldaError::FileTooSmall);
+    }
+
+    // Palettes apply to every indexed frame that follows, so they're
+    // resolved up front.
+    let mut palette = Arc::new(Palette::standard());
+    let mut sections = Vec::new();
+    for section in IldaView::new(ilda_bytes).sections() {
+      let section = section?;
+      if section.get_format() == Format::ColorPalette && !section.is_empty() {
+        let colors = section.records()
+            .filter_map(|record| record.rgb())
+            .map(|(r, g, b)| ColorPalette { r, g, b })
+            .collect();
+        palette = Arc::new(Palette::truncated(colors));
+      } else {
+        sections.push((section, palette.clone()));
+      }
+    }
+
+    let frames = sections.par_iter()
+        .map(|(section, palette)| Frame::from_section(section, palette))
+        .collect::<Result<Vec<Frame>, IldaError>>()?;
+
+    if frames.is_empty() {
+      return Err(IldaError::NoData);
+    }
+
+    Ok(Animation {
+      frames,
+    })
   }
 
   /// Get an frame iterator for the animation.
@@ -57,7 +149,7 @@ impl Animation {
   pub fn into_point_iter<'a>(&'a self) -> AnimationPointIterator<'a> {
     AnimationPointIterator {
       animation: self,
-      current_frame: self.frames.get(0),
+      current_frame: self.frames.first(),
       frame_index: 0,
       point_index: 0,
     }
@@ -68,6 +160,11 @@ impl Animation {
     &self.frames
   }
 
+  /// Take the frames out of the animation.
+  pub fn into_frames(self) -> Vec<Frame> {
+    self.frames
+  }
+
   /// Return the number of frames in the animation.
   pub fn frame_count(&self) -> usize {
     self.frames.len()
@@ -78,39 +175,204 @@ impl Animation {
     self.frames.get(position)
   }
 
-  fn process_entries(entries: Vec<IldaEntry>) -> Result<Animation, IldaError> {
+  /// Write the animation to an ILDA file.
+  pub fn write_file(&self, filename: &str) -> Result<(), IldaError> {
+    writer::write_file(filename, &self.to_entries()?)
+  }
+
+  /// Write the animation to raw ILDA bytes.
+  pub fn write_bytes(&self) -> Result<Vec<u8>, IldaError> {
+    Ok(writer::write_bytes(&self.to_entries()?))
+  }
+
+  /// Convert the animation into True Color 2D (format 5) ILDA entries,
+  /// terminated by an empty header. Frame numbers and totals are counted
+  /// separately for each projector.
+  ///
+  /// Fails with `TooLarge` if a frame has more than 65535 points, or a
+  /// projector more than 65535 frames.
+  pub fn to_entries(&self) -> Result<Vec<IldaEntry>, IldaError> {
+    self.build_entries(5, |point, status_code| {
+      IldaEntry::TcPoint2dEntry(TrueColorPoint2d {
+        x: point.x,
+        y: point.y,
+        status_code,
+        r: point.r,
+        g: point.g,
+        b: point.b,
+      })
+    })
+  }
+
+  /// Write the animation to an indexed color ILDA file.
+  pub fn write_indexed_file(&self, filename: &str, quantization: &Quantization)
+      -> Result<(), IldaError> {
+    writer::write_file(filename, &self.to_indexed_entries(quantization)?)
+  }
+
+  /// Write the animation to indexed color ILDA bytes.
+  pub fn write_indexed_bytes(&self, quantization: &Quantization)
+      -> Result<Vec<u8>, IldaError> {
+    Ok(writer::write_bytes(&self.to_indexed_entries(quantization)?))
+  }
+
+  /// Convert the animation into Indexed 2D (format 1) ILDA entries, for
+  /// hardware and software that don't support true color. Colors are
+  /// quantized as given, and a color palette (format 2) section is emitted
+  /// first unless the standard palette is used.
+  pub fn to_indexed_entries(&self, quantization: &Quantization)
+      -> Result<Vec<IldaEntry>, IldaError> {
+    let palette = quantization.palette_for(self);
+
+    let mut entries = Vec::new();
+    if palette != Palette::standard() {
+      entries.extend(palette.to_entries(0));
+    }
+
+    entries.extend(self.build_entries(1, |point, status_code| {
+      let color_index = if point.is_blank {
+        0
+      } else {
+        palette.nearest(point.r, point.g, point.b)
+      };
+      IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+        x: point.x,
+        y: point.y,
+        status_code,
+        color_index,
+      })
+    })?);
+    Ok(entries)
+  }
+
+  // Emit a header per frame followed by its points, and a final empty header.
+  // A header without records would end the file early, so empty frames are
+  // written as a single blanked point. The terminator frame read from a file
+  // is dropped, since it's written again at the end. Status bits other than
+  // blanking and last point are carried over from the frame's status codes.
+  fn build_entries<F>(&self, format_code: u8, mut to_entry: F)
+      -> Result<Vec<IldaEntry>, IldaError>
+      where F: FnMut(&SimplePoint, u8) -> IldaEntry {
+    let mut frames: &[Frame] = &self.frames;
+    if let Some((last, rest)) = frames.split_last() {
+      if last.is_terminator() {
+        frames = rest;
+      }
+    }
+
+    let mut totals = BTreeMap::new();
+    for frame in frames {
+      *totals.entry(frame.projector_number).or_insert(0usize) += 1;
+    }
+    if totals.values().any(|&total| total > u16::MAX as usize) {
+      return Err(IldaError::TooLarge);
+    }
+
+    let blank = [SimplePoint { is_blank: true, ..SimplePoint::default() }];
+    let mut numbers = BTreeMap::new();
+    let mut entries = Vec::new();
+
+    for frame in frames {
+      let points: &[SimplePoint] = if frame.points.is_empty() {
+        &blank
+      } else {
+        &frame.points
+      };
+      if points.len() > u16::MAX as usize {
+        return Err(IldaError::TooLarge);
+      }
+
+      let number = numbers.entry(frame.projector_number).or_insert(0u16);
+
+      let mut header = Header::new(format_code);
+      header.name = frame.frame_name.clone();
+      header.company_name = frame.company_name.clone();
+      header.record_count = points.len() as u16;
+      header.number = *number;
+      header.total_frames = totals[&frame.projector_number] as u16;
+      header.projector_number = frame.projector_number;
+      entries.push(IldaEntry::HeaderEntry(header));
+      *number += 1;
+
+      let codes = frame.status_codes.as_deref().unwrap_or(&[]);
+      let last = points.len() - 1;
+      for (i, point) in points.iter().enumerate() {
+        let mut status_code = codes.get(i)
+            .map_or(0, |code| code & !(BLANK_BIT | LAST_POINT_BIT));
+        if point.is_blank {
+          status_code |= BLANK_BIT;
+        }
+        if i == last {
+          status_code |= LAST_POINT_BIT;
+        }
+        entries.push(to_entry(point, status_code));
+      }
+    }
+
+    entries.push(IldaEntry::HeaderEntry(Header::new(format_code)));
+    Ok(entries)
+  }
+
+  /// Build an animation from parsed ILDA entries.
+  pub fn from_entries(entries: Vec<IldaEntry>) -> Result<Animation, IldaError> {
     let mut frames = Vec::new();
     let mut current_frame = None;
+    let mut palette = Arc::new(Palette::standard());
+    let mut palette_colors = None;
 
     // NB: This does not check for format consistency.
     // Frame-type / point-type mismatch is allowed.
     for entry in entries {
       match entry {
         IldaEntry::HeaderEntry(mut header) => {
-          if current_frame.is_some() {
-            let frame = current_frame.take().unwrap();
+          if let Some(frame) = current_frame.take() {
             frames.push(frame);
           }
 
+          // A color palette applies to every indexed frame that follows.
+          if let Some(colors) = palette_colors.take() {
+            palette = Arc::new(Palette::truncated(colors));
+          }
+
+          if header.format_code == 2 && header.record_count > 0 {
+            palette_colors = Some(Vec::new());
+            continue;
+          }
+
+          let frame_palette = match header.get_format() {
+            Format::Indexed2d | Format::Indexed3d => Some(palette.clone()),
+            _ => None,
+          };
+
           current_frame = Some(Frame {
             points: Vec::new(),
             frame_name: header.name.take(),
             company_name: header.company_name.take(),
+            projector_number: header.projector_number,
+            palette: frame_palette,
+            status_codes: Some(Vec::new()),
           });
-
-          continue;
         },
-        _ => {},
+        IldaEntry::ColorPaletteEntry(color) => {
+          match palette_colors {
+            Some(ref mut colors) => colors.push(color),
+            None => return Err(IldaError::InvalidData),
+          }
+        },
+        entry => {
+          let frame = match current_frame {
+            // TODO: Better error type / message
+            None => return Err(IldaError::InvalidData),
+            Some(ref mut frame) => frame,
+          };
+
+          if let Some(ref mut codes) = frame.status_codes {
+            codes.extend(entry.status_code());
+          }
+          let point = palette_entry_to_point(entry, &palette)?;
+          frame.points.push(point);
+        },
       }
-
-      let mut frame = match current_frame {
-        // TODO: Better error type / message
-        None => return Err(IldaError::InvalidData),
-        Some(ref mut frame) => frame,
-      };
-
-      let point = ilda_entry_to_point(entry)?;
-      frame.points.push(point);
     }
 
     // Take the last frame.
@@ -124,22 +386,31 @@ impl Animation {
     }
 
     Ok(Animation {
-      frames: frames,
+      frames,
     })
   }
 }
 
 /// Convert an IldaEntry containing a point into a respective animation point.
-/// Color palettes and headers will return errors.
+/// Indexed colors are looked up in the standard palette. Color palettes and
+/// headers will return errors.
 pub fn ilda_entry_to_point(entry: IldaEntry) -> Result<SimplePoint, IldaError> {
+  palette_entry_to_point(entry, &Palette::standard())
+}
+
+/// Convert an IldaEntry containing a point into a respective animation point,
+/// looking up indexed colors in the given palette. Color palettes and headers
+/// will return errors.
+pub fn palette_entry_to_point(entry: IldaEntry, palette: &Palette)
+    -> Result<SimplePoint, IldaError> {
   match entry {
     IldaEntry::HeaderEntry(_) => {
       // Already handled by caller.
       Err(IldaError::InvalidData)
     },
     IldaEntry::ColorPaletteEntry(_) => {
-      // TODO: Handle color palettes.
-      Err(IldaError::Unsupported)
+      // Handled by caller.
+      Err(IldaError::InvalidData)
     },
     IldaEntry::TcPoint2dEntry(point) => {
       Ok(SimplePoint {
@@ -162,7 +433,7 @@ pub fn ilda_entry_to_point(entry: IldaEntry) -> Result<SimplePoint, IldaError> {
       })
     },
     IldaEntry::IdxPoint2dEntry(point) => {
-      let color = default_color_index(point.color_index);
+      let color = palette.color(point.color_index);
       Ok(SimplePoint {
         x: point.x,
         y: point.y,
@@ -173,7 +444,7 @@ pub fn ilda_entry_to_point(entry: IldaEntry) -> Result<SimplePoint, IldaError> {
       })
     },
     IldaEntry::IdxPoint3dEntry(point) => {
-      let color = default_color_index(point.color_index);
+      let color = palette.color(point.color_index);
       Ok(SimplePoint {
         x: point.x,
         y: point.y,
@@ -187,6 +458,51 @@ pub fn ilda_entry_to_point(entry: IldaEntry) -> Result<SimplePoint, IldaError> {
 }
 
 impl Frame {
+  /// Create an unnamed frame from the given points.
+  pub fn new(points: Vec<SimplePoint>) -> Frame {
+    Frame {
+      points,
+      frame_name: None,
+      company_name: None,
+      projector_number: 0,
+      palette: None,
+      status_codes: None,
+    }
+  }
+
+  /// Decode a frame from a section of points.
+  #[cfg(feature = "rayon")]
+  fn from_section(section: &SectionView, palette: &Arc<Palette>)
+      -> Result<Frame, IldaError> {
+    let mut points = Vec::with_capacity(section.len());
+    let mut status_codes = Vec::with_capacity(section.len());
+    for record in section.records() {
+      status_codes.push(record.status_code());
+      points.push(palette_entry_to_point(record.to_entry(), palette)?);
+    }
+
+    let frame_palette = match section.get_format() {
+      Format::Indexed2d | Format::Indexed3d => Some(palette.clone()),
+      _ => None,
+    };
+
+    Ok(Frame {
+      points,
+      frame_name: section.name(),
+      company_name: section.company_name(),
+      projector_number: section.projector_number(),
+      palette: frame_palette,
+      status_codes: Some(status_codes),
+    })
+  }
+
+  /// Replace the points in the frame. Any status codes read with the old
+  /// points are dropped.
+  pub fn set_points(&mut self, points: Vec<SimplePoint>) {
+    self.points = points;
+    self.status_codes = None;
+  }
+
   /// Get a reference to the points in the frame.
   pub fn get_points(&self) -> &Vec<SimplePoint> {
     &self.points
@@ -197,10 +513,95 @@ impl Frame {
     self.points.len()
   }
 
+  /// Whether this is the empty, unnamed frame read from the header that
+  /// terminates a file.
+  pub fn is_terminator(&self) -> bool {
+    self.points.is_empty() && self.frame_name.is_none()
+  }
+
   /// Get a reference to the point at the given offset, if it exists.
   pub fn get_point(&self, position: usize) -> Option<&SimplePoint> {
     self.points.get(position)
   }
+
+  /// Get the raw status code of each point, as read from an ILDA file. This
+  /// is `None` for frames that weren't read from a file, or whose points
+  /// have been replaced.
+  pub fn get_status_codes(&self) -> Option<&[u8]> {
+    self.status_codes.as_deref()
+  }
+
+  /// Set the raw status code of each point. There must be one per point.
+  pub fn set_status_codes(&mut self, status_codes: Option<Vec<u8>>)
+      -> Result<(), IldaError> {
+    match status_codes {
+      Some(ref codes) if codes.len() != self.points.len() => {
+        Err(IldaError::InvalidData)
+      },
+      status_codes => {
+        self.status_codes = status_codes;
+        Ok(())
+      },
+    }
+  }
+
+  /// Whether the point at the given offset had its last point bit set.
+  /// Returns `None` if status codes aren't known.
+  pub fn is_last_point(&self, position: usize) -> Option<bool> {
+    self.status_codes.as_ref()
+        .and_then(|codes| codes.get(position))
+        .map(|code| code & LAST_POINT_BIT == LAST_POINT_BIT)
+  }
+
+  /// Offset of the first point with its last point bit set, if status codes
+  /// are known and any point has it. A frame that ends correctly has it on
+  /// its final point only.
+  pub fn get_last_point_index(&self) -> Option<usize> {
+    self.status_codes.as_ref()?
+        .iter()
+        .position(|code| code & LAST_POINT_BIT == LAST_POINT_BIT)
+  }
+
+  /// Get the name of the frame, if one was supplied in its header.
+  pub fn get_frame_name(&self) -> Option<&str> {
+    self.frame_name.as_deref()
+  }
+
+  /// Get the company name for the frame, if one was supplied in its header.
+  pub fn get_company_name(&self) -> Option<&str> {
+    self.company_name.as_deref()
+  }
+
+  /// Set the name of the frame.
+  pub fn set_frame_name(&mut self, name: Option<String>) {
+    self.frame_name = name;
+  }
+
+  /// Set the company name for the frame.
+  pub fn set_company_name(&mut self, name: Option<String>) {
+    self.company_name = name;
+  }
+
+  /// Get the color palette used to resolve the frame's indexed colors. This
+  /// is `None` for true color frames.
+  pub fn get_palette(&self) -> Option<&Palette> {
+    self.palette.as_deref()
+  }
+
+  /// Set the color palette for the frame's indexed colors.
+  pub fn set_palette(&mut self, palette: Option<Palette>) {
+    self.palette = palette.map(Arc::new);
+  }
+
+  /// Get the projector the frame is addressed to.
+  pub fn get_projector_number(&self) -> u8 {
+    self.projector_number
+  }
+
+  /// Set the projector the frame is addressed to.
+  pub fn set_projector_number(&mut self, projector_number: u8) {
+    self.projector_number = projector_number;
+  }
 }
 
 /// Iterator over all the frames in the animation.
@@ -221,7 +622,7 @@ impl <'a> AnimationPointIterator<'a> {
   // Get the next point for the current frame and advance pointer.
   fn next_point_for_frame(&mut self) -> Option<&'a SimplePoint> {
     match self.current_frame {
-      None => return None, // Iteration has ended
+      None => None, // Iteration has ended
       Some(frame) => {
         match frame.get_point(self.point_index) {
           Some(point) => {
@@ -289,7 +690,170 @@ impl<'a> Iterator for FramePointIterator<'a> {
   }
 }
 
+// `SimplePoint` comes from another crate, so frames serialize their points
+// through a mirror of it.
+#[cfg(feature = "serde")]
+mod serde_points {
+  use point::SimplePoint;
+  use serde::Deserialize;
+  use serde::Deserializer;
+  use serde::Serializer;
+
+  #[derive(Serialize, Deserialize)]
+  struct Point {
+    x: i16,
+    y: i16,
+    r: u8,
+    g: u8,
+    b: u8,
+    is_blank: bool,
+  }
+
+  pub fn serialize<S>(points: &[SimplePoint], serializer: S)
+      -> Result<S::Ok, S::Error> where S: Serializer {
+    serializer.collect_seq(points.iter().map(|p| Point {
+      x: p.x,
+      y: p.y,
+      r: p.r,
+      g: p.g,
+      b: p.b,
+      is_blank: p.is_blank,
+    }))
+  }
+
+  pub fn deserialize<'de, D>(deserializer: D)
+      -> Result<Vec<SimplePoint>, D::Error> where D: Deserializer<'de> {
+    let points: Vec<Point> = Vec::deserialize(deserializer)?;
+    Ok(points.into_iter()
+        .map(|p| SimplePoint {
+          x: p.x,
+          y: p.y,
+          r: p.r,
+          g: p.g,
+          b: p.b,
+          is_blank: p.is_blank,
+        })
+        .collect())
+  }
+}
+
+// Animations serialize through mirrors of themselves that list each distinct
+// palette once, with frames referring to it by index.
+#[cfg(feature = "serde")]
+mod serde_animation {
+  use super::Animation;
+  use super::Frame;
+  use super::serde_points;
+  use palette::Palette;
+  use point::SimplePoint;
+  use serde::Deserialize;
+  use serde::Deserializer;
+  use serde::Serialize;
+  use serde::Serializer;
+  use serde::de::Error;
+  use std::sync::Arc;
+
+  #[derive(Serialize)]
+  struct AnimationRef<'a> {
+    palettes: Vec<&'a Palette>,
+    frames: Vec<FrameRef<'a>>,
+  }
+
+  #[derive(Serialize)]
+  struct FrameRef<'a> {
+    #[serde(serialize_with = "serde_points::serialize")]
+    points: &'a [SimplePoint],
+    frame_name: &'a Option<String>,
+    company_name: &'a Option<String>,
+    projector_number: u8,
+    palette: Option<usize>,
+    status_codes: &'a Option<Vec<u8>>,
+  }
+
+  #[derive(Deserialize)]
+  struct AnimationData {
+    #[serde(default)]
+    palettes: Vec<Palette>,
+    frames: Vec<FrameData>,
+  }
+
+  #[derive(Deserialize)]
+  struct FrameData {
+    #[serde(with = "serde_points")]
+    points: Vec<SimplePoint>,
+    #[serde(default)]
+    frame_name: Option<String>,
+    #[serde(default)]
+    company_name: Option<String>,
+    #[serde(default)]
+    projector_number: u8,
+    #[serde(default)]
+    palette: Option<usize>,
+    #[serde(default)]
+    status_codes: Option<Vec<u8>>,
+  }
+
+  impl Serialize for Animation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer {
+      let mut palettes: Vec<&Arc<Palette>> = Vec::new();
+      let mut frames = Vec::with_capacity(self.frames.len());
+      for frame in self.frames.iter() {
+        let palette = frame.palette.as_ref().map(|palette| {
+          palettes.iter()
+              .position(|p| Arc::ptr_eq(p, palette) || *p == palette)
+              .unwrap_or_else(|| {
+                palettes.push(palette);
+                palettes.len() - 1
+              })
+        });
+        frames.push(FrameRef {
+          points: &frame.points,
+          frame_name: &frame.frame_name,
+          company_name: &frame.company_name,
+          projector_number: frame.projector_number,
+          palette,
+          status_codes: &frame.status_codes,
+        });
+      }
+      AnimationRef {
+        palettes: palettes.into_iter().map(|p| &**p).collect(),
+        frames,
+      }.serialize(serializer)
+    }
+  }
+
+  impl<'de> Deserialize<'de> for Animation {
+    fn deserialize<D>(deserializer: D) -> Result<Animation, D::Error>
+        where D: Deserializer<'de> {
+      let data = AnimationData::deserialize(deserializer)?;
+      let palettes: Vec<Arc<Palette>> =
+          data.palettes.into_iter().map(Arc::new).collect();
+      let mut frames = Vec::with_capacity(data.frames.len());
+      for frame in data.frames {
+        let palette = match frame.palette {
+          Some(index) => Some(palettes.get(index).cloned().ok_or_else(|| {
+            D::Error::custom(format!("no palette at index {}", index))
+          })?),
+          None => None,
+        };
+        frames.push(Frame {
+          points: frame.points,
+          frame_name: frame.frame_name,
+          company_name: frame.company_name,
+          projector_number: frame.projector_number,
+          palette,
+          status_codes: frame.status_codes,
+        });
+      }
+      Ok(Animation { frames })
+    }
+  }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::field_reassign_with_default,
+    clippy::redundant_field_names)]
 mod tests {
   use super::*;
   use data::IldaEntry;
@@ -308,6 +872,9 @@ mod tests {
         points: points,
         frame_name: None,
         company_name: None,
+        projector_number: 0,
+        palette: None,
+        status_codes: None,
       }
     }
 
@@ -426,6 +993,325 @@ mod tests {
     assert_eq!(point.is_blank, true);
   }
 
+  #[test]
+  fn test_color_palette_section() {
+    let palette = Palette::from_text("10 20 30\n40 50 60").unwrap();
+    let point = IndexedPoint2d {
+      color_index: 1,
+      ..Default::default()
+    };
+
+    let mut entries = palette.to_entries(0);
+    let mut header = Header::new(1);
+    header.record_count = 1;
+    entries.push(IldaEntry::HeaderEntry(header));
+    entries.push(IldaEntry::IdxPoint2dEntry(point));
+    entries.push(IldaEntry::HeaderEntry(Header::new(5)));
+
+    let animation = Animation::read_bytes(&writer::write_bytes(&entries))
+        .unwrap();
+    assert_eq!(2, animation.frame_count());
+
+    let frame = animation.get_frame(0).unwrap();
+    assert_eq!(Some(&palette), frame.get_palette());
+    assert_eq!(40, frame.get_point(0).unwrap().r);
+    assert_eq!(None, animation.get_frame(1).unwrap().get_palette());
+  }
+
+  #[test]
+  fn test_write_bytes_round_trip() {
+    let mut first = frame(vec![point(1), point(2)]);
+    first.set_frame_name(Some("first".to_string()));
+    first.points[0].is_blank = true;
+    let mut second = frame(vec![point(3)]);
+    second.set_projector_number(2);
+
+    let animation = Animation::new(vec![first, second]);
+    let read = Animation::read_bytes(&animation.write_bytes().unwrap()).unwrap();
+
+    // The terminating header reads back as an empty frame.
+    assert_eq!(3, read.frame_count());
+    assert_eq!(0, read.get_frame(2).unwrap().point_count());
+
+    let first = read.get_frame(0).unwrap();
+    assert_eq!(Some("first"), first.get_frame_name());
+    assert!(first.get_point(0).unwrap().is_blank);
+    assert_eq!(2, first.get_point(1).unwrap().r);
+    assert_eq!(2, read.get_frame(1).unwrap().get_projector_number());
+  }
+
+  #[test]
+  fn test_status_codes() {
+    let mut header = Header::new(5);
+    header.record_count = 3;
+    let mut entries = vec![IldaEntry::HeaderEntry(header)];
+    for status_code in [64, 128, 0].iter() {
+      let point = TrueColorPoint2d {
+        status_code: *status_code,
+        ..Default::default()
+      };
+      entries.push(IldaEntry::TcPoint2dEntry(point));
+    }
+
+    let animation = Animation::from_entries(entries).unwrap();
+    let mut frame = animation.get_frame(0).unwrap().clone();
+    assert_eq!(Some(&[64, 128, 0][..]), frame.get_status_codes());
+    assert_eq!(Some(1), frame.get_last_point_index());
+    assert_eq!(Some(false), frame.is_last_point(2));
+
+    // Written frames are marked correctly.
+    let read = Animation::read_bytes(&animation.write_bytes().unwrap()).unwrap();
+    assert_eq!(Some(2), read.get_frame(0).unwrap().get_last_point_index());
+
+    // Other bits are kept, with blanking and last point set from the points.
+    frame.set_status_codes(Some(vec![64 | 1, 128 | 2, 4])).unwrap();
+    let written = Animation::new(vec![frame.clone()]).write_bytes().unwrap();
+    let read = Animation::read_bytes(&written).unwrap();
+    assert_eq!(Some(&[64 | 1, 2, 128 | 4][..]),
+        read.get_frame(0).unwrap().get_status_codes());
+
+    assert!(frame.set_status_codes(Some(vec![0])).is_err());
+    frame.set_points(Vec::new());
+    assert_eq!(None, frame.get_status_codes());
+    assert_eq!(None, frame.get_last_point_index());
+  }
+
+  #[test]
+  fn test_to_entries_status_and_numbering() {
+    let mut second = frame(vec![point(3)]);
+    second.set_projector_number(1);
+    let animation = Animation::new(vec![frame(vec![point(1), point(2)]),
+        second, frame(vec![point(4)])]);
+
+    let headers: Vec<_> = animation.to_entries().unwrap().into_iter()
+        .filter_map(|entry| match entry {
+          IldaEntry::HeaderEntry(header) => Some(header),
+          _ => None,
+        })
+        .map(|h| (h.number, h.total_frames, h.projector_number))
+        .collect();
+    assert_eq!(vec![(0, 2, 0), (0, 1, 1), (1, 2, 0), (0, 0, 0)], headers);
+
+    let status: Vec<_> = animation.to_entries().unwrap().into_iter()
+        .filter_map(|entry| match entry {
+          IldaEntry::TcPoint2dEntry(point) => Some(point.status_code),
+          _ => None,
+        })
+        .collect();
+    assert_eq!(vec![0, 128, 128, 128], status);
+  }
+
+  #[test]
+  fn test_write_empty_and_terminator_frames() {
+    let animation = Animation::new(vec![frame(vec![point(1)]),
+        frame(Vec::new()), frame(vec![point(2)])]);
+
+    let entries = animation.to_entries().unwrap();
+    let headers: Vec<_> = entries.iter()
+        .filter_map(|entry| match *entry {
+          IldaEntry::HeaderEntry(ref header) => Some(header.record_count),
+          _ => None,
+        })
+        .collect();
+    assert_eq!(vec![1, 1, 1, 0], headers);
+
+    // Writing what was read doesn't add another terminator.
+    let read = Animation::read_bytes(&animation.write_bytes().unwrap())
+        .unwrap();
+    assert_eq!(4, read.frame_count());
+    let again = Animation::read_bytes(&read.write_bytes().unwrap()).unwrap();
+    assert_eq!(4, again.frame_count());
+    assert!(again.get_frame(1).unwrap().get_point(0).unwrap().is_blank);
+  }
+
+  #[test]
+  fn test_write_too_many_points() {
+    let animation = Animation::new(vec![frame(vec![point(0); 65536])]);
+    assert!(matches!(animation.to_entries(), Err(IldaError::TooLarge)));
+
+    let animation = Animation::new(vec![frame(vec![point(0); 65535])]);
+    assert_eq!(65537, animation.to_entries().unwrap().len());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_round_trip() {
+    let mut first = frame(vec![point(1), point(2)]);
+    first.set_frame_name(Some("first".to_string()));
+    first.set_palette(Some(Palette::standard()));
+    first.points[1].is_blank = true;
+    let animation = Animation::new(vec![first, frame(vec![point(3)])]);
+
+    let json = ::serde_json::to_string(&animation).unwrap();
+    let read: Animation = ::serde_json::from_str(&json).unwrap();
+
+    assert_eq!(2, read.frame_count());
+    let first = read.get_frame(0).unwrap();
+    assert_eq!(Some("first"), first.get_frame_name());
+    assert_eq!(Some(&Palette::standard()), first.get_palette());
+    assert!(first.get_point(1).unwrap().is_blank);
+    assert_eq!(3, read.get_frame(1).unwrap().get_point(0).unwrap().r);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_shared_palettes() {
+    let custom = Palette::from_text("1 2 3\n4 5 6").unwrap();
+    let mut frames = Vec::new();
+    for palette in [Some(Palette::standard()), None, Some(custom.clone()),
+        Some(Palette::standard())].iter() {
+      let mut frame = frame(vec![point(1)]);
+      frame.set_palette(palette.clone());
+      frames.push(frame);
+    }
+    let animation = Animation::new(frames);
+
+    let json = ::serde_json::to_value(&animation).unwrap();
+    assert_eq!(2, json["palettes"].as_array().unwrap().len());
+    let indices: Vec<_> = json["frames"].as_array().unwrap().iter()
+        .map(|frame| frame["palette"].clone())
+        .collect();
+    assert_eq!(vec![::serde_json::json!(0), ::serde_json::Value::Null,
+        ::serde_json::json!(1), ::serde_json::json!(0)], indices);
+
+    let read: Animation = ::serde_json::from_value(json).unwrap();
+    assert_eq!(Some(&custom), read.get_frame(2).unwrap().get_palette());
+    assert!(Arc::ptr_eq(read.get_frame(0).unwrap().palette.as_ref().unwrap(),
+        read.get_frame(3).unwrap().palette.as_ref().unwrap()));
+
+    // Everything but the points can be left out.
+    let read: Animation = ::serde_json::from_str(
+        r#"{ "frames": [{ "points": [] }] }"#).unwrap();
+    assert_eq!(Some(0), read.get_frame(0).map(|f| f.get_projector_number()));
+
+    let missing = r#"{ "frames": [{ "points": [], "palette": 0 }] }"#;
+    assert!(::serde_json::from_str::<Animation>(missing).is_err());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_field_names() {
+    let json = ::serde_json::to_value(frame(vec![point(7)])).unwrap();
+    assert_eq!(::serde_json::json!({
+      "points": [{ "x": 0, "y": 0, "r": 7, "g": 7, "b": 7, "is_blank": false }],
+      "frame_name": null,
+      "company_name": null,
+      "projector_number": 0,
+      "palette": null,
+      "status_codes": null,
+    }), json);
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_read_bytes_parallel() {
+    use data::IndexedPoint3d;
+    use data::TrueColorPoint3d;
+
+    let mut named = Header::new(0);
+    named.record_count = 2;
+    named.name = Some("first".to_string());
+    named.projector_number = 2;
+    let mut entries = vec![
+      IldaEntry::HeaderEntry(named),
+      IldaEntry::IdxPoint3dEntry(IndexedPoint3d::default()),
+      IldaEntry::IdxPoint3dEntry(IndexedPoint3d {
+        x: 5, y: -5, z: 1, status_code: 192, color_index: 3,
+      }),
+    ];
+    entries.extend(Palette::from_text("1 2 3\n4 5 6").unwrap().to_entries(0));
+    let mut header = Header::new(1);
+    header.record_count = 1;
+    entries.push(IldaEntry::HeaderEntry(header));
+    entries.push(IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+      x: 9, y: 8, status_code: 128, color_index: 1,
+    }));
+    header = Header::new(4);
+    header.record_count = 1;
+    entries.push(IldaEntry::HeaderEntry(header));
+    entries.push(IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+      x: 1, y: 2, z: 3, status_code: 64, r: 7, g: 8, b: 9,
+    }));
+    entries.push(IldaEntry::HeaderEntry(Header::new(5)));
+
+    let example = include_bytes!("../examples/files/ildatest.ild");
+    for bytes in &[writer::write_bytes(&entries), example.to_vec()] {
+      assert!(assert_same_read(bytes));
+    }
+
+    let truncated = &writer::write_bytes(&entries)[..40];
+    assert!(matches!(Animation::read_bytes_parallel(truncated),
+        Err(IldaError::InvalidData)));
+    assert!(matches!(Animation::read_bytes_parallel(&example[..10]),
+        Err(IldaError::FileTooSmall)));
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  fn test_read_bytes_parallel_damaged() {
+    let mut entries = Palette::from_text("1 2 3").unwrap().to_entries(0);
+    let mut header = Header::new(1);
+    header.record_count = 2;
+    entries.push(IldaEntry::HeaderEntry(header));
+    for color_index in 0..2 {
+      entries.push(IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+        x: 1, y: 2, status_code: 64, color_index,
+      }));
+    }
+    entries.extend(Palette::from_text("4 5 6").unwrap().to_entries(0));
+    let bytes = writer::write_bytes(&entries);
+
+    for len in 0..bytes.len() {
+      assert_same_read(&bytes[..len]);
+    }
+    for i in 0..bytes.len() {
+      for flip in &[0x01, 0x02, 0x80, 0xff] {
+        let mut mutated = bytes.clone();
+        mutated[i] ^= flip;
+        assert_same_read(&mutated);
+      }
+    }
+
+    let example = include_bytes!("../examples/files/ildatest.ild");
+    for len in (0..example.len()).step_by(7) {
+      assert_same_read(&example[..len]);
+    }
+
+    let mut oversized = Header::new(2);
+    oversized.record_count = 257;
+    entries.push(IldaEntry::HeaderEntry(oversized));
+    entries.extend((0..257).map(|_| {
+      IldaEntry::ColorPaletteEntry(ColorPalette { r: 1, g: 2, b: 3 })
+    }));
+    assert_same_read(&writer::write_bytes(&entries));
+  }
+
+  // Check that the serial and parallel readers agree, returning whether the
+  // bytes were read.
+  #[cfg(feature = "rayon")]
+  fn assert_same_read(bytes: &[u8]) -> bool {
+    let (serial, parallel) = match (Animation::read_bytes(bytes),
+        Animation::read_bytes_parallel(bytes)) {
+      (Ok(serial), Ok(parallel)) => (serial, parallel),
+      (Err(a), Err(b)) => {
+        assert_eq!(format!("{:?}", a), format!("{:?}", b));
+        return false;
+      },
+      (a, b) => panic!("serial {:?}, parallel {:?}", a.err(), b.err()),
+    };
+
+    assert_eq!(serial.frame_count(), parallel.frame_count());
+    for (a, b) in serial.frames.iter().zip(parallel.frames.iter()) {
+      assert_eq!(a.get_frame_name(), b.get_frame_name());
+      assert_eq!(a.get_company_name(), b.get_company_name());
+      assert_eq!(a.get_projector_number(), b.get_projector_number());
+      assert_eq!(a.get_palette(), b.get_palette());
+      assert_eq!(a.get_status_codes(), b.get_status_codes());
+      assert_eq!(format!("{:?}", a.points), format!("{:?}", b.points));
+    }
+    true
+  }
+
   // Create sentinel value points.
   fn point(color: u8) -> SimplePoint {
     SimplePoint {
@@ -444,6 +1330,9 @@ mod tests {
       points: points,
       frame_name: None,
       company_name: None,
+      projector_number: 0,
+      palette: None,
+      status_codes: None,
     }
   }
 }
@@ -0,0 +1,110 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Minimal command line parsing: positional arguments, `--flag`s and
+//! `--option value` pairs.
+
+use std::str::FromStr;
+
+/// Parsed arguments for a subcommand.
+pub struct Args {
+  positional: Vec<String>,
+  flags: Vec<String>,
+  values: Vec<(String, String)>,
+}
+
+impl Args {
+  /// Parse arguments, accepting the given flags and options that take a
+  /// value.
+  pub fn parse(args: &[String], flags: &[&str], options: &[&str])
+      -> Result<Args, String> {
+    let mut parsed = Args {
+      positional: Vec::new(),
+      flags: Vec::new(),
+      values: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let name = arg.as_str();
+      if flags.contains(&name) {
+        parsed.flags.push(arg.clone());
+      } else if options.contains(&name) {
+        let value = args.next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        parsed.values.push((arg.clone(), value.clone()));
+      } else if name.starts_with('-') && name.len() > 1 {
+        return Err(format!("Unknown option {}", arg));
+      } else {
+        parsed.positional.push(arg.clone());
+      }
+    }
+
+    Ok(parsed)
+  }
+
+  /// Require exactly the given number of positional arguments.
+  pub fn expect_positional(&self, count: usize) -> Result<&[String], String> {
+    if self.positional.len() != count {
+      return Err(format!("Expected {} arguments, got {}", count,
+          self.positional.len()));
+    }
+    Ok(&self.positional)
+  }
+
+  /// Whether a flag was given.
+  pub fn flag(&self, name: &str) -> bool {
+    self.flags.iter().any(|flag| flag == name)
+  }
+
+  /// The last value given for an option.
+  pub fn value(&self, name: &str) -> Option<&str> {
+    self.values.iter()
+        .rev()
+        .find(|&(option, _)| option == name)
+        .map(|(_, value)| value.as_str())
+  }
+
+  /// The last value given for an option, parsed.
+  pub fn parse_value<T: FromStr>(&self, name: &str)
+      -> Result<Option<T>, String> {
+    match self.value(name) {
+      None => Ok(None),
+      Some(value) => value.parse()
+          .map(Some)
+          .map_err(|_| format!("Invalid value for {}: {}", name, value)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn test_parse() {
+    let args = Args::parse(&strings(&["in.ild", "--fit", "--scale", "0.5",
+        "out.ild", "--scale", "2"]), &["--fit"], &["--scale"]).unwrap();
+
+    assert_eq!(&strings(&["in.ild", "out.ild"])[..],
+        args.expect_positional(2).unwrap());
+    assert!(args.flag("--fit"));
+    assert!(!args.flag("--json"));
+    assert_eq!(Some(2.0), args.parse_value::<f64>("--scale").unwrap());
+    assert_eq!(None, args.parse_value::<u8>("--format").unwrap());
+    assert!(args.expect_positional(1).is_err());
+  }
+
+  #[test]
+  fn test_errors() {
+    assert!(Args::parse(&strings(&["--nope"]), &[], &[]).is_err());
+    assert!(Args::parse(&strings(&["--scale"]), &[], &["--scale"]).is_err());
+
+    let args = Args::parse(&strings(&["--scale", "x"]), &[], &["--scale"])
+        .unwrap();
+    assert!(args.parse_value::<f64>("--scale").is_err());
+  }
+}
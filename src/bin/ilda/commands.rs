@@ -0,0 +1,321 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! The `convert`, `diff`, `render` and `optimize` subcommands.
+
+use args::Args;
+use ilda::animation::Animation;
+use ilda::convert;
+use ilda::convert::Conversion;
+use ilda::data::Format;
+use ilda::data::IldaEntry;
+use ilda::diff;
+use ilda::diff::Change;
+use ilda::diff::DiffOptions;
+use ilda::morph;
+use ilda::optimize::Optimizer;
+use ilda::palette::Palette;
+use ilda::quantize;
+use ilda::render;
+use ilda::render::RenderOptions;
+use ilda::text;
+use ilda::writer;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// Change the format, palette or scale of a file.
+pub fn convert(args: &[String]) -> Result<(), String> {
+  let args = Args::parse(args, &["--fit"],
+      &["--format", "--palette", "--scale"])?;
+  let files = args.expect_positional(2)?;
+  let (input, output) = (&files[0], &files[1]);
+
+  let mut entries = read_entries(input)?;
+
+  if args.flag("--fit") {
+    convert::fit_entries(&mut entries);
+  }
+  if let Some(factor) = args.parse_value::<f64>("--scale")? {
+    convert::scale_entries(&mut entries, factor);
+  }
+
+  let format = match args.parse_value::<u8>("--format")? {
+    Some(code) => format_for_code(code)
+        .ok_or_else(|| format!("Unsupported format: {}", code))?,
+    None => first_frame_format(&entries),
+  };
+
+  let palette = match args.value("--palette") {
+    None => None,
+    Some(_) if !format.is_indexed() => {
+      return Err("--palette requires --format 0 or 1".to_string());
+    },
+    Some("standard") => Some(Palette::standard()),
+    Some(value) if value.starts_with("optimal") => {
+      let max_colors = match value.split(':').nth(1) {
+        None => 256,
+        Some(count) => count.parse()
+            .map_err(|_| format!("Invalid color count: {}", count))?,
+      };
+      let animation = Animation::from_entries(entries.clone())
+          .map_err(|e| ::error(input, &e))?;
+      Some(quantize::generate_palette(&animation, max_colors))
+    },
+    Some(filename) => {
+      Some(Palette::read_text_file(filename)
+          .map_err(|e| ::error(filename, &e))?)
+    },
+  };
+
+  let conversion = Conversion { z: 0, palette };
+  let converted = convert::convert_entries(&entries, format, &conversion)
+      .map_err(|e| ::error(input, &e))?;
+  let result = if is_text(output) {
+    text::write_file(output, &converted)
+  } else {
+    writer::write_file(output, &converted)
+  };
+  result.map_err(|e| ::error(output, &e))
+}
+
+/// Compare two files, returning whether they're the same within the
+/// tolerances.
+pub fn diff(args: &[String]) -> Result<bool, String> {
+  let args = Args::parse(args, &[], &["--tolerance", "--color-tolerance"])?;
+  let files = args.expect_positional(2)?;
+  let (old_file, new_file) = (&files[0], &files[1]);
+
+  let defaults = DiffOptions::default();
+  let options = DiffOptions {
+    tolerance: args.parse_value("--tolerance")?.unwrap_or(defaults.tolerance),
+    color_tolerance: args.parse_value("--color-tolerance")?
+        .unwrap_or(defaults.color_tolerance),
+  };
+
+  let old = Animation::read_file(old_file)
+      .map_err(|e| ::error(old_file, &e))?;
+  let new = Animation::read_file(new_file)
+      .map_err(|e| ::error(new_file, &e))?;
+  let diff = diff::diff(&old, &new, &options);
+
+  let mut out = String::new();
+  for index in diff.removed.iter() {
+    out.push_str(&format!("frame {} removed\n", index));
+  }
+  for index in diff.added.iter() {
+    out.push_str(&format!("frame {} added\n", index));
+  }
+  for frame in diff.changed.iter() {
+    out.push_str(&format!("frame {} -> {}:\n", frame.old_index,
+        frame.new_index));
+    for change in frame.changes.iter() {
+      out.push_str(&format!("  {}\n", describe(change)));
+    }
+  }
+  ::output(&out);
+
+  Ok(diff.is_empty())
+}
+
+/// Render frames to PNG or SVG images.
+pub fn render(args: &[String]) -> Result<(), String> {
+  let args = Args::parse(args, &["--show-blanking"],
+      &["--frame", "--sheet", "--size"])?;
+  let files = args.expect_positional(2)?;
+  let (input, output) = (&files[0], &files[1]);
+
+  let mut options = RenderOptions {
+    show_blanking: args.flag("--show-blanking"),
+    ..RenderOptions::default()
+  };
+  if let Some(size) = args.value("--size") {
+    let (width, height) = parse_size(size)
+        .ok_or_else(|| format!("Invalid size: {}", size))?;
+    options.width = width;
+    options.height = height;
+  }
+
+  let animation = Animation::read_file(input)
+      .map_err(|e| ::error(input, &e))?;
+  let svg = output.to_lowercase().ends_with(".svg");
+
+  if let Some(columns) = args.parse_value::<u32>("--sheet")? {
+    if svg {
+      return Err("Contact sheets can only be saved as PNG".to_string());
+    }
+    let sheet = render::contact_sheet(&animation, columns, &options)
+        .map_err(|e| ::error(output, &e))?;
+    return sheet.write_png(output).map_err(|e| ::error(output, &e));
+  }
+
+  let frames: Vec<usize> = match args.parse_value::<usize>("--frame")? {
+    Some(index) if index >= animation.frame_count() => {
+      return Err(format!("{}: no frame {}", input, index));
+    },
+    Some(index) => vec![index],
+    None => (0..animation.frame_count()).collect(),
+  };
+
+  for index in frames.iter().cloned() {
+    let filename = if frames.len() == 1 {
+      output.to_string()
+    } else {
+      numbered(output, index)
+    };
+    let frame = &animation.get_frames()[index];
+
+    let result = if svg {
+      File::create(&filename)
+          .and_then(|mut file| {
+            file.write_all(render::frame_to_svg(frame, &options).as_bytes())
+          })
+          .map_err(From::from)
+    } else {
+      render::render_frame(frame, &options).write_png(&filename)
+    };
+    result.map_err(|e| ::error(&filename, &e))?;
+  }
+
+  Ok(())
+}
+
+/// Reorder, blank and resample paths for output.
+pub fn optimize(args: &[String]) -> Result<(), String> {
+  let args = Args::parse(args, &["--no-reorder"],
+      &["--max-step", "--blank-step", "--dwell", "--end-dwell", "--points"])?;
+  let files = args.expect_positional(2)?;
+  let (input, output) = (&files[0], &files[1]);
+
+  let defaults = Optimizer::default();
+  let optimizer = Optimizer {
+    reorder: !args.flag("--no-reorder"),
+    max_lit_step: args.parse_value("--max-step")?,
+    max_blank_step: args.parse_value("--blank-step")?,
+    start_dwell: args.parse_value("--dwell")?.unwrap_or(defaults.start_dwell),
+    end_dwell: args.parse_value("--end-dwell")?.unwrap_or(defaults.end_dwell),
+  };
+
+  let mut animation = Animation::read_file(input)
+      .map_err(|e| ::error(input, &e))?;
+
+  // Resample first, so the dwell points added by optimizing stay put.
+  if let Some(count) = args.parse_value::<usize>("--points")? {
+    let frames = animation.into_frame_iter()
+        .map(|frame| {
+          let mut frame = frame.clone();
+          let points = morph::resample(&frame, count);
+          frame.set_points(points);
+          frame
+        })
+        .collect();
+    animation = Animation::new(frames);
+  }
+
+  optimizer.optimize_animation(&animation).write_file(output)
+      .map_err(|e| ::error(output, &e))
+}
+
+fn describe(change: &Change) -> String {
+  let name = |name: &Option<String>| match *name {
+    Some(ref name) => format!("{:?}", name),
+    None => "none".to_string(),
+  };
+  match *change {
+    Change::FrameName { ref old, ref new } => {
+      format!("name: {} -> {}", name(old), name(new))
+    },
+    Change::CompanyName { ref old, ref new } => {
+      format!("company: {} -> {}", name(old), name(new))
+    },
+    Change::ProjectorNumber { old, new } => {
+      format!("projector: {} -> {}", old, new)
+    },
+    Change::Palette => "palette changed".to_string(),
+    Change::PointCount { old, new } => format!("points: {} -> {}", old, new),
+    Change::Points(ref deviation) => {
+      format!("{} points moved (max {:.1}, mean {:.1} units), {} recolored",
+          deviation.moved, deviation.max_distance, deviation.mean_distance,
+          deviation.recolored)
+    },
+  }
+}
+
+fn read_entries(filename: &str) -> Result<Vec<IldaEntry>, String> {
+  if is_text(filename) {
+    return text::read_file(filename).map_err(|e| ::error(filename, &e));
+  }
+  let bytes = ::read_bytes(filename)?;
+  ::ilda::parser::read_bytes(&bytes).map_err(|e| ::error(filename, &e))
+}
+
+// Whether a file holds ILDA text rather than binary data.
+fn is_text(filename: &str) -> bool {
+  filename.to_lowercase().ends_with(".txt")
+}
+
+fn format_for_code(code: u8) -> Option<Format> {
+  match code {
+    0 => Some(Format::Indexed3d),
+    1 => Some(Format::Indexed2d),
+    4 => Some(Format::TrueColor3d),
+    5 => Some(Format::TrueColor2d),
+    _ => None,
+  }
+}
+
+// The format of the first frame, or true color 2D if there's none.
+fn first_frame_format(entries: &[IldaEntry]) -> Format {
+  entries.iter()
+      .filter_map(|entry| match *entry {
+        IldaEntry::HeaderEntry(ref header) if header.format_code != 2 => {
+          Some(header.get_format())
+        },
+        _ => None,
+      })
+      .next()
+      .unwrap_or(Format::TrueColor2d)
+}
+
+// Parse "512" or "640x480".
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+  let mut parts = size.splitn(2, 'x');
+  let width = parts.next()?.parse().ok()?;
+  let height = match parts.next() {
+    Some(height) => height.parse().ok()?,
+    None => width,
+  };
+  if width == 0 || height == 0 {
+    return None;
+  }
+  Some((width, height))
+}
+
+// Insert a frame number before the extension, eg. "out-0003.png".
+fn numbered(filename: &str, index: usize) -> String {
+  let path = Path::new(filename);
+  let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+  let name = match path.extension().and_then(|e| e.to_str()) {
+    Some(extension) => format!("{}-{:04}.{}", stem, index, extension),
+    None => format!("{}-{:04}", stem, index),
+  };
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_size() {
+    assert_eq!(Some((512, 512)), parse_size("512"));
+    assert_eq!(Some((640, 480)), parse_size("640x480"));
+    assert_eq!(None, parse_size("0"));
+    assert_eq!(None, parse_size("640x"));
+  }
+
+  #[test]
+  fn test_numbered() {
+    assert_eq!("out-0003.png", numbered("out.png", 3));
+    assert_eq!("dir/out-0012", numbered("dir/out", 12));
+  }
+}
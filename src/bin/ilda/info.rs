@@ -0,0 +1,361 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! The `info` subcommand: describe the sections in an ILDA file.
+
+use ilda::data::BLANK_BIT;
+use ilda::data::Format;
+use ilda::data::HEADER_SIZE;
+use ilda::data::Header;
+use ilda::data::IldaEntry;
+use ilda::validate;
+use ilda::validate::Diagnostic;
+use ilda::validate::ValidateOptions;
+use json::Json;
+use std::fmt::Write;
+
+/// Point statistics for a section, or a whole file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PointStats {
+  pub count: usize,
+  pub blanked: usize,
+  pub bounds: Option<Bounds>,
+}
+
+/// Extent of the points in a section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+  pub min_x: i16,
+  pub max_x: i16,
+  pub min_y: i16,
+  pub max_y: i16,
+  pub min_z: i16,
+  pub max_z: i16,
+}
+
+/// A header and a description of the records that follow it.
+pub struct Section {
+  pub offset: usize,
+  pub header: Header,
+  pub points: PointStats,
+  pub colors: usize,
+}
+
+/// Everything reported about a file.
+pub struct Summary {
+  pub file_size: usize,
+  pub sections: Vec<Section>,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl PointStats {
+  fn add(&mut self, x: i16, y: i16, z: i16, is_blank: bool) {
+    self.count += 1;
+    if is_blank {
+      self.blanked += 1;
+    }
+    self.extend(Bounds { min_x: x, max_x: x, min_y: y, max_y: y, min_z: z,
+        max_z: z });
+  }
+
+  fn merge(&mut self, other: &PointStats) {
+    self.count += other.count;
+    self.blanked += other.blanked;
+    if let Some(bounds) = other.bounds {
+      self.extend(bounds);
+    }
+  }
+
+  fn extend(&mut self, other: Bounds) {
+    self.bounds = Some(match self.bounds {
+      None => other,
+      Some(b) => Bounds {
+        min_x: b.min_x.min(other.min_x),
+        max_x: b.max_x.max(other.max_x),
+        min_y: b.min_y.min(other.min_y),
+        max_y: b.max_y.max(other.max_y),
+        min_z: b.min_z.min(other.min_z),
+        max_z: b.max_z.max(other.max_z),
+      },
+    });
+  }
+
+  fn lit(&self) -> usize {
+    self.count - self.blanked
+  }
+
+  fn blanked_ratio(&self) -> f64 {
+    if self.count == 0 { 0.0 } else { self.blanked as f64 / self.count as f64 }
+  }
+}
+
+impl Summary {
+  /// Describe parsed entries read from a file of the given size.
+  pub fn new(entries: &[IldaEntry], file_size: usize) -> Summary {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut offset = 0;
+
+    for entry in entries {
+      if let IldaEntry::HeaderEntry(ref header) = *entry {
+        if let Some(last) = sections.last() {
+          offset = last.offset + section_size(&last.header);
+        }
+        sections.push(Section {
+          offset,
+          header: header.clone(),
+          points: PointStats::default(),
+          colors: 0,
+        });
+        continue;
+      }
+
+      let section = match sections.last_mut() {
+        Some(section) => section,
+        None => continue,
+      };
+
+      let (x, y, z, status_code) = match *entry {
+        IldaEntry::IdxPoint3dEntry(ref p) => (p.x, p.y, p.z, p.status_code),
+        IldaEntry::IdxPoint2dEntry(ref p) => (p.x, p.y, 0, p.status_code),
+        IldaEntry::TcPoint3dEntry(ref p) => (p.x, p.y, p.z, p.status_code),
+        IldaEntry::TcPoint2dEntry(ref p) => (p.x, p.y, 0, p.status_code),
+        _ => {
+          section.colors += 1;
+          continue;
+        },
+      };
+
+      section.points.add(x, y, z, status_code & BLANK_BIT != 0);
+    }
+
+    let diagnostics = validate::validate(entries, &ValidateOptions::default());
+    Summary { file_size, sections, diagnostics }
+  }
+
+  /// Statistics over every point in the file.
+  pub fn totals(&self) -> PointStats {
+    let mut totals = PointStats::default();
+    for section in self.sections.iter() {
+      totals.merge(&section.points);
+    }
+    totals
+  }
+
+  fn frame_count(&self) -> usize {
+    self.sections.iter().filter(|s| is_frame(&s.header)).count()
+  }
+
+  fn palette_count(&self) -> usize {
+    self.sections.iter().filter(|s| s.header.format_code == 2).count()
+  }
+
+  /// Render as a human readable table.
+  pub fn to_table(&self) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{:>4}  {:>8}  {:<14}  {:<8}  {:<8}  {:>7}  {:>6}  \
+        {:>6}  {:>4}", "#", "Offset", "Format", "Name", "Company", "Records",
+        "Number", "Total", "Proj");
+
+    for (i, section) in self.sections.iter().enumerate() {
+      let header = &section.header;
+      let _ = writeln!(out, "{:>4}  {:>8}  {:<14}  {:<8}  {:<8}  {:>7}  {:>6}  \
+          {:>6}  {:>4}", i, section.offset, format_name(header),
+          display_name(&header.name), display_name(&header.company_name),
+          header.record_count, header.number, header.total_frames,
+          header.projector_number);
+    }
+
+    let totals = self.totals();
+    let _ = writeln!(out);
+    let _ = writeln!(out, "File size: {} bytes", self.file_size);
+    let _ = writeln!(out, "Frames: {}", self.frame_count());
+    let _ = writeln!(out, "Palettes: {}", self.palette_count());
+    let _ = writeln!(out, "Points: {} ({} lit, {} blanked, {:.1}% blanked)",
+        totals.count, totals.lit(), totals.blanked,
+        totals.blanked_ratio() * 100.0);
+    if let Some(b) = totals.bounds {
+      let _ = writeln!(out, "Bounds: x {}..{}, y {}..{}, z {}..{}",
+          b.min_x, b.max_x, b.min_y, b.max_y, b.min_z, b.max_z);
+    }
+
+    if self.diagnostics.is_empty() {
+      let _ = writeln!(out, "Diagnostics: none");
+    } else {
+      let _ = writeln!(out, "Diagnostics:");
+      for diagnostic in self.diagnostics.iter() {
+        let _ = writeln!(out, "  - {}", diagnostic);
+      }
+    }
+
+    out
+  }
+
+  /// Render as JSON.
+  pub fn to_json(&self) -> Json {
+    let sections = self.sections.iter().map(|section| {
+      let header = &section.header;
+      let mut fields = vec![
+        ("offset", Json::Number(section.offset as f64)),
+        ("format_code", Json::Number(header.format_code as f64)),
+        ("format", Json::String(format_name(header).to_string())),
+        ("name", Json::optional_string(header.name.as_deref())),
+        ("company_name",
+            Json::optional_string(header.company_name.as_deref())),
+        ("record_count", Json::Number(header.record_count as f64)),
+        ("number", Json::Number(header.number as f64)),
+        ("total_frames", Json::Number(header.total_frames as f64)),
+        ("projector_number", Json::Number(header.projector_number as f64)),
+      ];
+      if header.format_code == 2 {
+        fields.push(("colors", Json::Number(section.colors as f64)));
+      } else {
+        fields.push(("points", stats_json(&section.points)));
+      }
+      Json::object(fields)
+    }).collect();
+
+    Json::object(vec![
+      ("file_size", Json::Number(self.file_size as f64)),
+      ("frame_count", Json::Number(self.frame_count() as f64)),
+      ("palette_count", Json::Number(self.palette_count() as f64)),
+      ("has_palette", Json::Bool(self.palette_count() > 0)),
+      ("points", stats_json(&self.totals())),
+      ("sections", Json::Array(sections)),
+      ("diagnostics", Json::Array(self.diagnostics.iter()
+          .map(diagnostic_json)
+          .collect())),
+    ])
+  }
+}
+
+/// Describe a diagnostic as JSON.
+pub fn diagnostic_json(diagnostic: &Diagnostic) -> Json {
+  let index = |i: Option<usize>| {
+    i.map_or(Json::Null, |i| Json::Number(i as f64))
+  };
+  Json::object(vec![
+    ("severity", Json::String(diagnostic.severity.name().to_string())),
+    ("check", Json::String(diagnostic.check.name().to_string())),
+    ("section", index(diagnostic.section)),
+    ("record", index(diagnostic.record)),
+    ("message", Json::String(diagnostic.message.clone())),
+  ])
+}
+
+fn stats_json(stats: &PointStats) -> Json {
+  let bounds = match stats.bounds {
+    None => Json::Null,
+    Some(b) => Json::object(vec![
+      ("min_x", Json::Number(b.min_x as f64)),
+      ("max_x", Json::Number(b.max_x as f64)),
+      ("min_y", Json::Number(b.min_y as f64)),
+      ("max_y", Json::Number(b.max_y as f64)),
+      ("min_z", Json::Number(b.min_z as f64)),
+      ("max_z", Json::Number(b.max_z as f64)),
+    ]),
+  };
+  Json::object(vec![
+    ("count", Json::Number(stats.count as f64)),
+    ("lit", Json::Number(stats.lit() as f64)),
+    ("blanked", Json::Number(stats.blanked as f64)),
+    ("blanked_ratio", Json::Number(stats.blanked_ratio())),
+    ("bounds", bounds),
+  ])
+}
+
+fn format_name(header: &Header) -> &'static str {
+  match header.get_format() {
+    Format::Indexed3d => "0 Indexed 3D",
+    Format::Indexed2d => "1 Indexed 2D",
+    Format::ColorPalette => "2 Palette",
+    Format::TrueColor3d => "4 True 3D",
+    Format::TrueColor2d => "5 True 2D",
+    Format::Unknown => "? Unknown",
+  }
+}
+
+fn display_name(name: &Option<String>) -> &str {
+  match name.as_deref() {
+    None | Some("") => "-",
+    Some(name) => name,
+  }
+}
+
+fn section_size(header: &Header) -> usize {
+  let record_size = header.get_format().record_size().unwrap_or(0);
+  HEADER_SIZE + record_size * header.record_count as usize
+}
+
+fn is_frame(header: &Header) -> bool {
+  header.format_code != 2 && header.record_count > 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ilda::data::TrueColorPoint3d;
+
+  #[test]
+  fn test_summary() {
+    let mut header = Header::new(4);
+    header.record_count = 2;
+    header.total_frames = 1;
+    let entries = vec![
+      IldaEntry::HeaderEntry(header),
+      IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+        x: -10, y: 10, z: 5, status_code: 64, ..Default::default()
+      }),
+      IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+        x: 20, y: -20, z: -5, status_code: 128, ..Default::default()
+      }),
+      IldaEntry::HeaderEntry(Header::new(4)),
+    ];
+    let summary = Summary::new(&entries, 84);
+
+    assert_eq!(2, summary.sections.len());
+    assert_eq!(52, summary.sections[1].offset);
+    assert!(summary.diagnostics.is_empty());
+
+    let totals = summary.totals();
+    assert_eq!(2, totals.count);
+    assert_eq!(1, totals.blanked);
+    assert_eq!(Some(Bounds { min_x: -10, max_x: 20, min_y: -20, max_y: 10,
+        min_z: -5, max_z: 5 }), totals.bounds);
+
+    let table = summary.to_table();
+    assert!(table.contains("4 True 3D"));
+    assert!(table.contains("Points: 2 (1 lit, 1 blanked, 50.0% blanked)"));
+
+    let json = summary.to_json().to_pretty_string();
+    assert!(json.contains("\"frame_count\": 1"));
+    assert!(json.contains("\"has_palette\": false"));
+  }
+
+  #[test]
+  fn test_diagnostics() {
+    let mut header = Header::new(4);
+    header.record_count = 2;
+    header.number = 1;
+    header.total_frames = 1;
+    let entries = vec![
+      IldaEntry::HeaderEntry(header),
+      IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+        status_code: 128, ..Default::default()
+      }),
+      IldaEntry::TcPoint3dEntry(TrueColorPoint3d::default()),
+    ];
+    let summary = Summary::new(&entries, 0);
+
+    let messages: Vec<String> = summary.diagnostics.iter()
+        .map(|d| d.to_string())
+        .collect();
+    assert_eq!(vec![
+      "warning: section 0: frame number 1, expected 0 [frame-number]",
+      "warning: section 0, record 0: last point bit set before the final \
+          record [last-point]",
+      "warning: missing terminating header [terminator]",
+    ], messages);
+
+    let json = summary.to_json().to_pretty_string();
+    assert!(json.contains("\"check\": \"frame-number\""));
+  }
+}
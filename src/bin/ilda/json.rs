@@ -0,0 +1,129 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Just enough JSON output for the command line tool, without pulling in a
+//! serialization library.
+
+use std::fmt::Write;
+
+/// A JSON value.
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  /// Build an object from key-value pairs.
+  pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect())
+  }
+
+  /// A string value, or null.
+  pub fn optional_string(value: Option<&str>) -> Json {
+    match value {
+      Some(value) => Json::String(value.to_string()),
+      None => Json::Null,
+    }
+  }
+
+  /// Render the value with two-space indentation.
+  pub fn to_pretty_string(&self) -> String {
+    let mut out = String::new();
+    self.write(&mut out, 0);
+    out
+  }
+
+  fn write(&self, out: &mut String, indent: usize) {
+    match *self {
+      Json::Null => out.push_str("null"),
+      Json::Bool(value) => out.push_str(if value { "true" } else { "false" }),
+      Json::Number(value) => {
+        if value.is_finite() {
+          let _ = write!(out, "{}", value);
+        } else {
+          out.push_str("null");
+        }
+      },
+      Json::String(ref value) => write_string(out, value),
+      Json::Array(ref values) => {
+        if values.is_empty() {
+          out.push_str("[]");
+          return;
+        }
+        out.push_str("[\n");
+        for (i, value) in values.iter().enumerate() {
+          push_indent(out, indent + 1);
+          value.write(out, indent + 1);
+          out.push_str(if i + 1 < values.len() { ",\n" } else { "\n" });
+        }
+        push_indent(out, indent);
+        out.push(']');
+      },
+      Json::Object(ref fields) => {
+        if fields.is_empty() {
+          out.push_str("{}");
+          return;
+        }
+        out.push_str("{\n");
+        for (i, (key, value)) in fields.iter().enumerate() {
+          push_indent(out, indent + 1);
+          write_string(out, key);
+          out.push_str(": ");
+          value.write(out, indent + 1);
+          out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+        }
+        push_indent(out, indent);
+        out.push('}');
+      },
+    }
+  }
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+  for _ in 0..indent {
+    out.push_str("  ");
+  }
+}
+
+fn write_string(out: &mut String, value: &str) {
+  out.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => {
+        let _ = write!(out, "\\u{:04x}", c as u32);
+      },
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_to_pretty_string() {
+    let json = Json::object(vec![
+      ("name", Json::String("a \"b\"\n\u{1}".to_string())),
+      ("values", Json::Array(vec![Json::Number(1.5), Json::Null])),
+      ("empty", Json::Array(Vec::new())),
+      ("ok", Json::Bool(true)),
+    ]);
+
+    let expected = "{\n  \"name\": \"a \\\"b\\\"\\n\\u0001\",\n  \
+        \"values\": [\n    1.5,\n    null\n  ],\n  \"empty\": [],\n  \
+        \"ok\": true\n}";
+    assert_eq!(expected, json.to_pretty_string());
+  }
+}
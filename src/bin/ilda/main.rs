@@ -0,0 +1,177 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Command line tool for working with ILDA files.
+
+extern crate ilda;
+
+mod args;
+mod commands;
+mod info;
+mod json;
+
+use args::Args;
+use ilda::IldaError;
+use ilda::parser;
+use ilda::validate;
+use ilda::validate::Severity;
+use ilda::validate::ValidateOptions;
+use info::Summary;
+use json::Json;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::process;
+
+const USAGE: &str = "\
+Usage: ilda <command> [options]
+
+Commands:
+  info [--json] <file>
+      Describe the sections in an ILDA file.
+
+  validate [--json] [--max-points <count>] <file>
+      Check a file against the ILDA specification. Exits with status 1 if
+      there are errors. Frames with more than --max-points points, 4000 by
+      default, are reported.
+
+  convert [options] <input> <output>
+      Rewrite a file in another format. Files ending in .txt are read and
+      written as ILDA text.
+        --format <0|1|4|5>    Point format. Defaults to the input's.
+        --palette <palette>   For indexed formats: standard, optimal[:colors],
+                              or a palette text file.
+        --scale <factor>      Scale points about the center.
+        --fit                 Center points and scale them to fill the
+                              coordinate space.
+
+  diff [options] <old> <new>
+      Compare the frames of two files. Exits with status 1 if they differ.
+        --tolerance <units>   Distance within which points are the same.
+                              Defaults to 1.
+        --color-tolerance <n> Color difference within which points are the
+                              same. Defaults to 1.
+
+  render [options] <input> <output>
+      Render frames to PNG, or SVG if the output ends in .svg. Without
+      --frame or --sheet, each frame is numbered, eg. out-0000.png.
+        --frame <index>       Render only one frame.
+        --sheet <columns>     Render every frame to one PNG contact sheet.
+        --size <w>[x<h>]      Image (or cell) size. Defaults to 512.
+        --show-blanking       Draw blanked moves.
+
+  optimize [options] <input> <output>
+      Rebuild paths for smoother output, written as true color.
+        --no-reorder          Keep the original path order.
+        --max-step <units>    Subdivide longer lit lines.
+        --blank-step <units>  Subdivide longer blanked moves.
+        --dwell <points>      Blank points before each path. Defaults to 3.
+        --end-dwell <points>  Lit points after each path. Defaults to 2.
+        --points <count>      Resample every frame to this many points
+                              before optimizing.
+
+  help
+      Show this message.
+";
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+
+  let result = match args.first().map(|arg| arg.as_str()) {
+    Some("info") => info(&args[1..]),
+    Some("validate") => match validate_file(&args[1..]) {
+      Ok(false) => process::exit(1),
+      result => result.map(|_| ()),
+    },
+    Some("convert") => commands::convert(&args[1..]),
+    Some("diff") => match commands::diff(&args[1..]) {
+      Ok(false) => process::exit(1),
+      result => result.map(|_| ()),
+    },
+    Some("render") => commands::render(&args[1..]),
+    Some("optimize") => commands::optimize(&args[1..]),
+    Some("help") | Some("--help") | Some("-h") => {
+      output(USAGE);
+      Ok(())
+    },
+    _ => Err(usage()),
+  };
+
+  if let Err(message) = result {
+    eprintln!("{}", message);
+    process::exit(1);
+  }
+}
+
+fn info(args: &[String]) -> Result<(), String> {
+  let args = Args::parse(args, &["--json"], &[])?;
+  let filename = &args.expect_positional(1)?[0];
+  let bytes = read_bytes(filename)?;
+  let entries = parser::read_bytes(&bytes)
+      .map_err(|e| error(filename, &e))?;
+  let summary = Summary::new(&entries, bytes.len());
+
+  if args.flag("--json") {
+    output(&format!("{}\n", summary.to_json().to_pretty_string()));
+  } else {
+    output(&summary.to_table());
+  }
+  Ok(())
+}
+
+// Report diagnostics, returning whether the file has no errors.
+fn validate_file(args: &[String]) -> Result<bool, String> {
+  let args = Args::parse(args, &["--json"], &["--max-points"])?;
+  let filename = &args.expect_positional(1)?[0];
+  let bytes = read_bytes(filename)?;
+  let entries = parser::read_bytes(&bytes)
+      .map_err(|e| error(filename, &e))?;
+
+  let defaults = ValidateOptions::default();
+  let options = ValidateOptions {
+    max_points: args.parse_value("--max-points")?
+        .unwrap_or(defaults.max_points),
+  };
+  let diagnostics = validate::validate(&entries, &options);
+
+  if args.flag("--json") {
+    let json = Json::Array(diagnostics.iter()
+        .map(info::diagnostic_json)
+        .collect());
+    output(&format!("{}\n", json.to_pretty_string()));
+  } else {
+    let text: String = diagnostics.iter()
+        .map(|diagnostic| format!("{}: {}\n", filename, diagnostic))
+        .collect();
+    output(&text);
+  }
+
+  Ok(diagnostics.iter().all(|d| d.severity != Severity::Error))
+}
+
+// Write to stdout, quietly stopping if the reader has gone away.
+fn output(text: &str) {
+  let _ = io::stdout().write_all(text.as_bytes());
+}
+
+/// Read a whole file.
+pub fn read_bytes(filename: &str) -> Result<Vec<u8>, String> {
+  let mut bytes = Vec::new();
+  File::open(filename)
+      .and_then(|mut file| file.read_to_end(&mut bytes))
+      .map_err(|e| error(filename, &IldaError::from(e)))?;
+  Ok(bytes)
+}
+
+/// Describe an error with the file it relates to.
+pub fn error(filename: &str, error: &IldaError) -> String {
+  match *error {
+    IldaError::IoError { ref cause } => format!("{}: {}", filename, cause),
+    ref error => format!("{}: {}", filename, error),
+  }
+}
+
+fn usage() -> String {
+  USAGE.to_string()
+}
@@ -0,0 +1,465 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Conversion between the ILDA point formats.
+//!
+//! Lossless conversions are `From` implementations. Conversions that drop
+//! information, such as discarding Z or reducing true color to a palette
+//! index, are explicit methods. Whole entry streams, and so whole files, can
+//! be rewritten in another format with `convert_entries` and `convert_file`,
+//! and their points scaled or fit to the coordinate space.
+
+use data::ColorPalette;
+use data::Format;
+use data::IldaEntry;
+use data::IndexedPoint2d;
+use data::IndexedPoint3d;
+use data::TrueColorPoint2d;
+use data::TrueColorPoint3d;
+use error::IldaError;
+use palette::Palette;
+use parser;
+use writer;
+
+/// Options for converting entry streams between formats.
+#[derive(Clone, Debug, Default)]
+pub struct Conversion {
+  /// Z coordinate given to points converted from 2D to 3D.
+  pub z: i16,
+  /// Palette for indexed output, written before the first frame unless it's
+  /// the standard palette. If `None`, indexed input keeps its indices and
+  /// palette sections, and true color input maps onto the palette in effect
+  /// at that point in the stream.
+  pub palette: Option<Palette>,
+}
+
+impl From<IndexedPoint2d> for IndexedPoint3d {
+  /// Place the point at a Z of 0.
+  fn from(point: IndexedPoint2d) -> IndexedPoint3d {
+    point.to_3d(0)
+  }
+}
+
+impl From<TrueColorPoint2d> for TrueColorPoint3d {
+  /// Place the point at a Z of 0.
+  fn from(point: TrueColorPoint2d) -> TrueColorPoint3d {
+    point.to_3d(0)
+  }
+}
+
+impl IndexedPoint2d {
+  /// Convert to a 3D point at the given Z.
+  pub fn to_3d(&self, z: i16) -> IndexedPoint3d {
+    IndexedPoint3d {
+      x: self.x,
+      y: self.y,
+      z,
+      status_code: self.status_code,
+      color_index: self.color_index,
+    }
+  }
+
+  /// Convert to true color by looking up the index in a palette.
+  pub fn to_true_color(&self, palette: &Palette) -> TrueColorPoint2d {
+    let color = palette.color(self.color_index);
+    TrueColorPoint2d {
+      x: self.x,
+      y: self.y,
+      status_code: self.status_code,
+      r: color.r,
+      g: color.g,
+      b: color.b,
+    }
+  }
+}
+
+impl IndexedPoint3d {
+  /// Convert to a 2D point. Lossy: Z is discarded.
+  pub fn to_2d(&self) -> IndexedPoint2d {
+    IndexedPoint2d {
+      x: self.x,
+      y: self.y,
+      status_code: self.status_code,
+      color_index: self.color_index,
+    }
+  }
+
+  /// Convert to true color by looking up the index in a palette.
+  pub fn to_true_color(&self, palette: &Palette) -> TrueColorPoint3d {
+    let color = palette.color(self.color_index);
+    TrueColorPoint3d {
+      x: self.x,
+      y: self.y,
+      z: self.z,
+      status_code: self.status_code,
+      r: color.r,
+      g: color.g,
+      b: color.b,
+    }
+  }
+}
+
+impl TrueColorPoint2d {
+  /// Convert to a 3D point at the given Z.
+  pub fn to_3d(&self, z: i16) -> TrueColorPoint3d {
+    TrueColorPoint3d {
+      x: self.x,
+      y: self.y,
+      z,
+      status_code: self.status_code,
+      r: self.r,
+      g: self.g,
+      b: self.b,
+    }
+  }
+
+  /// Convert to indexed color. Lossy: the color becomes the nearest palette
+  /// entry.
+  pub fn to_indexed(&self, palette: &Palette) -> IndexedPoint2d {
+    IndexedPoint2d {
+      x: self.x,
+      y: self.y,
+      status_code: self.status_code,
+      color_index: palette.nearest(self.r, self.g, self.b),
+    }
+  }
+}
+
+impl TrueColorPoint3d {
+  /// Convert to a 2D point. Lossy: Z is discarded.
+  pub fn to_2d(&self) -> TrueColorPoint2d {
+    TrueColorPoint2d {
+      x: self.x,
+      y: self.y,
+      status_code: self.status_code,
+      r: self.r,
+      g: self.g,
+      b: self.b,
+    }
+  }
+
+  /// Convert to indexed color. Lossy: the color becomes the nearest palette
+  /// entry.
+  pub fn to_indexed(&self, palette: &Palette) -> IndexedPoint3d {
+    IndexedPoint3d {
+      x: self.x,
+      y: self.y,
+      z: self.z,
+      status_code: self.status_code,
+      color_index: palette.nearest(self.r, self.g, self.b),
+    }
+  }
+}
+
+/// Rewrite an entry stream with every frame in the given point format.
+/// Headers keep all fields other than the format code. Palette sections are
+/// used to resolve indexed input colors; they're dropped from true color
+/// output. See `Conversion` for how indexed output is colored.
+pub fn convert_entries(entries: &[IldaEntry], format: Format,
+    conversion: &Conversion) -> Result<Vec<IldaEntry>, IldaError> {
+  if format == Format::ColorPalette {
+    return Err(IldaError::Unsupported);
+  }
+  let format_code = format.code().ok_or(IldaError::Unsupported)?;
+
+  let mut out = Vec::with_capacity(entries.len());
+  let mut input_palette = Palette::standard();
+  let mut palette_colors: Option<Vec<ColorPalette>> = None;
+  let mut same_palette = conversion.palette.as_ref()
+      .map_or(true, |palette| *palette == input_palette);
+
+  if let Some(ref palette) = conversion.palette {
+    if format.is_indexed() && *palette != Palette::standard() {
+      out.extend(palette.to_entries(0));
+    }
+  }
+
+  // A fixed output palette replaces the file's own palette sections.
+  let keep_palettes = format.is_indexed() && conversion.palette.is_none();
+
+  for entry in entries {
+    match *entry {
+      IldaEntry::HeaderEntry(ref header) => {
+        if let Some(colors) = palette_colors.take() {
+          input_palette = Palette::truncated(colors);
+          same_palette = conversion.palette.as_ref()
+              .map_or(true, |palette| *palette == input_palette);
+        }
+
+        if header.format_code == 2 {
+          palette_colors = Some(Vec::new());
+          if keep_palettes {
+            out.push(entry.clone());
+          }
+          continue;
+        }
+
+        let mut header = header.clone();
+        header.format_code = format_code;
+        out.push(IldaEntry::HeaderEntry(header));
+      },
+      IldaEntry::ColorPaletteEntry(ref color) => {
+        match palette_colors {
+          Some(ref mut colors) => colors.push(color.clone()),
+          None => return Err(IldaError::InvalidData),
+        }
+        if keep_palettes {
+          out.push(entry.clone());
+        }
+      },
+      _ => {
+        let output_palette = conversion.palette.as_ref()
+            .unwrap_or(&input_palette);
+        out.push(convert_point(entry, format, conversion.z, &input_palette,
+            output_palette, same_palette)?);
+      },
+    }
+  }
+
+  Ok(out)
+}
+
+/// Rewrite an ILDA file with every frame in the given point format.
+pub fn convert_file(input: &str, output: &str, format: Format,
+    conversion: &Conversion) -> Result<(), IldaError> {
+  let entries = parser::read_file(input)?;
+  let converted = convert_entries(&entries, format, conversion)?;
+  writer::write_file(output, &converted)
+}
+
+/// Scale the position of every point about the center, clamping at the
+/// edges of the coordinate space.
+pub fn scale_entries(entries: &mut [IldaEntry], factor: f64) {
+  for entry in entries.iter_mut() {
+    if let Some((x, y)) = position_mut(entry) {
+      *x = clamp_coordinate(*x as f64 * factor);
+      *y = clamp_coordinate(*y as f64 * factor);
+    }
+  }
+}
+
+/// Center the points and scale them to fill the coordinate space, keeping
+/// their aspect ratio.
+pub fn fit_entries(entries: &mut [IldaEntry]) {
+  let mut bounds: Option<(i16, i16, i16, i16)> = None;
+  for entry in entries.iter_mut() {
+    if let Some((&mut x, &mut y)) = position_mut(entry) {
+      bounds = Some(match bounds {
+        None => (x, x, y, y),
+        Some((min_x, max_x, min_y, max_y)) => {
+          (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+        },
+      });
+    }
+  }
+
+  let (min_x, max_x, min_y, max_y) = match bounds {
+    None => return,
+    Some(bounds) => bounds,
+  };
+
+  let center_x = (min_x as f64 + max_x as f64) / 2.0;
+  let center_y = (min_y as f64 + max_y as f64) / 2.0;
+  let extent = (max_x as f64 - min_x as f64).max(max_y as f64 - min_y as f64);
+  let factor = if extent > 0.0 { 65534.0 / extent } else { 1.0 };
+
+  for entry in entries.iter_mut() {
+    if let Some((x, y)) = position_mut(entry) {
+      *x = clamp_coordinate((*x as f64 - center_x) * factor);
+      *y = clamp_coordinate((*y as f64 - center_y) * factor);
+    }
+  }
+}
+
+fn position_mut(entry: &mut IldaEntry) -> Option<(&mut i16, &mut i16)> {
+  match *entry {
+    IldaEntry::IdxPoint3dEntry(ref mut p) => Some((&mut p.x, &mut p.y)),
+    IldaEntry::IdxPoint2dEntry(ref mut p) => Some((&mut p.x, &mut p.y)),
+    IldaEntry::TcPoint3dEntry(ref mut p) => Some((&mut p.x, &mut p.y)),
+    IldaEntry::TcPoint2dEntry(ref mut p) => Some((&mut p.x, &mut p.y)),
+    _ => None,
+  }
+}
+
+fn clamp_coordinate(value: f64) -> i16 {
+  value.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+}
+
+// Convert a single point entry, going through true color only when the
+// palette changes.
+fn convert_point(entry: &IldaEntry, format: Format, z: i16,
+    input_palette: &Palette, output_palette: &Palette, same_palette: bool)
+    -> Result<IldaEntry, IldaError> {
+  // Normalize to a 3D point of the same color type.
+  let (indexed, true_color) = match *entry {
+    IldaEntry::IdxPoint2dEntry(ref point) => (Some(point.to_3d(z)), None),
+    IldaEntry::IdxPoint3dEntry(ref point) => (Some(point.clone()), None),
+    IldaEntry::TcPoint2dEntry(ref point) => (None, Some(point.to_3d(z))),
+    IldaEntry::TcPoint3dEntry(ref point) => (None, Some(point.clone())),
+    _ => return Err(IldaError::InvalidData),
+  };
+
+  let converted = if format.is_indexed() {
+    let point = match (indexed, true_color) {
+      (Some(point), _) if same_palette => point,
+      (Some(point), _) => {
+        point.to_true_color(input_palette).to_indexed(output_palette)
+      },
+      (None, Some(point)) => point.to_indexed(output_palette),
+      (None, None) => return Err(IldaError::InvalidData),
+    };
+    if format.is_3d() {
+      IldaEntry::IdxPoint3dEntry(point)
+    } else {
+      IldaEntry::IdxPoint2dEntry(point.to_2d())
+    }
+  } else {
+    let point = match (indexed, true_color) {
+      (Some(point), _) => point.to_true_color(input_palette),
+      (None, Some(point)) => point,
+      (None, None) => return Err(IldaError::InvalidData),
+    };
+    if format.is_3d() {
+      IldaEntry::TcPoint3dEntry(point)
+    } else {
+      IldaEntry::TcPoint2dEntry(point.to_2d())
+    }
+  };
+
+  Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use animation::Animation;
+  use data::Header;
+
+  fn header(format_code: u8, record_count: u16) -> IldaEntry {
+    let mut header = Header::new(format_code);
+    header.name = Some("frame".to_string());
+    header.record_count = record_count;
+    IldaEntry::HeaderEntry(header)
+  }
+
+  fn indexed_file() -> Vec<IldaEntry> {
+    vec![
+      header(0, 2),
+      IldaEntry::IdxPoint3dEntry(IndexedPoint3d {
+        x: 1, y: 2, z: 3, status_code: 0, color_index: 24,
+      }),
+      IldaEntry::IdxPoint3dEntry(IndexedPoint3d {
+        x: 4, y: 5, z: 6, status_code: 192, color_index: 40,
+      }),
+      header(0, 0),
+    ]
+  }
+
+  #[test]
+  fn test_point_conversions() {
+    let point = TrueColorPoint2d { x: 1, y: 2, status_code: 64, r: 250,
+        g: 0, b: 0 };
+    let point3d = TrueColorPoint3d::from(point.clone());
+    assert_eq!((1, 2, 0, 64), (point3d.x, point3d.y, point3d.z,
+        point3d.status_code));
+    assert_eq!(9, point.to_3d(9).z);
+
+    let indexed = point.to_indexed(&Palette::standard());
+    assert_eq!(0, indexed.color_index);
+    assert_eq!(64, indexed.status_code);
+    assert_eq!(255, indexed.to_true_color(&Palette::standard()).r);
+    assert_eq!(0, IndexedPoint3d::from(indexed).z);
+  }
+
+  #[test]
+  fn test_format_0_to_format_5() {
+    let conversion = Conversion::default();
+    let entries = convert_entries(&indexed_file(), Format::TrueColor2d,
+        &conversion).unwrap();
+    assert_eq!(4, entries.len());
+
+    match entries[0] {
+      IldaEntry::HeaderEntry(ref header) => {
+        assert_eq!(5, header.format_code);
+        assert_eq!(Some("frame".to_string()), header.name);
+        assert_eq!(2, header.record_count);
+      },
+      _ => panic!("expected header"),
+    }
+    match entries[2] {
+      IldaEntry::TcPoint2dEntry(ref point) => {
+        assert_eq!((4, 5, 192), (point.x, point.y, point.status_code));
+        assert_eq!((0, 0, 255), (point.r, point.g, point.b));
+      },
+      _ => panic!("expected true color point"),
+    }
+
+    // And back again, with the indices restored.
+    let entries = convert_entries(&entries, Format::Indexed3d,
+        &Conversion { z: 7, palette: None }).unwrap();
+    match entries[1] {
+      IldaEntry::IdxPoint3dEntry(ref point) => {
+        assert_eq!((1, 2, 7, 24), (point.x, point.y, point.z,
+            point.color_index));
+      },
+      _ => panic!("expected indexed point"),
+    }
+  }
+
+  #[test]
+  fn test_palette_sections() {
+    let palette = Palette::from_text("0 0 255\n0 255 0").unwrap();
+
+    // A custom output palette is written first and replaces the indices.
+    let conversion = Conversion { z: 0, palette: Some(palette.clone()) };
+    let entries = convert_entries(&indexed_file(), Format::Indexed2d,
+        &conversion).unwrap();
+    let animation = Animation::read_bytes(&writer::write_bytes(&entries))
+        .unwrap();
+    let frame = animation.get_frame(0).unwrap();
+    assert_eq!(Some(&palette), frame.get_palette());
+    assert_eq!(255, frame.get_point(0).unwrap().g);
+    assert_eq!(255, frame.get_point(1).unwrap().b);
+
+    // The file's own palette is used for true color output, then dropped.
+    let entries = convert_entries(&entries, Format::TrueColor3d,
+        &Conversion::default()).unwrap();
+    assert_eq!(4, entries.len());
+    match entries[1] {
+      IldaEntry::TcPoint3dEntry(ref point) => {
+        assert_eq!((0, 255, 0), (point.r, point.g, point.b));
+      },
+      _ => panic!("expected true color point"),
+    }
+  }
+
+  #[test]
+  fn test_scale_and_fit() {
+    let mut entries = indexed_file();
+    scale_entries(&mut entries, 10_000.0);
+    match entries[2] {
+      IldaEntry::IdxPoint3dEntry(ref point) => {
+        assert_eq!((32767, 32767, 6), (point.x, point.y, point.z));
+      },
+      _ => panic!("expected indexed point"),
+    }
+
+    let mut entries = indexed_file();
+    fit_entries(&mut entries);
+    let positions: Vec<_> = entries.iter()
+        .filter_map(|entry| match *entry {
+          IldaEntry::IdxPoint3dEntry(ref point) => Some((point.x, point.y)),
+          _ => None,
+        })
+        .collect();
+    assert_eq!(vec![(-32767, -32767), (32767, 32767)], positions);
+  }
+
+  #[test]
+  fn test_unsupported_target() {
+    let conversion = Conversion::default();
+    assert!(convert_entries(&indexed_file(), Format::ColorPalette,
+        &conversion).is_err());
+    assert!(convert_entries(&indexed_file(), Format::Unknown,
+        &conversion).is_err());
+  }
+}
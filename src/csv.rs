@@ -0,0 +1,418 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Reading and writing frame points as CSV.
+//!
+//! Each row is a point. Columns are mapped to point fields either explicitly
+//! or, when reading a file with a header row, by the names in the header.
+//! Frames are 2D, so a `z` column is ignored when reading and written as
+//! zero. When there's a frame index column, rows are grouped into frames by
+//! index.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+
+/// The point field held by a column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+  /// X coordinate.
+  X,
+  /// Y coordinate.
+  Y,
+  /// Z coordinate.
+  Z,
+  /// Red, 0 to 255.
+  R,
+  /// Green, 0 to 255.
+  G,
+  /// Blue, 0 to 255.
+  B,
+  /// Whether the point is blanked: `1`, `0`, `true` or `false`.
+  Blank,
+  /// The index of the frame the point belongs to.
+  Frame,
+  /// A column that isn't read, and is left empty when writing.
+  Ignore,
+}
+
+/// CSV settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvOptions {
+  /// The field of each column. If `None`, columns are taken from the header
+  /// row when reading, and otherwise default to `x,y,r,g,b,blank`, with a
+  /// leading `frame` column for animations.
+  pub columns: Option<Vec<Column>>,
+  /// Whether coordinates are normalized to -1.0 to 1.0, rather than in ILDA
+  /// units of -32768 to 32767.
+  pub normalized: bool,
+  /// Whether the first row holds column names.
+  pub header: bool,
+  /// The field separator.
+  pub delimiter: char,
+}
+
+impl Default for CsvOptions {
+  fn default() -> CsvOptions {
+    CsvOptions {
+      columns: None,
+      normalized: false,
+      header: true,
+      delimiter: ',',
+    }
+  }
+}
+
+impl Column {
+  /// The column for a header name, eg. "x" or "Red". Unknown names are
+  /// ignored.
+  pub fn from_name(name: &str) -> Column {
+    match name.trim().to_lowercase().as_str() {
+      "x" => Column::X,
+      "y" => Column::Y,
+      "z" => Column::Z,
+      "r" | "red" => Column::R,
+      "g" | "green" => Column::G,
+      "b" | "blue" => Column::B,
+      "blank" | "blanked" | "is_blank" => Column::Blank,
+      "frame" | "frame_index" => Column::Frame,
+      _ => Column::Ignore,
+    }
+  }
+
+  /// The name written in header rows.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Column::X => "x",
+      Column::Y => "y",
+      Column::Z => "z",
+      Column::R => "r",
+      Column::G => "g",
+      Column::B => "b",
+      Column::Blank => "blank",
+      Column::Frame => "frame",
+      Column::Ignore => "",
+    }
+  }
+}
+
+const FRAME_COLUMNS: [Column; 6] =
+    [Column::X, Column::Y, Column::R, Column::G, Column::B, Column::Blank];
+
+/// Read an animation from a CSV file.
+pub fn read_file(filename: &str, options: &CsvOptions)
+    -> Result<Animation, IldaError> {
+  let mut text = String::new();
+  File::open(filename)?.read_to_string(&mut text)?;
+  read_animation(&text, options)
+}
+
+/// Write an animation to a CSV file.
+pub fn write_file(filename: &str, animation: &Animation, options: &CsvOptions)
+    -> Result<(), IldaError> {
+  let mut file = File::create(filename)?;
+  file.write_all(write_animation(animation, options).as_bytes())?;
+  Ok(())
+}
+
+/// Read an animation. Rows are grouped into frames in order of their frame
+/// index, or make up a single frame if there's no frame index column.
+pub fn read_animation(text: &str, options: &CsvOptions)
+    -> Result<Animation, IldaError> {
+  let mut frames: BTreeMap<u64, Vec<SimplePoint>> = BTreeMap::new();
+  for (index, point) in read_rows(text, options)? {
+    frames.entry(index).or_default().push(point);
+  }
+  Ok(Animation::new(frames.into_values().map(Frame::new).collect()))
+}
+
+/// Read every row as a point of a single frame, ignoring any frame index.
+pub fn read_frame(text: &str, options: &CsvOptions)
+    -> Result<Frame, IldaError> {
+  let rows = read_rows(text, options)?;
+  Ok(Frame::new(rows.into_iter().map(|(_, point)| point).collect()))
+}
+
+/// Write an animation, numbering frames from zero.
+pub fn write_animation(animation: &Animation, options: &CsvOptions)
+    -> String {
+  let mut columns = vec![Column::Frame];
+  columns.extend_from_slice(&FRAME_COLUMNS);
+  let columns = options.columns.as_ref().unwrap_or(&columns);
+
+  let mut out = header_row(columns, options);
+  for (index, frame) in animation.into_frame_iter().enumerate() {
+    write_points(&mut out, frame.get_points(), index, columns, options);
+  }
+  out
+}
+
+/// Write the points of a frame, as frame zero.
+pub fn write_frame(frame: &Frame, options: &CsvOptions) -> String {
+  let columns = options.columns.as_deref().unwrap_or(&FRAME_COLUMNS);
+  let mut out = header_row(columns, options);
+  write_points(&mut out, frame.get_points(), 0, columns, options);
+  out
+}
+
+fn header_row(columns: &[Column], options: &CsvOptions) -> String {
+  if !options.header {
+    return String::new();
+  }
+  let names: Vec<&str> = columns.iter().map(|column| column.name()).collect();
+  let mut row = names.join(&options.delimiter.to_string());
+  row.push('\n');
+  row
+}
+
+fn write_points(out: &mut String, points: &[SimplePoint], index: usize,
+    columns: &[Column], options: &CsvOptions) {
+  for point in points {
+    for (i, column) in columns.iter().enumerate() {
+      if i > 0 {
+        out.push(options.delimiter);
+      }
+      let _ = match *column {
+        Column::X => write_coordinate(out, point.x, options),
+        Column::Y => write_coordinate(out, point.y, options),
+        Column::Z => write_coordinate(out, 0, options),
+        Column::R => write!(out, "{}", point.r),
+        Column::G => write!(out, "{}", point.g),
+        Column::B => write!(out, "{}", point.b),
+        Column::Blank => write!(out, "{}", point.is_blank as u8),
+        Column::Frame => write!(out, "{}", index),
+        Column::Ignore => Ok(()),
+      };
+    }
+    out.push('\n');
+  }
+}
+
+fn write_coordinate(out: &mut String, value: i16, options: &CsvOptions)
+    -> ::std::fmt::Result {
+  if options.normalized {
+    write!(out, "{}", value as f64 / i16::MAX as f64)
+  } else {
+    write!(out, "{}", value)
+  }
+}
+
+// Read each row as a point and its frame index.
+fn read_rows(text: &str, options: &CsvOptions)
+    -> Result<Vec<(u64, SimplePoint)>, IldaError> {
+  let mut lines = text.lines()
+      .enumerate()
+      .map(|(i, line)| (i + 1, line))
+      .filter(|(_, line)| !line.trim().is_empty());
+
+  let header = if options.header {
+    lines.next().map(|(_, line)| split_row(line, options.delimiter))
+  } else {
+    None
+  };
+
+  let columns = match (options.columns.as_ref(), header) {
+    (Some(columns), _) => columns.clone(),
+    (None, Some(names)) => names.iter().map(|n| Column::from_name(n)).collect(),
+    (None, None) => FRAME_COLUMNS.to_vec(),
+  };
+  for required in [Column::X, Column::Y].iter() {
+    if !columns.contains(required) {
+      return Err(IldaError::InvalidText {
+        line: 1,
+        cause: format!("no {} column", required.name()),
+      });
+    }
+  }
+  let has_color = columns.iter()
+      .any(|column| [Column::R, Column::G, Column::B].contains(column));
+
+  let mut rows = Vec::new();
+  for (line, row) in lines {
+    let invalid = |cause| IldaError::InvalidText { line, cause };
+    let fields = split_row(row, options.delimiter);
+    if fields.len() < columns.len() {
+      return Err(invalid(format!("expected {} fields, found {}",
+          columns.len(), fields.len())));
+    }
+
+    let white = if has_color { 0 } else { 255 };
+    let mut point = SimplePoint {
+      x: 0, y: 0, r: white, g: white, b: white, is_blank: false,
+    };
+    let mut index = 0;
+
+    for (column, field) in columns.iter().zip(fields.iter()) {
+      let field = field.trim();
+      match *column {
+        Column::X => point.x = parse_coordinate(field, options.normalized)
+            .map_err(&invalid)?,
+        Column::Y => point.y = parse_coordinate(field, options.normalized)
+            .map_err(&invalid)?,
+        Column::R => point.r = parse_color(field).map_err(&invalid)?,
+        Column::G => point.g = parse_color(field).map_err(&invalid)?,
+        Column::B => point.b = parse_color(field).map_err(&invalid)?,
+        Column::Blank => point.is_blank = parse_blank(field)
+            .map_err(&invalid)?,
+        Column::Frame => index = field.parse()
+            .map_err(|_| invalid(format!("invalid frame index '{}'", field)))?,
+        Column::Z | Column::Ignore => {},
+      }
+    }
+
+    rows.push((index, point));
+  }
+
+  Ok(rows)
+}
+
+// Split a row on the delimiter, allowing double quoted fields.
+fn split_row(row: &str, delimiter: char) -> Vec<String> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = row.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if quoted {
+      if c == '"' && chars.peek() == Some(&'"') {
+        chars.next();
+        field.push('"');
+      } else if c == '"' {
+        quoted = false;
+      } else {
+        field.push(c);
+      }
+    } else if c == '"' {
+      quoted = true;
+    } else if c == delimiter {
+      fields.push(field);
+      field = String::new();
+    } else {
+      field.push(c);
+    }
+  }
+
+  fields.push(field);
+  fields
+}
+
+fn parse_number(field: &str) -> Result<f64, String> {
+  field.parse::<f64>()
+      .ok()
+      .filter(|value| value.is_finite())
+      .ok_or_else(|| format!("invalid number '{}'", field))
+}
+
+fn parse_coordinate(field: &str, normalized: bool) -> Result<i16, String> {
+  let mut value = parse_number(field)?;
+  if normalized {
+    value *= i16::MAX as f64;
+  }
+  let value = value.round();
+  if value < i16::MIN as f64 || value > i16::MAX as f64 {
+    return Err(format!("coordinate out of range '{}'", field));
+  }
+  Ok(value as i16)
+}
+
+fn parse_color(field: &str) -> Result<u8, String> {
+  let value = parse_number(field)?.round();
+  if !(0.0..=255.0).contains(&value) {
+    return Err(format!("color out of range '{}'", field));
+  }
+  Ok(value as u8)
+}
+
+fn parse_blank(field: &str) -> Result<bool, String> {
+  match field.to_lowercase().as_str() {
+    "1" | "true" | "yes" => Ok(true),
+    "0" | "false" | "no" | "" => Ok(false),
+    _ => Err(format!("invalid blank flag '{}'", field)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn points(frame: &Frame) -> Vec<(i16, i16, u8, u8, u8, bool)> {
+    frame.get_points().iter()
+        .map(|p| (p.x, p.y, p.r, p.g, p.b, p.is_blank))
+        .collect()
+  }
+
+  #[test]
+  fn test_read_with_header_names() {
+    let text = "\
+Frame,Red,Green,Blue,X,Y,Note,Blanked
+1,255,0,0,100,-200,\"a, b\",0
+0,0,255,0,5,6,,true
+1,0,0,255.0,7,8,,1
+";
+    let animation = read_animation(text, &CsvOptions::default()).unwrap();
+
+    assert_eq!(2, animation.frame_count());
+    assert_eq!(vec![(5, 6, 0, 255, 0, true)],
+        points(animation.get_frame(0).unwrap()));
+    assert_eq!(vec![(100, -200, 255, 0, 0, false), (7, 8, 0, 0, 255, true)],
+        points(animation.get_frame(1).unwrap()));
+  }
+
+  #[test]
+  fn test_read_mapped_normalized() {
+    let options = CsvOptions {
+      columns: Some(vec![Column::Y, Column::Ignore, Column::X]),
+      normalized: true,
+      header: false,
+      delimiter: ';',
+    };
+    let frame = read_frame("1.0;x;-0.5\n0;x;0\n", &options).unwrap();
+
+    // No color columns, so points are white.
+    assert_eq!(vec![(-16384, 32767, 255, 255, 255, false),
+        (0, 0, 255, 255, 255, false)], points(&frame));
+  }
+
+  #[test]
+  fn test_write_round_trip() {
+    let frame = Frame::new(vec![
+      SimplePoint { x: -32767, y: 10, r: 1, g: 2, b: 3, is_blank: true },
+      SimplePoint { x: 32767, y: -10, r: 4, g: 5, b: 6, is_blank: false },
+    ]);
+    let animation = Animation::new(vec![frame.clone(), frame.clone()]);
+
+    let options = CsvOptions::default();
+    let text = write_animation(&animation, &options);
+    assert!(text.starts_with("frame,x,y,r,g,b,blank\n0,-32767,10,1,2,3,1\n"));
+
+    let read = read_animation(&text, &options).unwrap();
+    assert_eq!(2, read.frame_count());
+    assert_eq!(points(&frame), points(read.get_frame(1).unwrap()));
+
+    let options = CsvOptions { normalized: true, ..CsvOptions::default() };
+    let text = write_frame(&frame, &options);
+    assert!(text.starts_with("x,y,r,g,b,blank\n-1,"));
+    assert_eq!(points(&frame),
+        points(&read_frame(&text, &options).unwrap()));
+  }
+
+  #[test]
+  fn test_errors() {
+    let line = |text: &str| match read_frame(text, &CsvOptions::default()) {
+      Err(IldaError::InvalidText { line, .. }) => line,
+      _ => panic!("expected an error"),
+    };
+
+    assert_eq!(1, line("x,r\n1,2\n"));
+    assert_eq!(4, line("x,y\n1,2\n\n3\n"));
+    assert_eq!(2, line("x,y\n40000,0\n"));
+    assert_eq!(2, line("x,y,r\n0,0,256\n"));
+    assert_eq!(2, line("x,y,blank\n0,0,maybe\n"));
+  }
+}
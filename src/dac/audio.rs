@@ -0,0 +1,391 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Laser output through a DC-coupled sound card.
+//!
+//! Each point becomes one multichannel sample frame, so the sound card's
+//! sample rate is the scan rate. Coordinates map onto the full signed sample
+//! range and colors onto the positive half. The channel layout, inversion and
+//! bit depth are configurable to suit the wiring of a particular rig, and
+//! buffers can be written out as WAV files for offline inspection.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use source::PointSource;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+
+/// Format tag of the extensible WAV format, whose real format is given by a
+/// subformat GUID.
+const WAVE_FORMAT_EXTENSIBLE : u16 = 0xfffe;
+
+/// The subformat GUID after its leading format tag.
+const SUBFORMAT_SUFFIX : [u8; 14] = [
+  0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38,
+  0x9b, 0x71,
+];
+
+/// A signal that can be assigned to an output channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+  /// Horizontal position.
+  X,
+  /// Vertical position.
+  Y,
+  /// Red intensity.
+  Red,
+  /// Green intensity.
+  Green,
+  /// Blue intensity.
+  Blue,
+  /// Brightest of the three colors; often wired to a shutter or TTL input.
+  Intensity,
+  /// Always zero; for unused channels.
+  Silence,
+}
+
+/// Sample encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+  /// Signed 16-bit integers.
+  Int16,
+  /// Signed 24-bit integers.
+  Int24,
+  /// 32-bit floats.
+  Float32,
+}
+
+/// One output channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Channel {
+  /// The signal carried on the channel.
+  pub signal: Signal,
+  /// Whether the signal is negated, eg. for a mirrored galvo.
+  pub invert: bool,
+}
+
+/// Sound card output settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PcmConfig {
+  /// Samples (points) per second.
+  pub sample_rate: u32,
+  /// Channel layout, in interleaving order.
+  pub channels: Vec<Channel>,
+  /// Sample encoding.
+  pub bit_depth: BitDepth,
+}
+
+impl Channel {
+  /// A non-inverted channel.
+  pub fn new(signal: Signal) -> Channel {
+    Channel { signal, invert: false }
+  }
+
+  /// An inverted channel.
+  pub fn inverted(signal: Signal) -> Channel {
+    Channel { signal, invert: true }
+  }
+}
+
+impl BitDepth {
+  /// Size of one sample in bytes.
+  pub fn bytes_per_sample(&self) -> usize {
+    match *self {
+      BitDepth::Int16 => 2,
+      BitDepth::Int24 => 3,
+      BitDepth::Float32 => 4,
+    }
+  }
+}
+
+impl Default for PcmConfig {
+  /// 48kHz, 16-bit, with X, Y, red, green and blue on the first five
+  /// channels.
+  fn default() -> PcmConfig {
+    PcmConfig {
+      sample_rate: 48_000,
+      channels: vec![
+        Channel::new(Signal::X),
+        Channel::new(Signal::Y),
+        Channel::new(Signal::Red),
+        Channel::new(Signal::Green),
+        Channel::new(Signal::Blue),
+      ],
+      bit_depth: BitDepth::Int16,
+    }
+  }
+}
+
+impl PcmConfig {
+  /// Size of one interleaved sample frame in bytes.
+  pub fn bytes_per_frame(&self) -> usize {
+    self.channels.len() * self.bit_depth.bytes_per_sample()
+  }
+
+  /// Convert points to interleaved samples in `[-1, 1]`.
+  pub fn convert_points(&self, points: &[SimplePoint]) -> Vec<f32> {
+    let mut out = Vec::with_capacity(points.len() * self.channels.len());
+    for point in points {
+      for channel in self.channels.iter() {
+        let value = signal_value(point, channel.signal);
+        out.push(if channel.invert { -value } else { value });
+      }
+    }
+    out
+  }
+
+  /// Encode points as interleaved little endian PCM.
+  pub fn encode_points(&self, points: &[SimplePoint]) -> Vec<u8> {
+    let samples = self.convert_points(points);
+    let mut out = Vec::with_capacity(samples.len()
+        * self.bit_depth.bytes_per_sample());
+
+    for sample in samples {
+      match self.bit_depth {
+        BitDepth::Int16 => {
+          let value = (sample * i16::MAX as f32).round() as i16;
+          out.extend_from_slice(&value.to_le_bytes());
+        },
+        BitDepth::Int24 => {
+          let value = (sample * 8_388_607.0).round() as i32;
+          out.extend_from_slice(&value.to_le_bytes()[..3]);
+        },
+        BitDepth::Float32 => {
+          out.extend_from_slice(&sample.to_le_bytes());
+        },
+      }
+    }
+
+    out
+  }
+
+  /// Encode the points of a frame, scanned once.
+  pub fn encode_frame(&self, frame: &Frame) -> Vec<u8> {
+    self.encode_points(frame.get_points())
+  }
+
+  /// Encode every frame of an animation, each scanned once.
+  pub fn encode_animation(&self, animation: &Animation) -> Vec<u8> {
+    let points: Vec<SimplePoint> = animation.into_point_iter()
+        .cloned()
+        .collect();
+    self.encode_points(&points)
+  }
+
+  /// Encode up to `count` points pulled from a source.
+  pub fn encode_source<S: PointSource>(&self, source: &mut S, count: usize)
+      -> Vec<u8> {
+    let points = source.next_points(count, self.sample_rate);
+    self.encode_points(&points)
+  }
+
+  /// Write encoded PCM data as a WAV file. More than two channels, or 24-bit
+  /// samples, are written in the extensible format. Fails with
+  /// `IldaError::TooLarge` if the data doesn't fit in a WAV file.
+  pub fn write_wav<W: Write>(&self, writer: &mut W, pcm: &[u8])
+      -> Result<(), IldaError> {
+    if self.channels.is_empty()
+        || pcm.len() % self.bytes_per_frame() != 0 {
+      return Err(IldaError::InvalidData);
+    }
+
+    let format_tag: u16 = match self.bit_depth {
+      BitDepth::Float32 => 3,
+      _ => 1,
+    };
+    let channels = u16::try_from(self.channels.len())
+        .map_err(|_| IldaError::TooLarge)?;
+    let block_align = u16::try_from(self.bytes_per_frame())
+        .map_err(|_| IldaError::TooLarge)?;
+    let bits = (self.bit_depth.bytes_per_sample() * 8) as u16;
+    let byte_rate = self.sample_rate.checked_mul(block_align as u32)
+        .ok_or(IldaError::TooLarge)?;
+
+    let extensible = channels > 2 || self.bit_depth == BitDepth::Int24;
+    let fmt_len = if extensible { 40 } else { 16 };
+    let riff_len = riff_len(fmt_len, pcm.len()).ok_or(IldaError::TooLarge)?;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_len.to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&fmt_len.to_le_bytes())?;
+    if extensible {
+      writer.write_all(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes())?;
+    } else {
+      writer.write_all(&format_tag.to_le_bytes())?;
+    }
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&self.sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits.to_le_bytes())?;
+    if extensible {
+      writer.write_all(&22u16.to_le_bytes())?; // Extension size
+      writer.write_all(&bits.to_le_bytes())?; // Valid bits
+      writer.write_all(&0u32.to_le_bytes())?; // No speaker positions
+      writer.write_all(&format_tag.to_le_bytes())?;
+      writer.write_all(&SUBFORMAT_SUFFIX)?;
+    }
+    writer.write_all(b"data")?;
+    writer.write_all(&(pcm.len() as u32).to_le_bytes())?;
+    writer.write_all(pcm)?;
+    if pcm.len() % 2 == 1 {
+      writer.write_all(&[0])?; // Chunks are padded to an even length.
+    }
+    Ok(())
+  }
+
+  /// Write an animation, each frame scanned once, to a WAV file.
+  pub fn write_wav_file(&self, filename: &str, animation: &Animation)
+      -> Result<(), IldaError> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    self.write_wav(&mut writer, &self.encode_animation(animation))?;
+    writer.flush()?;
+    Ok(())
+  }
+}
+
+// Size of a RIFF WAVE file after the RIFF chunk header, if it fits in the
+// 32-bit length field.
+fn riff_len(fmt_len: u32, data_len: usize) -> Option<u32> {
+  let data_len = u32::try_from(data_len.checked_add(data_len % 2)?).ok()?;
+  data_len.checked_add(4 + 8 + fmt_len + 8)
+}
+
+fn signal_value(point: &SimplePoint, signal: Signal) -> f32 {
+  let color = |value: u8| {
+    if point.is_blank { 0.0 } else { value as f32 / 255.0 }
+  };
+  match signal {
+    Signal::X => coordinate(point.x),
+    Signal::Y => coordinate(point.y),
+    Signal::Red => color(point.r),
+    Signal::Green => color(point.g),
+    Signal::Blue => color(point.b),
+    Signal::Intensity => color(point.r.max(point.g).max(point.b)),
+    Signal::Silence => 0.0,
+  }
+}
+
+// Map a coordinate onto [-1, 1]. The extra negative value is clamped.
+fn coordinate(value: i16) -> f32 {
+  (value as f32 / i16::MAX as f32).max(-1.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(x: i16, y: i16, r: u8, is_blank: bool) -> SimplePoint {
+    SimplePoint { x, y, r, g: 0, b: 255, is_blank }
+  }
+
+  #[test]
+  fn test_convert_points() {
+    let config = PcmConfig::default();
+    let samples = config.convert_points(&[point(32767, -32768, 255, false),
+        point(0, 0, 255, true)]);
+
+    assert_eq!(vec![1.0, -1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        samples);
+  }
+
+  #[test]
+  fn test_channel_mapping_and_inversion() {
+    let config = PcmConfig {
+      sample_rate: 30_000,
+      channels: vec![
+        Channel::inverted(Signal::Y),
+        Channel::new(Signal::Silence),
+        Channel::new(Signal::Intensity),
+      ],
+      bit_depth: BitDepth::Float32,
+    };
+
+    let samples = config.convert_points(&[point(0, 32767, 51, false)]);
+    assert_eq!(vec![-1.0, 0.0, 1.0], samples);
+    assert_eq!(12, config.bytes_per_frame());
+  }
+
+  #[test]
+  fn test_bit_depths() {
+    let points = [point(32767, -32767, 0, false)];
+    let mut config = PcmConfig {
+      channels: vec![Channel::new(Signal::X), Channel::new(Signal::Y)],
+      ..PcmConfig::default()
+    };
+
+    assert_eq!(vec![0xff, 0x7f, 0x01, 0x80], config.encode_points(&points));
+
+    config.bit_depth = BitDepth::Int24;
+    assert_eq!(vec![0xff, 0xff, 0x7f, 0x01, 0x00, 0x80],
+        config.encode_points(&points));
+
+    config.bit_depth = BitDepth::Float32;
+    let mut expected = 1.0f32.to_le_bytes().to_vec();
+    expected.extend_from_slice(&(-1.0f32).to_le_bytes());
+    assert_eq!(expected, config.encode_points(&points));
+  }
+
+  #[test]
+  fn test_write_wav() {
+    let config = PcmConfig::default();
+    let animation = Animation::new(vec![
+      Frame::new(vec![point(0, 0, 0, false); 3]),
+      Frame::new(vec![point(0, 0, 0, false); 2]),
+    ]);
+    let pcm = config.encode_animation(&animation);
+    assert_eq!(5 * config.bytes_per_frame(), pcm.len());
+
+    let mut wav = Vec::new();
+    config.write_wav(&mut wav, &pcm).unwrap();
+
+    assert_eq!(68 + pcm.len(), wav.len());
+    assert_eq!(b"RIFF", &wav[0..4]);
+    assert_eq!(&(60 + pcm.len() as u32).to_le_bytes(), &wav[4..8]);
+    assert_eq!(b"WAVE", &wav[8..12]);
+    assert_eq!(&40u32.to_le_bytes(), &wav[16..20]); // Format size
+    assert_eq!(&0xfffeu16.to_le_bytes(), &wav[20..22]); // Extensible
+    assert_eq!(&5u16.to_le_bytes(), &wav[22..24]); // Channels
+    assert_eq!(&48_000u32.to_le_bytes(), &wav[24..28]);
+    assert_eq!(&10u16.to_le_bytes(), &wav[32..34]); // Block align
+    assert_eq!(&16u16.to_le_bytes(), &wav[34..36]); // Bits
+    assert_eq!(&22u16.to_le_bytes(), &wav[36..38]); // Extension size
+    assert_eq!(&16u16.to_le_bytes(), &wav[38..40]); // Valid bits
+    assert_eq!(&[1, 0, 0, 0, 0, 0, 0x10, 0], &wav[44..52]); // PCM GUID
+    assert_eq!(&(pcm.len() as u32).to_le_bytes(), &wav[64..68]);
+    assert_eq!(&pcm[..], &wav[68..]);
+
+    assert!(config.write_wav(&mut Vec::new(), &pcm[1..]).is_err());
+
+    // Stereo 16-bit data keeps the plain format.
+    let stereo = PcmConfig {
+      channels: vec![Channel::new(Signal::X), Channel::new(Signal::Y)],
+      ..PcmConfig::default()
+    };
+    let pcm = stereo.encode_animation(&animation);
+    let mut wav = Vec::new();
+    stereo.write_wav(&mut wav, &pcm).unwrap();
+    assert_eq!(44 + pcm.len(), wav.len());
+    assert_eq!(&1u16.to_le_bytes(), &wav[20..22]); // PCM
+    assert_eq!(&(pcm.len() as u32).to_le_bytes(), &wav[40..44]);
+
+    // Odd length data is padded.
+    let mono = PcmConfig {
+      channels: vec![Channel::new(Signal::X)],
+      bit_depth: BitDepth::Int24,
+      ..PcmConfig::default()
+    };
+    let mut wav = Vec::new();
+    mono.write_wav(&mut wav, &[1, 2, 3]).unwrap();
+    assert_eq!(68 + 4, wav.len());
+    assert_eq!(&3u32.to_le_bytes(), &wav[64..68]);
+
+    assert_eq!(Some(36 + 4), riff_len(16, 3));
+    assert_eq!(None, riff_len(16, u32::MAX as usize - 35));
+  }
+}
@@ -0,0 +1,1234 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Ether Dream network DAC protocol.
+//!
+//! Ether Dream DACs announce themselves with a UDP broadcast once per second
+//! and accept a single TCP connection over which commands are sent. Every
+//! command is answered with an acknowledgement carrying the DAC's status,
+//! including how full its point buffer is, which is used for flow control.
+//! All multi-byte fields are little endian.
+//!
+//! An `Emulator` that speaks the same protocol on localhost is included so the
+//! driver can be exercised without hardware.
+
+use animation::Animation;
+use error::IldaError;
+use point::SimplePoint;
+use source::AnimationSource;
+use source::PointSource;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// UDP port the DAC broadcasts its presence on.
+pub const BROADCAST_PORT : u16 = 7654;
+/// TCP port the DAC accepts commands on.
+pub const COMMUNICATION_PORT : u16 = 7765;
+
+/// Size of a DAC status section in bytes.
+pub const STATUS_SIZE : usize = 20;
+/// Size of a broadcast packet in bytes.
+pub const BROADCAST_SIZE : usize = 16 + STATUS_SIZE;
+/// Size of a command response in bytes.
+pub const RESPONSE_SIZE : usize = 2 + STATUS_SIZE;
+/// Size of a single point in a data command in bytes.
+pub const POINT_SIZE : usize = 18;
+
+/// Point control flag: apply the next queued rate change at this point.
+pub const CONTROL_RATE_CHANGE : u16 = 0x8000;
+
+/// Playback flag: the buffer ran dry while playing.
+pub const PLAYBACK_FLAG_UNDERFLOW : u16 = 0x0004;
+
+/// Buffer size of stock Ether Dream hardware, in points.
+pub const DEFAULT_BUFFER_CAPACITY : u16 = 1799;
+
+const COMMAND_PREPARE : u8 = b'p';
+const COMMAND_BEGIN : u8 = b'b';
+const COMMAND_QUEUE_RATE_CHANGE : u8 = b'q';
+const COMMAND_DATA : u8 = b'd';
+const COMMAND_STOP : u8 = b's';
+const COMMAND_EMERGENCY_STOP : u8 = 0x00;
+const COMMAND_EMERGENCY_STOP_ALT : u8 = 0xff;
+const COMMAND_CLEAR_EMERGENCY_STOP : u8 = b'c';
+const COMMAND_PING : u8 = b'?';
+
+/// State of the DAC's light engine.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightEngineState {
+  Ready,
+  Warmup,
+  Cooldown,
+  EmergencyStop,
+}
+
+/// State of the DAC's playback system.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackState {
+  Idle,
+  Prepared,
+  Playing,
+}
+
+/// Status section reported in broadcasts and command responses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DacStatus {
+  /// Protocol version.
+  pub protocol: u8,
+  /// Light engine state.
+  pub light_engine_state: LightEngineState,
+  /// Playback state.
+  pub playback_state: PlaybackState,
+  /// Point source; `0` is network streaming.
+  pub source: u8,
+  /// Light engine flags.
+  pub light_engine_flags: u16,
+  /// Playback flags.
+  pub playback_flags: u16,
+  /// Source flags.
+  pub source_flags: u16,
+  /// Number of points currently buffered.
+  pub buffer_fullness: u16,
+  /// Current scan rate in points per second.
+  pub point_rate: u32,
+  /// Number of points played since playback began.
+  pub point_count: u32,
+}
+
+/// Presence announcement broadcast by the DAC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Broadcast {
+  /// Hardware address of the DAC.
+  pub mac_address: [u8; 6],
+  /// Hardware revision.
+  pub hw_revision: u16,
+  /// Firmware revision.
+  pub sw_revision: u16,
+  /// Size of the point buffer.
+  pub buffer_capacity: u16,
+  /// Maximum supported scan rate in points per second.
+  pub max_point_rate: u32,
+  /// Current status.
+  pub status: DacStatus,
+}
+
+/// A point as sent to the DAC. Colors and intensity are 16-bit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[allow(missing_docs)]
+pub struct DacPoint {
+  pub control: u16,
+  pub x: i16,
+  pub y: i16,
+  pub r: u16,
+  pub g: u16,
+  pub b: u16,
+  pub i: u16,
+  pub u1: u16,
+  pub u2: u16,
+}
+
+/// Commands accepted by the DAC.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+  /// Move from idle to prepared, clearing the buffer.
+  Prepare,
+  /// Start playing buffered points.
+  Begin {
+    /// Unused by current firmware.
+    low_water_mark: u16,
+    /// Scan rate in points per second.
+    point_rate: u32,
+  },
+  /// Queue a scan rate, applied at the next point with
+  /// `CONTROL_RATE_CHANGE` set.
+  QueueRateChange {
+    /// Scan rate in points per second.
+    point_rate: u32,
+  },
+  /// Append points to the buffer.
+  Data(Vec<DacPoint>),
+  /// Stop playback and return to idle.
+  Stop,
+  /// Enter the emergency stop state.
+  EmergencyStop,
+  /// Leave the emergency stop state.
+  ClearEmergencyStop,
+  /// Request the current status.
+  Ping,
+}
+
+/// How the DAC answered a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseCode {
+  /// The command was accepted.
+  Ack,
+  /// Data was rejected because the buffer is full.
+  NakFull,
+  /// The command was invalid in the current state.
+  NakInvalid,
+  /// The command was rejected because of an emergency stop.
+  NakStopCondition,
+}
+
+/// The DAC's answer to a command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+  /// Whether the command was accepted.
+  pub response: ResponseCode,
+  /// The command byte being answered.
+  pub command: u8,
+  /// The DAC status after the command.
+  pub status: DacStatus,
+}
+
+/// A connection to an Ether Dream DAC.
+pub struct Dac {
+  stream: TcpStream,
+  status: DacStatus,
+  buffer_capacity: u16,
+}
+
+/// An in-process Ether Dream that accepts a single connection on localhost.
+pub struct Emulator {
+  address: SocketAddr,
+  broadcast: Broadcast,
+  state: Arc<Mutex<EmulatorState>>,
+}
+
+impl LightEngineState {
+  fn from_u8(value: u8) -> Result<LightEngineState, IldaError> {
+    match value {
+      0 => Ok(LightEngineState::Ready),
+      1 => Ok(LightEngineState::Warmup),
+      2 => Ok(LightEngineState::Cooldown),
+      3 => Ok(LightEngineState::EmergencyStop),
+      _ => Err(IldaError::InvalidData),
+    }
+  }
+
+  fn to_u8(self) -> u8 {
+    match self {
+      LightEngineState::Ready => 0,
+      LightEngineState::Warmup => 1,
+      LightEngineState::Cooldown => 2,
+      LightEngineState::EmergencyStop => 3,
+    }
+  }
+}
+
+impl PlaybackState {
+  fn from_u8(value: u8) -> Result<PlaybackState, IldaError> {
+    match value {
+      0 => Ok(PlaybackState::Idle),
+      1 => Ok(PlaybackState::Prepared),
+      2 => Ok(PlaybackState::Playing),
+      _ => Err(IldaError::InvalidData),
+    }
+  }
+
+  fn to_u8(self) -> u8 {
+    match self {
+      PlaybackState::Idle => 0,
+      PlaybackState::Prepared => 1,
+      PlaybackState::Playing => 2,
+    }
+  }
+}
+
+impl Default for DacStatus {
+  fn default() -> DacStatus {
+    DacStatus {
+      protocol: 0,
+      light_engine_state: LightEngineState::Ready,
+      playback_state: PlaybackState::Idle,
+      source: 0,
+      light_engine_flags: 0,
+      playback_flags: 0,
+      source_flags: 0,
+      buffer_fullness: 0,
+      point_rate: 0,
+      point_count: 0,
+    }
+  }
+}
+
+impl DacStatus {
+  /// Read a status section from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<DacStatus, IldaError> {
+    if bytes.len() != STATUS_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(DacStatus {
+      protocol: bytes[0],
+      light_engine_state: LightEngineState::from_u8(bytes[1])?,
+      playback_state: PlaybackState::from_u8(bytes[2])?,
+      source: bytes[3],
+      light_engine_flags: read_u16(&bytes[4..6]),
+      playback_flags: read_u16(&bytes[6..8]),
+      source_flags: read_u16(&bytes[8..10]),
+      buffer_fullness: read_u16(&bytes[10..12]),
+      point_rate: read_u32(&bytes[12..16]),
+      point_count: read_u32(&bytes[16..20]),
+    })
+  }
+
+  /// Serialize the status section.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(STATUS_SIZE);
+    out.push(self.protocol);
+    out.push(self.light_engine_state.to_u8());
+    out.push(self.playback_state.to_u8());
+    out.push(self.source);
+    out.extend_from_slice(&self.light_engine_flags.to_le_bytes());
+    out.extend_from_slice(&self.playback_flags.to_le_bytes());
+    out.extend_from_slice(&self.source_flags.to_le_bytes());
+    out.extend_from_slice(&self.buffer_fullness.to_le_bytes());
+    out.extend_from_slice(&self.point_rate.to_le_bytes());
+    out.extend_from_slice(&self.point_count.to_le_bytes());
+    out
+  }
+}
+
+impl Broadcast {
+  /// Read a broadcast packet from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<Broadcast, IldaError> {
+    if bytes.len() != BROADCAST_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    let mut mac_address = [0u8; 6];
+    mac_address.copy_from_slice(&bytes[0..6]);
+
+    Ok(Broadcast {
+      mac_address,
+      hw_revision: read_u16(&bytes[6..8]),
+      sw_revision: read_u16(&bytes[8..10]),
+      buffer_capacity: read_u16(&bytes[10..12]),
+      max_point_rate: read_u32(&bytes[12..16]),
+      status: DacStatus::read_bytes(&bytes[16..])?,
+    })
+  }
+
+  /// Serialize the broadcast packet.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BROADCAST_SIZE);
+    out.extend_from_slice(&self.mac_address);
+    out.extend_from_slice(&self.hw_revision.to_le_bytes());
+    out.extend_from_slice(&self.sw_revision.to_le_bytes());
+    out.extend_from_slice(&self.buffer_capacity.to_le_bytes());
+    out.extend_from_slice(&self.max_point_rate.to_le_bytes());
+    out.extend_from_slice(&self.status.to_bytes());
+    out
+  }
+}
+
+impl DacPoint {
+  /// Read a point from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<DacPoint, IldaError> {
+    if bytes.len() != POINT_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(DacPoint {
+      control: read_u16(&bytes[0..2]),
+      x: read_u16(&bytes[2..4]) as i16,
+      y: read_u16(&bytes[4..6]) as i16,
+      r: read_u16(&bytes[6..8]),
+      g: read_u16(&bytes[8..10]),
+      b: read_u16(&bytes[10..12]),
+      i: read_u16(&bytes[12..14]),
+      u1: read_u16(&bytes[14..16]),
+      u2: read_u16(&bytes[16..18]),
+    })
+  }
+
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; POINT_SIZE] {
+    let mut out = [0u8; POINT_SIZE];
+    let fields = [self.control, self.x as u16, self.y as u16, self.r, self.g,
+        self.b, self.i, self.u1, self.u2];
+    for (i, field) in fields.iter().enumerate() {
+      out[i * 2 .. i * 2 + 2].copy_from_slice(&field.to_le_bytes());
+    }
+    out
+  }
+
+  /// Convert to a `SimplePoint`, keeping the high byte of each color.
+  pub fn to_simple_point(&self) -> SimplePoint {
+    let is_blank = self.r == 0 && self.g == 0 && self.b == 0;
+    SimplePoint {
+      x: self.x,
+      y: self.y,
+      r: (self.r >> 8) as u8,
+      g: (self.g >> 8) as u8,
+      b: (self.b >> 8) as u8,
+      is_blank,
+    }
+  }
+}
+
+impl<'a> From<&'a SimplePoint> for DacPoint {
+  fn from(point: &'a SimplePoint) -> DacPoint {
+    if point.is_blank {
+      return DacPoint { x: point.x, y: point.y, ..DacPoint::default() };
+    }
+
+    let i = point.r.max(point.g).max(point.b);
+    DacPoint {
+      control: 0,
+      x: point.x,
+      y: point.y,
+      r: widen(point.r),
+      g: widen(point.g),
+      b: widen(point.b),
+      i: widen(i),
+      u1: 0,
+      u2: 0,
+    }
+  }
+}
+
+impl Command {
+  /// The command byte.
+  pub fn code(&self) -> u8 {
+    match *self {
+      Command::Prepare => COMMAND_PREPARE,
+      Command::Begin { .. } => COMMAND_BEGIN,
+      Command::QueueRateChange { .. } => COMMAND_QUEUE_RATE_CHANGE,
+      Command::Data(_) => COMMAND_DATA,
+      Command::Stop => COMMAND_STOP,
+      Command::EmergencyStop => COMMAND_EMERGENCY_STOP,
+      Command::ClearEmergencyStop => COMMAND_CLEAR_EMERGENCY_STOP,
+      Command::Ping => COMMAND_PING,
+    }
+  }
+
+  /// Serialize the command. Fails with `TooLarge` if a data command has more
+  /// than 65535 points.
+  pub fn to_bytes(&self) -> Result<Vec<u8>, IldaError> {
+    let mut out = vec![self.code()];
+    match *self {
+      Command::Begin { low_water_mark, point_rate } => {
+        out.extend_from_slice(&low_water_mark.to_le_bytes());
+        out.extend_from_slice(&point_rate.to_le_bytes());
+      },
+      Command::QueueRateChange { point_rate } => {
+        out.extend_from_slice(&point_rate.to_le_bytes());
+      },
+      Command::Data(ref points) => {
+        if points.len() > u16::MAX as usize {
+          return Err(IldaError::TooLarge);
+        }
+        out.extend_from_slice(&(points.len() as u16).to_le_bytes());
+        for point in points {
+          out.extend_from_slice(&point.to_bytes());
+        }
+      },
+      _ => {},
+    }
+    Ok(out)
+  }
+
+  /// Read a single command from a stream.
+  pub fn read_from<R: Read>(reader: &mut R) -> Result<Command, IldaError> {
+    let mut code = [0u8; 1];
+    reader.read_exact(&mut code)?;
+
+    let command = match code[0] {
+      COMMAND_PREPARE => Command::Prepare,
+      COMMAND_BEGIN => {
+        let mut bytes = [0u8; 6];
+        reader.read_exact(&mut bytes)?;
+        Command::Begin {
+          low_water_mark: read_u16(&bytes[0..2]),
+          point_rate: read_u32(&bytes[2..6]),
+        }
+      },
+      COMMAND_QUEUE_RATE_CHANGE => {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Command::QueueRateChange { point_rate: read_u32(&bytes) }
+      },
+      COMMAND_DATA => {
+        let mut count = [0u8; 2];
+        reader.read_exact(&mut count)?;
+        let count = read_u16(&count) as usize;
+        let mut bytes = vec![0u8; count * POINT_SIZE];
+        reader.read_exact(&mut bytes)?;
+        let points = bytes.chunks(POINT_SIZE)
+            .map(DacPoint::read_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+        Command::Data(points)
+      },
+      COMMAND_STOP => Command::Stop,
+      COMMAND_EMERGENCY_STOP | COMMAND_EMERGENCY_STOP_ALT =>
+          Command::EmergencyStop,
+      COMMAND_CLEAR_EMERGENCY_STOP => Command::ClearEmergencyStop,
+      COMMAND_PING => Command::Ping,
+      _ => return Err(IldaError::InvalidData),
+    };
+
+    Ok(command)
+  }
+}
+
+impl ResponseCode {
+  fn from_u8(value: u8) -> Result<ResponseCode, IldaError> {
+    match value {
+      b'a' => Ok(ResponseCode::Ack),
+      b'F' => Ok(ResponseCode::NakFull),
+      b'I' => Ok(ResponseCode::NakInvalid),
+      b'!' => Ok(ResponseCode::NakStopCondition),
+      _ => Err(IldaError::InvalidData),
+    }
+  }
+
+  fn to_u8(self) -> u8 {
+    match self {
+      ResponseCode::Ack => b'a',
+      ResponseCode::NakFull => b'F',
+      ResponseCode::NakInvalid => b'I',
+      ResponseCode::NakStopCondition => b'!',
+    }
+  }
+}
+
+impl Response {
+  /// Read a response from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<Response, IldaError> {
+    if bytes.len() != RESPONSE_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(Response {
+      response: ResponseCode::from_u8(bytes[0])?,
+      command: bytes[1],
+      status: DacStatus::read_bytes(&bytes[2..])?,
+    })
+  }
+
+  /// Serialize the response.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(RESPONSE_SIZE);
+    out.push(self.response.to_u8());
+    out.push(self.command);
+    out.extend_from_slice(&self.status.to_bytes());
+    out
+  }
+}
+
+/// Wait for a DAC broadcast on `BROADCAST_PORT`, returning the sender's
+/// address and announcement.
+pub fn discover(timeout: Duration)
+    -> Result<(SocketAddr, Broadcast), IldaError> {
+  let socket = UdpSocket::bind(("0.0.0.0", BROADCAST_PORT))?;
+  receive_broadcast(&socket, timeout)
+}
+
+/// Wait for a DAC broadcast on an already bound socket.
+pub fn receive_broadcast(socket: &UdpSocket, timeout: Duration)
+    -> Result<(SocketAddr, Broadcast), IldaError> {
+  socket.set_read_timeout(Some(timeout))?;
+  let mut buffer = [0u8; 64];
+  let (len, address) = socket.recv_from(&mut buffer)?;
+  Ok((address, Broadcast::read_bytes(&buffer[..len])?))
+}
+
+impl Dac {
+  /// Connect to a DAC and read its initial status. `buffer_capacity` comes
+  /// from the DAC's broadcast.
+  pub fn connect<A: ToSocketAddrs>(address: A, buffer_capacity: u16)
+      -> Result<Dac, IldaError> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut dac = Dac {
+      stream,
+      status: DacStatus::default(),
+      buffer_capacity,
+    };
+
+    // The DAC greets every new connection with a ping response.
+    let response = dac.read_response()?;
+    dac.status = response.status;
+    Ok(dac)
+  }
+
+  /// The status from the most recent response.
+  pub fn get_status(&self) -> &DacStatus {
+    &self.status
+  }
+
+  /// Send a command and wait for its acknowledgement. Any negative
+  /// acknowledgement is reported as `IldaError::Rejected`.
+  pub fn send(&mut self, command: &Command) -> Result<&DacStatus, IldaError> {
+    self.stream.write_all(&command.to_bytes()?)?;
+    let response = self.read_response()?;
+    self.status = response.status;
+
+    if response.command != command.code()
+        || response.response != ResponseCode::Ack {
+      return Err(IldaError::Rejected);
+    }
+
+    Ok(&self.status)
+  }
+
+  /// Request the current status.
+  pub fn ping(&mut self) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::Ping)
+  }
+
+  /// Prepare for playback.
+  pub fn prepare(&mut self) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::Prepare)
+  }
+
+  /// Begin playing buffered points at the given rate.
+  pub fn begin(&mut self, point_rate: u32) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::Begin { low_water_mark: 0, point_rate })
+  }
+
+  /// Queue a scan rate change.
+  pub fn queue_rate_change(&mut self, point_rate: u32)
+      -> Result<&DacStatus, IldaError> {
+    self.send(&Command::QueueRateChange { point_rate })
+  }
+
+  /// Append points to the DAC's buffer. At most 65535 points can be written
+  /// at a time.
+  pub fn write_points(&mut self, points: Vec<DacPoint>)
+      -> Result<&DacStatus, IldaError> {
+    self.send(&Command::Data(points))
+  }
+
+  /// Stop playback.
+  pub fn stop(&mut self) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::Stop)
+  }
+
+  /// Enter the emergency stop state.
+  pub fn emergency_stop(&mut self) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::EmergencyStop)
+  }
+
+  /// Leave the emergency stop state.
+  pub fn clear_emergency_stop(&mut self) -> Result<&DacStatus, IldaError> {
+    self.send(&Command::ClearEmergencyStop)
+  }
+
+  /// Stream an animation once at the given scan rate.
+  pub fn play_animation(&mut self, animation: &Animation, point_rate: u32)
+      -> Result<(), IldaError> {
+    let mut source = AnimationSource::new(animation.clone());
+    self.play(&mut source, point_rate)
+  }
+
+  /// Stream points from a source until it is exhausted, keeping the DAC's
+  /// buffer topped up without overflowing it. Returns once the final points
+  /// have been written; they may still be playing.
+  ///
+  /// Fails with `Unsupported` if the buffer holds fewer than two points, as
+  /// one slot is always left free.
+  pub fn play<S: PointSource>(&mut self, source: &mut S, point_rate: u32)
+      -> Result<(), IldaError> {
+    let point_rate = point_rate.max(1);
+    let capacity = self.buffer_capacity as usize;
+    if capacity < 2 {
+      return Err(IldaError::Unsupported);
+    }
+    // Start playing once this much has been buffered.
+    let start_threshold = (capacity / 2).max(1);
+    // Don't bother writing fewer points than this at a time.
+    let min_batch = (capacity / 4).max(1);
+
+    if self.status.light_engine_state == LightEngineState::EmergencyStop {
+      self.clear_emergency_stop()?;
+    }
+
+    loop {
+      if self.status.playback_state == PlaybackState::Idle {
+        self.prepare()?;
+      }
+
+      // Leave one slot free; the reported fullness lags slightly behind.
+      let fullness = self.status.buffer_fullness as usize;
+      let room = capacity.saturating_sub(fullness + 1);
+
+      if room < min_batch {
+        // Sleep for roughly as long as it takes to drain a batch.
+        let wait = min_batch as u64 * 1_000_000 / point_rate as u64;
+        thread::sleep(Duration::from_micros(wait));
+        self.ping()?;
+        continue;
+      }
+
+      let points: Vec<DacPoint> = source.next_points(room, point_rate)
+          .iter()
+          .map(DacPoint::from)
+          .collect();
+      let finished = points.len() < room;
+
+      if !points.is_empty() {
+        let command = Command::Data(points);
+        if let Err(e) = self.send(&command) {
+          // The buffer ran dry and the DAC dropped back to idle before the
+          // write arrived; prepare again and resend.
+          if self.status.playback_state != PlaybackState::Idle {
+            return Err(e);
+          }
+          self.prepare()?;
+          self.send(&command)?;
+        }
+      }
+
+      let buffered = self.status.buffer_fullness as usize;
+      if self.status.playback_state == PlaybackState::Prepared
+          && (buffered >= start_threshold || finished) && buffered > 0 {
+        self.begin(point_rate)?;
+      }
+
+      if finished {
+        return Ok(());
+      }
+    }
+  }
+
+  fn read_response(&mut self) -> Result<Response, IldaError> {
+    let mut bytes = [0u8; RESPONSE_SIZE];
+    self.stream.read_exact(&mut bytes)?;
+    Response::read_bytes(&bytes)
+  }
+}
+
+impl Drop for Dac {
+  fn drop(&mut self) {
+    let _r = self.stream.shutdown(Shutdown::Both);
+  }
+}
+
+struct EmulatorState {
+  status: DacStatus,
+  buffer: VecDeque<DacPoint>,
+  rate_queue: VecDeque<u32>,
+  played: Vec<DacPoint>,
+  received: Vec<DacPoint>,
+  naks: usize,
+  overflows: usize,
+  last_update: Instant,
+}
+
+impl EmulatorState {
+  // Play out however many points would have been scanned since the last
+  // update at the current rate.
+  fn update(&mut self) {
+    let now = Instant::now();
+    if self.status.playback_state != PlaybackState::Playing {
+      self.last_update = now;
+      return;
+    }
+
+    let elapsed = now.duration_since(self.last_update);
+    let due = elapsed.as_micros() * self.status.point_rate as u128 / 1_000_000;
+    if due == 0 {
+      return;
+    }
+
+    // Carry the remainder over so slow polling doesn't lose time.
+    let used = due * 1_000_000 / self.status.point_rate.max(1) as u128;
+    self.last_update += Duration::from_micros(used as u64);
+
+    self.play_out(due as usize);
+  }
+
+  // Scan up to `count` points out of the buffer, dropping back to idle if
+  // it runs dry.
+  fn play_out(&mut self, count: usize) {
+    for _ in 0..count {
+      match self.buffer.pop_front() {
+        Some(point) => {
+          if point.control & CONTROL_RATE_CHANGE != 0 {
+            if let Some(rate) = self.rate_queue.pop_front() {
+              self.status.point_rate = rate;
+            }
+          }
+          self.played.push(point);
+          self.status.point_count = self.status.point_count.wrapping_add(1);
+        },
+        None => {
+          self.status.playback_state = PlaybackState::Idle;
+          self.status.playback_flags |= PLAYBACK_FLAG_UNDERFLOW;
+          break;
+        },
+      }
+    }
+
+    self.status.buffer_fullness = self.buffer.len() as u16;
+  }
+
+  fn handle(&mut self, command: Command, capacity: u16) -> ResponseCode {
+    self.update();
+
+    let stopped =
+        self.status.light_engine_state == LightEngineState::EmergencyStop;
+    let state = self.status.playback_state;
+
+    let response = match command {
+      Command::Ping => ResponseCode::Ack,
+      Command::Prepare if stopped => ResponseCode::NakStopCondition,
+      Command::Prepare if state != PlaybackState::Idle =>
+          ResponseCode::NakInvalid,
+      Command::Prepare => {
+        self.buffer.clear();
+        self.rate_queue.clear();
+        self.status.playback_state = PlaybackState::Prepared;
+        self.status.playback_flags = 0;
+        self.status.point_count = 0;
+        ResponseCode::Ack
+      },
+      Command::Begin { .. } if state != PlaybackState::Prepared =>
+          ResponseCode::NakInvalid,
+      Command::Begin { point_rate, .. } => {
+        self.status.playback_state = PlaybackState::Playing;
+        self.status.point_rate = point_rate;
+        self.last_update = Instant::now();
+        ResponseCode::Ack
+      },
+      Command::QueueRateChange { .. } if state == PlaybackState::Idle =>
+          ResponseCode::NakInvalid,
+      Command::QueueRateChange { point_rate } => {
+        self.rate_queue.push_back(point_rate);
+        ResponseCode::Ack
+      },
+      Command::Data(_) if state == PlaybackState::Idle =>
+          ResponseCode::NakInvalid,
+      Command::Data(ref points)
+          if self.buffer.len() + points.len() > capacity as usize =>
+          ResponseCode::NakFull,
+      Command::Data(points) => {
+        self.received.extend_from_slice(&points);
+        self.buffer.extend(points);
+        ResponseCode::Ack
+      },
+      Command::Stop if state == PlaybackState::Idle =>
+          ResponseCode::NakInvalid,
+      Command::Stop => {
+        self.buffer.clear();
+        self.status.playback_state = PlaybackState::Idle;
+        ResponseCode::Ack
+      },
+      Command::EmergencyStop => {
+        self.buffer.clear();
+        self.status.playback_state = PlaybackState::Idle;
+        self.status.light_engine_state = LightEngineState::EmergencyStop;
+        ResponseCode::Ack
+      },
+      Command::ClearEmergencyStop => {
+        self.status.light_engine_state = LightEngineState::Ready;
+        ResponseCode::Ack
+      },
+    };
+
+    if response != ResponseCode::Ack {
+      self.naks += 1;
+    }
+    if response == ResponseCode::NakFull {
+      self.overflows += 1;
+    }
+
+    self.status.buffer_fullness = self.buffer.len() as u16;
+    response
+  }
+}
+
+impl Emulator {
+  /// Start an emulated DAC with the given buffer size, listening on an
+  /// ephemeral localhost port.
+  pub fn start(buffer_capacity: u16) -> Result<Emulator, IldaError> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+
+    let state = Arc::new(Mutex::new(EmulatorState {
+      status: DacStatus::default(),
+      buffer: VecDeque::new(),
+      rate_queue: VecDeque::new(),
+      played: Vec::new(),
+      received: Vec::new(),
+      naks: 0,
+      overflows: 0,
+      last_update: Instant::now(),
+    }));
+
+    let broadcast = Broadcast {
+      mac_address: [0x00, 0x04, 0xa3, 0x00, 0x00, 0x01],
+      hw_revision: 2,
+      sw_revision: 2,
+      buffer_capacity,
+      max_point_rate: 100_000,
+      status: DacStatus::default(),
+    };
+
+    let thread_state = state.clone();
+    thread::spawn(move || {
+      if let Ok((stream, _)) = listener.accept() {
+        let _r = Emulator::serve(stream, thread_state, buffer_capacity);
+      }
+    });
+
+    Ok(Emulator { address, broadcast, state })
+  }
+
+  /// The address the emulator accepts connections on.
+  pub fn address(&self) -> SocketAddr {
+    self.address
+  }
+
+  /// The emulator's presence announcement, with its current status.
+  pub fn broadcast(&self) -> Broadcast {
+    let mut broadcast = self.broadcast.clone();
+    broadcast.status = self.lock().status.clone();
+    broadcast
+  }
+
+  /// Send the presence announcement to the given address.
+  pub fn send_broadcast<A: ToSocketAddrs>(&self, address: A)
+      -> Result<(), IldaError> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.send_to(&self.broadcast().to_bytes(), address)?;
+    Ok(())
+  }
+
+  /// Every point accepted into the buffer, in order.
+  pub fn received_points(&self) -> Vec<DacPoint> {
+    self.lock().received.clone()
+  }
+
+  /// Every point scanned out of the buffer so far, in order.
+  pub fn played_points(&self) -> Vec<DacPoint> {
+    let mut state = self.lock();
+    state.update();
+    state.played.clone()
+  }
+
+  /// Number of commands that were negatively acknowledged.
+  pub fn nak_count(&self) -> usize {
+    self.lock().naks
+  }
+
+  /// Number of data commands refused because the buffer was full.
+  pub fn overflow_count(&self) -> usize {
+    self.lock().overflows
+  }
+
+  /// Scan out everything buffered at once, so the DAC runs dry and drops
+  /// back to idle as if the host had fallen behind. Does nothing unless
+  /// playing.
+  pub fn underflow(&self) {
+    let mut state = self.lock();
+    state.update();
+    if state.status.playback_state == PlaybackState::Playing {
+      let count = state.buffer.len() + 1;
+      state.play_out(count);
+    }
+  }
+
+  fn lock(&self) -> ::std::sync::MutexGuard<'_, EmulatorState> {
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn serve(mut stream: TcpStream, state: Arc<Mutex<EmulatorState>>,
+      capacity: u16) -> Result<(), IldaError> {
+    stream.set_nodelay(true)?;
+
+    let greeting = Response {
+      response: ResponseCode::Ack,
+      command: COMMAND_PING,
+      status: state.lock().unwrap_or_else(|e| e.into_inner()).status.clone(),
+    };
+    stream.write_all(&greeting.to_bytes())?;
+
+    loop {
+      let (code, response) = match Command::read_from(&mut stream) {
+        Ok(command) => {
+          let code = command.code();
+          let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+          (code, state.handle(command, capacity))
+        },
+        Err(IldaError::InvalidData) => (0, ResponseCode::NakInvalid),
+        Err(e) => return Err(e), // Connection closed.
+      };
+
+      let status = state.lock().unwrap_or_else(|e| e.into_inner())
+          .status.clone();
+      let response = Response { response, command: code, status };
+      stream.write_all(&response.to_bytes())?;
+    }
+  }
+}
+
+fn widen(value: u8) -> u16 {
+  ((value as u16) << 8) | value as u16
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+  (bytes[0] as u16) | ((bytes[1] as u16) << 8)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32)
+      | ((bytes[1] as u32) << 8)
+      | ((bytes[2] as u32) << 16)
+      | ((bytes[3] as u32) << 24)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use animation::Frame;
+
+  fn status() -> DacStatus {
+    DacStatus {
+      protocol: 0,
+      light_engine_state: LightEngineState::Ready,
+      playback_state: PlaybackState::Playing,
+      source: 0,
+      light_engine_flags: 0,
+      playback_flags: PLAYBACK_FLAG_UNDERFLOW,
+      source_flags: 0,
+      buffer_fullness: 1000,
+      point_rate: 30_000,
+      point_count: 123_456,
+    }
+  }
+
+  #[test]
+  fn test_status_round_trip() {
+    let bytes = status().to_bytes();
+    assert_eq!(STATUS_SIZE, bytes.len());
+    assert_eq!(2, bytes[2]);
+    assert_eq!(&[0xe8, 0x03], &bytes[10..12]); // 1000, little endian
+    assert_eq!(status(), DacStatus::read_bytes(&bytes).unwrap());
+
+    assert!(DacStatus::read_bytes(&bytes[1..]).is_err());
+  }
+
+  #[test]
+  fn test_broadcast_parsing() {
+    let mut bytes = vec![1, 2, 3, 4, 5, 6, 2, 0, 3, 0, 0x07, 0x07,
+        0xa0, 0x86, 0x01, 0x00];
+    bytes.extend_from_slice(&status().to_bytes());
+
+    let broadcast = Broadcast::read_bytes(&bytes).unwrap();
+    assert_eq!([1, 2, 3, 4, 5, 6], broadcast.mac_address);
+    assert_eq!(2, broadcast.hw_revision);
+    assert_eq!(3, broadcast.sw_revision);
+    assert_eq!(1799, broadcast.buffer_capacity);
+    assert_eq!(100_000, broadcast.max_point_rate);
+    assert_eq!(status(), broadcast.status);
+    assert_eq!(bytes, broadcast.to_bytes());
+  }
+
+  #[test]
+  fn test_command_round_trip() {
+    let point = DacPoint { control: CONTROL_RATE_CHANGE, x: -5, y: 5,
+        r: 1, g: 2, b: 3, i: 4, u1: 0, u2: 0 };
+    let commands = vec![
+      Command::Prepare,
+      Command::Begin { low_water_mark: 0, point_rate: 20_000 },
+      Command::QueueRateChange { point_rate: 40_000 },
+      Command::Data(vec![point, DacPoint::default()]),
+      Command::Stop,
+      Command::EmergencyStop,
+      Command::ClearEmergencyStop,
+      Command::Ping,
+    ];
+
+    for command in commands {
+      let bytes = command.to_bytes().unwrap();
+      let read = Command::read_from(&mut &bytes[..]).unwrap();
+      assert_eq!(command, read);
+    }
+
+    let data = Command::Data(vec![point]).to_bytes().unwrap();
+    assert_eq!(1 + 2 + POINT_SIZE, data.len());
+    assert_eq!(&[b'd', 1, 0, 0x00, 0x80, 0xfb, 0xff], &data[0..7]);
+
+    let too_many = Command::Data(vec![DacPoint::default(); 65536]);
+    assert!(matches!(too_many.to_bytes(), Err(IldaError::TooLarge)));
+
+    assert!(Command::read_from(&mut &[b'x'][..]).is_err());
+  }
+
+  #[test]
+  fn test_response_decoding() {
+    let mut bytes = vec![b'F', b'd'];
+    bytes.extend_from_slice(&status().to_bytes());
+
+    let response = Response::read_bytes(&bytes).unwrap();
+    assert_eq!(ResponseCode::NakFull, response.response);
+    assert_eq!(b'd', response.command);
+    assert_eq!(bytes, response.to_bytes());
+
+    bytes[0] = b'?';
+    assert!(Response::read_bytes(&bytes).is_err());
+  }
+
+  #[test]
+  fn test_point_conversion() {
+    let point = SimplePoint { x: 10, y: -10, r: 255, g: 128, b: 0,
+        is_blank: false };
+    let dac_point = DacPoint::from(&point);
+    assert_eq!(0xffff, dac_point.r);
+    assert_eq!(0x8080, dac_point.g);
+    assert_eq!(0, dac_point.b);
+    assert_eq!(0xffff, dac_point.i);
+    let round_trip = dac_point.to_simple_point();
+    assert_eq!((10, -10, 255, 128, 0, false), (round_trip.x, round_trip.y,
+        round_trip.r, round_trip.g, round_trip.b, round_trip.is_blank));
+
+    let blank = SimplePoint { is_blank: true, ..point };
+    let dac_point = DacPoint::from(&blank);
+    assert_eq!(0, dac_point.r);
+    assert_eq!(0, dac_point.i);
+    assert_eq!(10, dac_point.x);
+  }
+
+  #[test]
+  fn test_emulator_state_machine() {
+    let emulator = Emulator::start(10).unwrap();
+    let mut dac = Dac::connect(emulator.address(), 10).unwrap();
+    assert_eq!(PlaybackState::Idle, dac.get_status().playback_state);
+
+    // Data is not accepted until prepared.
+    assert!(dac.write_points(vec![DacPoint::default()]).is_err());
+    assert!(dac.begin(1_000).is_err());
+
+    dac.prepare().unwrap();
+    assert_eq!(PlaybackState::Prepared, dac.get_status().playback_state);
+    assert!(dac.prepare().is_err());
+
+    dac.write_points(vec![DacPoint::default(); 8]).unwrap();
+    assert_eq!(8, dac.get_status().buffer_fullness);
+
+    // Overflow is refused.
+    assert!(dac.write_points(vec![DacPoint::default(); 3]).is_err());
+
+    dac.emergency_stop().unwrap();
+    assert_eq!(LightEngineState::EmergencyStop,
+        dac.get_status().light_engine_state);
+    assert!(dac.prepare().is_err());
+    dac.clear_emergency_stop().unwrap();
+    dac.prepare().unwrap();
+    dac.stop().unwrap();
+
+    assert_eq!(5, emulator.nak_count());
+    assert_eq!(1, emulator.overflow_count());
+  }
+
+  #[test]
+  fn test_stream_animation_to_emulator() {
+    let points: Vec<_> = (0..500)
+        .map(|i| SimplePoint { x: i, y: -i, r: 255, g: 0, b: 0,
+            is_blank: i % 10 == 0 })
+        .collect();
+    let animation = Animation::new(vec![
+      Frame::new(points[..200].to_vec()),
+      Frame::new(points[200..].to_vec()),
+    ]);
+
+    // Whether the buffer ever runs dry depends on timing, but every point
+    // arrives either way.
+    let emulator = Emulator::start(128).unwrap();
+    let mut dac = Dac::connect(emulator.address(), 128).unwrap();
+    dac.play_animation(&animation, 1_000).unwrap();
+
+    let received: Vec<_> = emulator.received_points().iter()
+        .map(DacPoint::to_simple_point)
+        .map(|point| (point.x, point.y, point.is_blank))
+        .collect();
+    let expected: Vec<_> = points.iter()
+        .map(|point| (point.x, point.y, point.is_blank))
+        .collect();
+    assert_eq!(expected, received);
+  }
+
+  // Runs the emulator dry the first time points are requested.
+  struct UnderflowSource<'a> {
+    emulator: &'a Emulator,
+    points: Vec<SimplePoint>,
+  }
+
+  impl<'a> PointSource for UnderflowSource<'a> {
+    fn next_points(&mut self, count: usize, _points_per_second: u32)
+        -> Vec<SimplePoint> {
+      self.emulator.underflow();
+      let count = count.min(self.points.len());
+      self.points.drain(..count).collect()
+    }
+  }
+
+  #[test]
+  fn test_play_recovers_from_underflow() {
+    let emulator = Emulator::start(64).unwrap();
+    let mut dac = Dac::connect(emulator.address(), 64).unwrap();
+
+    // Start playing, then let the buffer run dry behind the driver's back.
+    dac.prepare().unwrap();
+    dac.write_points(vec![DacPoint::default(); 8]).unwrap();
+    dac.begin(1_000).unwrap();
+    assert_eq!(PlaybackState::Playing, dac.get_status().playback_state);
+
+    let points: Vec<_> = (1..41)
+        .map(|i| SimplePoint { x: i, ..SimplePoint::default() })
+        .collect();
+    let mut source = UnderflowSource { emulator: &emulator, points };
+    dac.play(&mut source, 1_000).unwrap();
+
+    // The first write is refused while idle, then prepared and resent.
+    assert_eq!(1, emulator.nak_count());
+    assert_eq!(PlaybackState::Playing, dac.get_status().playback_state);
+    let received: Vec<_> = emulator.received_points().iter()
+        .map(|point| point.x)
+        .collect();
+    let expected: Vec<i16> = vec![0; 8].into_iter().chain(1..41).collect();
+    assert_eq!(expected, received);
+  }
+
+  #[test]
+  fn test_play_needs_room() {
+    let emulator = Emulator::start(1).unwrap();
+    let mut dac = Dac::connect(emulator.address(), 1).unwrap();
+    let animation = Animation::new(vec![Frame::new(vec![
+      SimplePoint::default(),
+    ])]);
+    assert!(matches!(dac.play_animation(&animation, 1_000),
+        Err(IldaError::Unsupported)));
+  }
+
+  #[test]
+  fn test_broadcast_discovery() {
+    let emulator = Emulator::start(DEFAULT_BUFFER_CAPACITY).unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    emulator.send_broadcast(socket.local_addr().unwrap()).unwrap();
+
+    let (_, broadcast) =
+        receive_broadcast(&socket, Duration::from_secs(5)).unwrap();
+    assert_eq!(DEFAULT_BUFFER_CAPACITY, broadcast.buffer_capacity);
+    assert_eq!(emulator.broadcast(), broadcast);
+  }
+}
@@ -0,0 +1,594 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Helios USB DAC frame encoding and control.
+//!
+//! The Helios takes whole frames over a USB bulk endpoint. Each point packs
+//! 12-bit X and Y into three bytes followed by 8-bit red, green, blue and
+//! intensity, and the frame ends with its scan rate, point count and flags.
+//! Status polling, stopping and the shutter use short messages on a separate
+//! interrupt endpoint. The USB layer is hidden behind the `Transport` trait;
+//! `MockTransport` records packets so conversion can be tested without
+//! hardware.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use std::collections::VecDeque;
+
+/// Size of a single encoded point in bytes.
+pub const POINT_SIZE : usize = 7;
+/// Size of the trailer that follows a frame's points in bytes.
+pub const FRAME_TRAILER_SIZE : usize = 5;
+/// Most points the DAC accepts in one frame.
+pub const MAX_POINTS : usize = 0x1000;
+/// Slowest supported scan rate in points per second.
+pub const MIN_RATE : u32 = 7;
+/// Fastest supported scan rate in points per second.
+pub const MAX_RATE : u32 = 0xffff;
+/// Largest coordinate value.
+pub const MAX_COORDINATE : u16 = 0x0fff;
+/// Size of a control message in bytes.
+pub const CONTROL_SIZE : usize = 2;
+/// Size of a control response buffer in bytes.
+pub const CONTROL_RESPONSE_SIZE : usize = 32;
+
+/// Frame flag: start the frame immediately rather than after the current one.
+pub const FLAG_START_IMMEDIATELY : u8 = 0x01;
+/// Frame flag: play the frame once instead of repeating it.
+pub const FLAG_SINGLE_MODE : u8 = 0x02;
+/// Frame flag: return immediately instead of blocking.
+pub const FLAG_DONT_BLOCK : u8 = 0x04;
+
+/// How many times to poll for readiness before giving up on a frame.
+pub const DEFAULT_STATUS_ATTEMPTS : usize = 512;
+
+const CONTROL_STOP : u8 = 0x01;
+const CONTROL_SET_SHUTTER : u8 = 0x02;
+const CONTROL_GET_STATUS : u8 = 0x03;
+const CONTROL_GET_FIRMWARE_VERSION : u8 = 0x04;
+const CONTROL_GET_NAME : u8 = 0x05;
+const RESPONSE_FLAG : u8 = 0x80;
+
+/// The USB endpoints a Helios is driven through.
+pub trait Transport {
+  /// Write a message to the interrupt (control) endpoint.
+  fn write_control(&mut self, data: &[u8]) -> Result<(), IldaError>;
+
+  /// Read a response from the interrupt (control) endpoint, returning the
+  /// number of bytes read.
+  fn read_control(&mut self, buffer: &mut [u8]) -> Result<usize, IldaError>;
+
+  /// Write a frame to the bulk endpoint.
+  fn write_bulk(&mut self, data: &[u8]) -> Result<(), IldaError>;
+}
+
+/// A point in the DAC's native resolution.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[allow(missing_docs)]
+pub struct HeliosPoint {
+  pub x: u16,
+  pub y: u16,
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+  pub i: u8,
+}
+
+/// Messages sent to the control endpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlCommand {
+  /// Stop output and blank the beam.
+  Stop,
+  /// Open (`true`) or close the shutter.
+  SetShutter(bool),
+  /// Ask whether the DAC is ready for another frame.
+  GetStatus,
+  /// Ask for the firmware version.
+  GetFirmwareVersion,
+  /// Ask for the device name.
+  GetName,
+}
+
+/// Where the driver is in its conversation with the DAC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DacState {
+  /// No frames have been written since connecting or stopping.
+  Idle,
+  /// The DAC reported it was ready and hasn't been sent a frame since.
+  Ready,
+  /// A frame has been written and is playing.
+  Playing,
+  /// Output has been stopped.
+  Stopped,
+}
+
+/// A Helios DAC on some transport.
+pub struct Dac<T: Transport> {
+  transport: T,
+  state: DacState,
+  shutter_open: bool,
+  status_attempts: usize,
+}
+
+/// A transport that records everything written to it and answers control
+/// requests like an attached DAC would.
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+  /// Every message written to the control endpoint.
+  pub control_writes: Vec<Vec<u8>>,
+  /// Every frame written to the bulk endpoint.
+  pub bulk_writes: Vec<Vec<u8>>,
+  /// Number of upcoming status polls to answer with "busy".
+  pub busy_polls: usize,
+  /// Firmware version to report.
+  pub firmware_version: u32,
+  /// Device name to report.
+  pub name: String,
+  responses: VecDeque<Vec<u8>>,
+}
+
+impl<'a> From<&'a SimplePoint> for HeliosPoint {
+  fn from(point: &'a SimplePoint) -> HeliosPoint {
+    let x = ((point.x as i32 + 32768) >> 4) as u16;
+    let y = ((point.y as i32 + 32768) >> 4) as u16;
+
+    if point.is_blank {
+      return HeliosPoint { x, y, ..HeliosPoint::default() };
+    }
+
+    HeliosPoint {
+      x,
+      y,
+      r: point.r,
+      g: point.g,
+      b: point.b,
+      i: point.r.max(point.g).max(point.b),
+    }
+  }
+}
+
+impl HeliosPoint {
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; POINT_SIZE] {
+    let x = self.x.min(MAX_COORDINATE);
+    let y = self.y.min(MAX_COORDINATE);
+    [
+      (x >> 4) as u8,
+      (((x & 0x0f) << 4) | (y >> 8)) as u8,
+      (y & 0xff) as u8,
+      self.r,
+      self.g,
+      self.b,
+      self.i,
+    ]
+  }
+
+  /// Read a point from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<HeliosPoint, IldaError> {
+    if bytes.len() != POINT_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(HeliosPoint {
+      x: ((bytes[0] as u16) << 4) | ((bytes[1] as u16) >> 4),
+      y: (((bytes[1] as u16) & 0x0f) << 8) | bytes[2] as u16,
+      r: bytes[3],
+      g: bytes[4],
+      b: bytes[5],
+      i: bytes[6],
+    })
+  }
+}
+
+/// Encode a frame for the bulk endpoint.
+pub fn encode_frame(points: &[HeliosPoint], rate: u32, flags: u8)
+    -> Result<Vec<u8>, IldaError> {
+  if points.is_empty() {
+    return Err(IldaError::NoData);
+  }
+
+  if points.len() > MAX_POINTS || !(MIN_RATE..=MAX_RATE).contains(&rate) {
+    return Err(IldaError::Unsupported);
+  }
+
+  let mut out = Vec::with_capacity(points.len() * POINT_SIZE
+      + FRAME_TRAILER_SIZE);
+  for point in points {
+    out.extend_from_slice(&point.to_bytes());
+  }
+  out.extend_from_slice(&(rate as u16).to_le_bytes());
+  out.extend_from_slice(&(points.len() as u16).to_le_bytes());
+  out.push(flags);
+  Ok(out)
+}
+
+/// Decode a frame written to the bulk endpoint, returning the points, scan
+/// rate and flags.
+pub fn decode_frame(bytes: &[u8])
+    -> Result<(Vec<HeliosPoint>, u32, u8), IldaError> {
+  if bytes.len() < FRAME_TRAILER_SIZE
+      || (bytes.len() - FRAME_TRAILER_SIZE) % POINT_SIZE != 0 {
+    return Err(IldaError::InvalidData);
+  }
+
+  let trailer = &bytes[bytes.len() - FRAME_TRAILER_SIZE ..];
+  let rate = (trailer[0] as u32) | ((trailer[1] as u32) << 8);
+  let count = (trailer[2] as usize) | ((trailer[3] as usize) << 8);
+  let body = &bytes[.. bytes.len() - FRAME_TRAILER_SIZE];
+
+  if body.len() != count * POINT_SIZE {
+    return Err(IldaError::InvalidData);
+  }
+
+  let points = body.chunks(POINT_SIZE)
+      .map(HeliosPoint::read_bytes)
+      .collect::<Result<Vec<_>, _>>()?;
+
+  Ok((points, rate, trailer[4]))
+}
+
+impl ControlCommand {
+  /// Serialize the control message.
+  pub fn to_bytes(&self) -> [u8; CONTROL_SIZE] {
+    match *self {
+      ControlCommand::Stop => [CONTROL_STOP, 0],
+      ControlCommand::SetShutter(open) => [CONTROL_SET_SHUTTER, open as u8],
+      ControlCommand::GetStatus => [CONTROL_GET_STATUS, 0],
+      ControlCommand::GetFirmwareVersion => [CONTROL_GET_FIRMWARE_VERSION, 0],
+      ControlCommand::GetName => [CONTROL_GET_NAME, 0],
+    }
+  }
+
+  /// Read a control message from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<ControlCommand, IldaError> {
+    if bytes.len() < CONTROL_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    match bytes[0] {
+      CONTROL_STOP => Ok(ControlCommand::Stop),
+      CONTROL_SET_SHUTTER => Ok(ControlCommand::SetShutter(bytes[1] != 0)),
+      CONTROL_GET_STATUS => Ok(ControlCommand::GetStatus),
+      CONTROL_GET_FIRMWARE_VERSION => Ok(ControlCommand::GetFirmwareVersion),
+      CONTROL_GET_NAME => Ok(ControlCommand::GetName),
+      _ => Err(IldaError::InvalidData),
+    }
+  }
+
+  // Whether the DAC answers this message.
+  fn has_response(&self) -> bool {
+    match *self {
+      ControlCommand::GetStatus
+          | ControlCommand::GetFirmwareVersion
+          | ControlCommand::GetName => true,
+      ControlCommand::Stop | ControlCommand::SetShutter(_) => false,
+    }
+  }
+}
+
+impl<T: Transport> Dac<T> {
+  /// Drive a DAC over the given transport.
+  pub fn new(transport: T) -> Dac<T> {
+    Dac {
+      transport,
+      state: DacState::Idle,
+      shutter_open: false,
+      status_attempts: DEFAULT_STATUS_ATTEMPTS,
+    }
+  }
+
+  /// Get a reference to the transport.
+  pub fn get_transport(&self) -> &T {
+    &self.transport
+  }
+
+  /// Get a mutable reference to the transport.
+  pub fn get_transport_mut(&mut self) -> &mut T {
+    &mut self.transport
+  }
+
+  /// Where the driver is in its conversation with the DAC.
+  pub fn get_state(&self) -> DacState {
+    self.state
+  }
+
+  /// Whether the shutter was last opened.
+  pub fn is_shutter_open(&self) -> bool {
+    self.shutter_open
+  }
+
+  /// How many times to poll for readiness before a frame write fails with
+  /// `IldaError::Rejected`.
+  pub fn set_status_attempts(&mut self, attempts: usize) {
+    self.status_attempts = attempts.max(1);
+  }
+
+  /// Ask whether the DAC is ready for another frame.
+  pub fn get_status(&mut self) -> Result<bool, IldaError> {
+    let response = self.request(ControlCommand::GetStatus)?;
+    let ready = response.get(1).cloned().unwrap_or(0) == 1;
+    if ready && self.state != DacState::Stopped {
+      self.state = DacState::Ready;
+    }
+    Ok(ready)
+  }
+
+  /// Ask for the firmware version.
+  pub fn get_firmware_version(&mut self) -> Result<u32, IldaError> {
+    let response = self.request(ControlCommand::GetFirmwareVersion)?;
+    if response.len() < 5 {
+      return Err(IldaError::InvalidData);
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&response[1..5]);
+    Ok(u32::from_le_bytes(bytes))
+  }
+
+  /// Ask for the device name.
+  pub fn get_name(&mut self) -> Result<String, IldaError> {
+    let response = self.request(ControlCommand::GetName)?;
+    Ok(response[1..].iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect())
+  }
+
+  /// Open or close the shutter.
+  pub fn set_shutter(&mut self, open: bool) -> Result<(), IldaError> {
+    self.request(ControlCommand::SetShutter(open))?;
+    self.shutter_open = open;
+    Ok(())
+  }
+
+  /// Stop output.
+  pub fn stop(&mut self) -> Result<(), IldaError> {
+    self.request(ControlCommand::Stop)?;
+    self.state = DacState::Stopped;
+    Ok(())
+  }
+
+  /// Wait until the DAC is ready, then write a frame. Frames with more than
+  /// `MAX_POINTS` points are rejected.
+  pub fn write_frame(&mut self, frame: &Frame, rate: u32, flags: u8)
+      -> Result<(), IldaError> {
+    let points: Vec<HeliosPoint> = frame.get_points().iter()
+        .map(HeliosPoint::from)
+        .collect();
+    let bytes = encode_frame(&points, rate, flags)?;
+
+    if !self.wait_until_ready()? {
+      return Err(IldaError::Rejected);
+    }
+
+    if !self.shutter_open {
+      self.set_shutter(true)?;
+    }
+
+    self.transport.write_bulk(&bytes)?;
+    self.state = DacState::Playing;
+    Ok(())
+  }
+
+  /// Write every frame of an animation in turn, each played once.
+  pub fn play_animation(&mut self, animation: &Animation, rate: u32)
+      -> Result<(), IldaError> {
+    for frame in animation.into_frame_iter() {
+      if frame.point_count() == 0 {
+        continue;
+      }
+      self.write_frame(frame, rate, FLAG_SINGLE_MODE)?;
+    }
+    Ok(())
+  }
+
+  fn wait_until_ready(&mut self) -> Result<bool, IldaError> {
+    for _ in 0..self.status_attempts {
+      if self.get_status()? {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  fn request(&mut self, command: ControlCommand) -> Result<Vec<u8>, IldaError> {
+    self.transport.write_control(&command.to_bytes())?;
+
+    if !command.has_response() {
+      return Ok(Vec::new());
+    }
+
+    let mut buffer = [0u8; CONTROL_RESPONSE_SIZE];
+    let len = self.transport.read_control(&mut buffer)?;
+    let expected = command.to_bytes()[0] | RESPONSE_FLAG;
+
+    if len < 2 || buffer[0] != expected {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(buffer[..len].to_vec())
+  }
+}
+
+impl MockTransport {
+  /// Create a transport for an idle, ready DAC.
+  pub fn new() -> MockTransport {
+    MockTransport {
+      name: "Helios".to_string(),
+      ..MockTransport::default()
+    }
+  }
+
+  /// Decode every frame written to the bulk endpoint.
+  pub fn frames(&self) -> Result<Vec<(Vec<HeliosPoint>, u32, u8)>, IldaError> {
+    self.bulk_writes.iter().map(|bytes| decode_frame(bytes)).collect()
+  }
+}
+
+impl Transport for MockTransport {
+  fn write_control(&mut self, data: &[u8]) -> Result<(), IldaError> {
+    self.control_writes.push(data.to_vec());
+
+    let response = match ControlCommand::read_bytes(data)? {
+      ControlCommand::GetStatus => {
+        let ready = if self.busy_polls > 0 {
+          self.busy_polls -= 1;
+          0
+        } else {
+          1
+        };
+        vec![CONTROL_GET_STATUS | RESPONSE_FLAG, ready]
+      },
+      ControlCommand::GetFirmwareVersion => {
+        let mut response = vec![CONTROL_GET_FIRMWARE_VERSION | RESPONSE_FLAG];
+        response.extend_from_slice(&self.firmware_version.to_le_bytes());
+        response
+      },
+      ControlCommand::GetName => {
+        let mut response = vec![CONTROL_GET_NAME | RESPONSE_FLAG];
+        response.extend(self.name.bytes().take(CONTROL_RESPONSE_SIZE - 2));
+        response.push(0);
+        response
+      },
+      ControlCommand::Stop | ControlCommand::SetShutter(_) => return Ok(()),
+    };
+
+    self.responses.push_back(response);
+    Ok(())
+  }
+
+  fn read_control(&mut self, buffer: &mut [u8]) -> Result<usize, IldaError> {
+    let response = self.responses.pop_front().ok_or(IldaError::NoData)?;
+    let len = response.len().min(buffer.len());
+    buffer[..len].copy_from_slice(&response[..len]);
+    Ok(len)
+  }
+
+  fn write_bulk(&mut self, data: &[u8]) -> Result<(), IldaError> {
+    self.bulk_writes.push(data.to_vec());
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(x: i16, y: i16, is_blank: bool) -> SimplePoint {
+    SimplePoint { x, y, r: 255, g: 100, b: 0, is_blank }
+  }
+
+  #[test]
+  fn test_point_conversion() {
+    let min = HeliosPoint::from(&point(-32768, -32768, false));
+    assert_eq!((0, 0), (min.x, min.y));
+
+    let max = HeliosPoint::from(&point(32767, 32767, false));
+    assert_eq!((MAX_COORDINATE, MAX_COORDINATE), (max.x, max.y));
+
+    let center = HeliosPoint::from(&point(0, 0, false));
+    assert_eq!((0x800, 0x800), (center.x, center.y));
+    assert_eq!((255, 100, 0, 255), (center.r, center.g, center.b, center.i));
+
+    let blank = HeliosPoint::from(&point(0, 0, true));
+    assert_eq!((0, 0, 0, 0), (blank.r, blank.g, blank.b, blank.i));
+  }
+
+  #[test]
+  fn test_point_packing() {
+    let point = HeliosPoint { x: 0xabc, y: 0x123, r: 1, g: 2, b: 3, i: 4 };
+    let bytes = point.to_bytes();
+    assert_eq!([0xab, 0xc1, 0x23, 1, 2, 3, 4], bytes);
+    assert_eq!(point, HeliosPoint::read_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_encode_frame() {
+    let points = vec![HeliosPoint::default(); 3];
+    let bytes = encode_frame(&points, 30_000, FLAG_SINGLE_MODE).unwrap();
+    assert_eq!(3 * POINT_SIZE + FRAME_TRAILER_SIZE, bytes.len());
+    assert_eq!(&[0x30, 0x75, 3, 0, FLAG_SINGLE_MODE], &bytes[21..]);
+
+    let (decoded, rate, flags) = decode_frame(&bytes).unwrap();
+    assert_eq!(points, decoded);
+    assert_eq!(30_000, rate);
+    assert_eq!(FLAG_SINGLE_MODE, flags);
+
+    assert!(encode_frame(&[], 30_000, 0).is_err());
+    assert!(encode_frame(&points, 6, 0).is_err());
+    assert!(encode_frame(&points, 70_000, 0).is_err());
+    assert!(encode_frame(&vec![HeliosPoint::default(); MAX_POINTS + 1],
+        30_000, 0).is_err());
+    assert!(decode_frame(&bytes[1..]).is_err());
+  }
+
+  #[test]
+  fn test_control_messages() {
+    let mut transport = MockTransport::new();
+    transport.firmware_version = 6;
+    let mut dac = Dac::new(transport);
+
+    assert_eq!(6, dac.get_firmware_version().unwrap());
+    assert_eq!("Helios", dac.get_name().unwrap());
+    dac.set_shutter(true).unwrap();
+    assert!(dac.is_shutter_open());
+    dac.stop().unwrap();
+    assert_eq!(DacState::Stopped, dac.get_state());
+
+    let writes = &dac.get_transport().control_writes;
+    assert_eq!(vec![vec![0x04, 0], vec![0x05, 0], vec![0x02, 1],
+        vec![0x01, 0]], *writes);
+  }
+
+  #[test]
+  fn test_write_frame_polls_status() {
+    let mut transport = MockTransport::new();
+    transport.busy_polls = 3;
+    let mut dac = Dac::new(transport);
+    assert_eq!(DacState::Idle, dac.get_state());
+
+    let frame = Frame::new(vec![point(0, 0, false)]);
+    dac.write_frame(&frame, 20_000, 0).unwrap();
+    assert_eq!(DacState::Playing, dac.get_state());
+
+    let transport = dac.get_transport();
+    let polls = transport.control_writes.iter()
+        .filter(|write| write[0] == CONTROL_GET_STATUS)
+        .count();
+    assert_eq!(4, polls);
+    assert_eq!(1, transport.bulk_writes.len());
+
+    // The DAC never becomes ready.
+    let mut transport = MockTransport::new();
+    transport.busy_polls = 10;
+    let mut dac = Dac::new(transport);
+    dac.set_status_attempts(5);
+    assert!(dac.write_frame(&frame, 20_000, 0).is_err());
+    assert!(dac.get_transport().bulk_writes.is_empty());
+  }
+
+  #[test]
+  fn test_play_animation() {
+    let animation = Animation::new(vec![
+      Frame::new(vec![point(-32768, 0, false), point(32767, 0, true)]),
+      Frame::new(Vec::new()),
+      Frame::new(vec![point(0, 32767, false)]),
+    ]);
+
+    let mut dac = Dac::new(MockTransport::new());
+    dac.play_animation(&animation, 30_000).unwrap();
+
+    let frames = dac.get_transport().frames().unwrap();
+    assert_eq!(2, frames.len());
+
+    let (ref points, rate, flags) = frames[0];
+    assert_eq!(30_000, rate);
+    assert_eq!(FLAG_SINGLE_MODE, flags);
+    assert_eq!(HeliosPoint { x: 0, y: 0x800, r: 255, g: 100, b: 0, i: 255 },
+        points[0]);
+    assert_eq!(HeliosPoint { x: 0xfff, y: 0x800, ..HeliosPoint::default() },
+        points[1]);
+
+    assert_eq!(0xfff, frames[1].0[0].y);
+  }
+}
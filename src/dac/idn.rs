@@ -0,0 +1,1063 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! ILDA Digital Network (IDN) streaming over UDP.
+//!
+//! Every IDN packet begins with an IDN-Hello header naming the command.
+//! Service discovery uses scan requests and responses; point data is carried
+//! in realtime channel messages. A channel message may include a channel
+//! configuration, which describes the layout of each sample, followed by a
+//! chunk of samples. In frame mode a chunk holds a complete frame that is
+//! scanned repeatedly until the next one arrives; in wave mode chunks are
+//! played back to back as a continuous stream. All multi-byte fields are
+//! big endian.
+//!
+//! A loopback `Receiver` decodes messages back into points so the stack can be
+//! verified on a single machine.
+
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::time::Instant;
+
+/// UDP port IDN devices listen on.
+pub const PORT : u16 = 7255;
+
+/// Size of the IDN-Hello packet header in bytes.
+pub const PACKET_HEADER_SIZE : usize = 4;
+/// Size of a channel message header in bytes.
+pub const CHANNEL_MESSAGE_HEADER_SIZE : usize = 8;
+/// Size of a channel configuration header in bytes, excluding descriptors.
+pub const CHANNEL_CONFIG_HEADER_SIZE : usize = 4;
+/// Size of a sample chunk header in bytes.
+pub const SAMPLE_CHUNK_HEADER_SIZE : usize = 4;
+/// Size of a scan response body in bytes.
+pub const SCAN_RESPONSE_SIZE : usize = 40;
+
+/// Largest channel message that fits in a single UDP datagram.
+pub const MAX_MESSAGE_SIZE : usize = 0xffff - 8 - 20 - PACKET_HEADER_SIZE;
+
+/// Descriptor tags for 16-bit X and Y with 8-bit red (638nm), green (532nm)
+/// and blue (460nm), padded to a whole number of 32-bit words.
+pub const XYRGB_DESCRIPTORS : [u16; 8] =
+    [0x4200, 0x4010, 0x4210, 0x4010, 0x527e, 0x5214, 0x51cc, 0x0000];
+
+/// Size in bytes of a sample described by `XYRGB_DESCRIPTORS`.
+pub const XYRGB_SAMPLE_SIZE : usize = 7;
+
+const CONTENT_CHANNEL_MESSAGE : u16 = 0x8000;
+const CONTENT_CONFIG_FLAG : u16 = 0x4000;
+const CONTENT_CHANNEL_MASK : u16 = 0x3f00;
+const CONTENT_CHUNK_MASK : u16 = 0x00ff;
+
+const CONFIG_FLAG_ROUTING : u8 = 0x01;
+const CONFIG_FLAG_CLOSE : u8 = 0x02;
+
+const CHUNK_FLAG_ONCE : u8 = 0x01;
+const MAX_DURATION : u32 = 0x00ff_ffff;
+
+/// IDN-Hello commands.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacketCommand {
+  Void,
+  ScanRequest,
+  ScanResponse,
+  ServiceMapRequest,
+  ServiceMapResponse,
+  ChannelMessage,
+  ChannelMessageAckRequest,
+  ChannelClose,
+  ChannelCloseAckRequest,
+  Acknowledge,
+  Unknown(u8),
+}
+
+/// The IDN-Hello header that starts every packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PacketHeader {
+  /// The packet command.
+  pub command: PacketCommand,
+  /// Command flags.
+  pub flags: u8,
+  /// Sequence number, echoed in responses.
+  pub sequence: u16,
+}
+
+/// A device's answer to a scan request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanResponse {
+  /// Protocol version; major in the high nibble, minor in the low.
+  pub protocol_version: u8,
+  /// Unit status flags.
+  pub status: u8,
+  /// Unique unit identifier.
+  pub unit_id: [u8; 16],
+  /// Human readable host name.
+  pub host_name: String,
+}
+
+/// How a service treats sample chunks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServiceMode {
+  /// Chunks are played back to back as one continuous stream.
+  Wave,
+  /// Each chunk is a complete frame, scanned until the next one arrives.
+  Frame,
+}
+
+/// Channel configuration describing the layout of each sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelConfig {
+  /// Service (output) the channel is routed to.
+  pub service_id: u8,
+  /// How sample chunks are interpreted.
+  pub service_mode: ServiceMode,
+  /// Sample layout descriptor tags.
+  pub descriptors: Vec<u16>,
+  /// Whether the channel is being closed.
+  pub close: bool,
+}
+
+/// A realtime channel message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelMessage {
+  /// Channel number, `0` to `63`.
+  pub channel_id: u8,
+  /// Sender timestamp in microseconds.
+  pub timestamp: u32,
+  /// Configuration, sent with the first message and periodically after.
+  pub config: Option<ChannelConfig>,
+  /// Samples carried by the message, if any.
+  pub chunk: Option<SampleChunk>,
+}
+
+/// A chunk of laser samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleChunk {
+  /// Mode the samples are meant for, which sets the chunk type.
+  pub service_mode: ServiceMode,
+  /// Chunk flags.
+  pub flags: u8,
+  /// How long the chunk takes to scan, in microseconds.
+  pub duration: u32,
+  /// Encoded samples.
+  pub samples: Vec<u8>,
+}
+
+/// Turns frames into channel messages.
+#[derive(Clone, Debug)]
+pub struct Encoder {
+  channel_id: u8,
+  config: ChannelConfig,
+  max_samples: usize,
+  config_interval: usize,
+  messages_since_config: Option<usize>,
+}
+
+/// Sends frames to an IDN device.
+pub struct Sender {
+  socket: UdpSocket,
+  target: SocketAddr,
+  encoder: Encoder,
+  sequence: u16,
+  start: Instant,
+}
+
+/// Receives and decodes IDN packets, answering scan requests.
+pub struct Receiver {
+  socket: UdpSocket,
+  unit_id: [u8; 16],
+  host_name: String,
+  configs: HashMap<u8, ChannelConfig>,
+}
+
+/// Something a `Receiver` got off the wire.
+#[derive(Clone, Debug)]
+pub enum Received {
+  /// A scan request, which has been answered.
+  Scan(SocketAddr),
+  /// Points decoded from a channel message.
+  Points {
+    /// Channel the points arrived on.
+    channel_id: u8,
+    /// Service mode of the channel.
+    service_mode: ServiceMode,
+    /// Scan duration in microseconds.
+    duration: u32,
+    /// Decoded points.
+    points: Vec<SimplePoint>,
+  },
+  /// A channel was closed.
+  Close(u8),
+  /// Any other packet.
+  Other(PacketHeader),
+}
+
+impl PacketCommand {
+  fn from_u8(value: u8) -> PacketCommand {
+    match value {
+      0x00 => PacketCommand::Void,
+      0x10 => PacketCommand::ScanRequest,
+      0x11 => PacketCommand::ScanResponse,
+      0x12 => PacketCommand::ServiceMapRequest,
+      0x13 => PacketCommand::ServiceMapResponse,
+      0x40 => PacketCommand::ChannelMessage,
+      0x41 => PacketCommand::ChannelMessageAckRequest,
+      0x44 => PacketCommand::ChannelClose,
+      0x45 => PacketCommand::ChannelCloseAckRequest,
+      0x47 => PacketCommand::Acknowledge,
+      other => PacketCommand::Unknown(other),
+    }
+  }
+
+  fn to_u8(self) -> u8 {
+    match self {
+      PacketCommand::Void => 0x00,
+      PacketCommand::ScanRequest => 0x10,
+      PacketCommand::ScanResponse => 0x11,
+      PacketCommand::ServiceMapRequest => 0x12,
+      PacketCommand::ServiceMapResponse => 0x13,
+      PacketCommand::ChannelMessage => 0x40,
+      PacketCommand::ChannelMessageAckRequest => 0x41,
+      PacketCommand::ChannelClose => 0x44,
+      PacketCommand::ChannelCloseAckRequest => 0x45,
+      PacketCommand::Acknowledge => 0x47,
+      PacketCommand::Unknown(other) => other,
+    }
+  }
+}
+
+impl PacketHeader {
+  /// Create a header with no flags.
+  pub fn new(command: PacketCommand, sequence: u16) -> PacketHeader {
+    PacketHeader { command, flags: 0, sequence }
+  }
+
+  /// Read a packet header from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<PacketHeader, IldaError> {
+    if bytes.len() < PACKET_HEADER_SIZE {
+      return Err(IldaError::InvalidHeader);
+    }
+
+    Ok(PacketHeader {
+      command: PacketCommand::from_u8(bytes[0]),
+      flags: bytes[1],
+      sequence: read_u16(&bytes[2..4]),
+    })
+  }
+
+  /// Serialize the packet header.
+  pub fn to_bytes(&self) -> [u8; PACKET_HEADER_SIZE] {
+    let sequence = self.sequence.to_be_bytes();
+    [self.command.to_u8(), self.flags, sequence[0], sequence[1]]
+  }
+}
+
+impl ScanResponse {
+  /// Read a scan response body from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<ScanResponse, IldaError> {
+    if bytes.len() < SCAN_RESPONSE_SIZE
+        || bytes[0] as usize != SCAN_RESPONSE_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    let mut unit_id = [0u8; 16];
+    unit_id.copy_from_slice(&bytes[4..20]);
+
+    let host_name = bytes[20..40].iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect();
+
+    Ok(ScanResponse {
+      protocol_version: bytes[1],
+      status: bytes[2],
+      unit_id,
+      host_name,
+    })
+  }
+
+  /// Serialize the scan response body. Host names are truncated to 20 bytes.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = vec![SCAN_RESPONSE_SIZE as u8, self.protocol_version,
+        self.status, 0];
+    out.extend_from_slice(&self.unit_id);
+    let mut name = [0u8; 20];
+    for (slot, byte) in name.iter_mut().zip(self.host_name.bytes()) {
+      *slot = byte;
+    }
+    out.extend_from_slice(&name);
+    out
+  }
+}
+
+impl ServiceMode {
+  fn to_u8(self) -> u8 {
+    match self {
+      ServiceMode::Wave => 0x01,
+      ServiceMode::Frame => 0x02,
+    }
+  }
+
+  fn from_u8(value: u8) -> Result<ServiceMode, IldaError> {
+    match value {
+      0x01 => Ok(ServiceMode::Wave),
+      0x02 => Ok(ServiceMode::Frame),
+      _ => Err(IldaError::Unsupported),
+    }
+  }
+
+  // Mode of a chunk type. Only wave samples have a chunk type of their own;
+  // the rest are complete or fragmented frames.
+  fn from_chunk_type(chunk_type: u8) -> ServiceMode {
+    match chunk_type {
+      0x01 => ServiceMode::Wave,
+      _ => ServiceMode::Frame,
+    }
+  }
+
+  // Chunk type for a complete sample chunk in this mode.
+  fn chunk_type(self) -> u8 {
+    match self {
+      ServiceMode::Wave => 0x01,
+      ServiceMode::Frame => 0x02,
+    }
+  }
+}
+
+impl ChannelConfig {
+  /// Configuration for 16-bit XY and 8-bit RGB samples on the first service.
+  pub fn xyrgb(service_mode: ServiceMode) -> ChannelConfig {
+    ChannelConfig {
+      service_id: 0,
+      service_mode,
+      descriptors: XYRGB_DESCRIPTORS.to_vec(),
+      close: false,
+    }
+  }
+
+  /// Size of a sample described by this configuration, or `None` if the
+  /// layout isn't one this library can encode.
+  pub fn sample_size(&self) -> Option<usize> {
+    if self.descriptors == XYRGB_DESCRIPTORS {
+      Some(XYRGB_SAMPLE_SIZE)
+    } else {
+      None
+    }
+  }
+}
+
+impl ChannelMessage {
+  /// Read a channel message, excluding the packet header, from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<ChannelMessage, IldaError> {
+    if bytes.len() < CHANNEL_MESSAGE_HEADER_SIZE {
+      return Err(IldaError::InvalidHeader);
+    }
+
+    let total_size = read_u16(&bytes[0..2]) as usize;
+    let content_id = read_u16(&bytes[2..4]);
+    let timestamp = read_u32(&bytes[4..8]);
+
+    if total_size != bytes.len() || content_id & CONTENT_CHANNEL_MESSAGE == 0 {
+      return Err(IldaError::InvalidHeader);
+    }
+
+    let channel_id = ((content_id & CONTENT_CHANNEL_MASK) >> 8) as u8;
+    let chunk_type = (content_id & CONTENT_CHUNK_MASK) as u8;
+    let mut i = CHANNEL_MESSAGE_HEADER_SIZE;
+
+    let config = if content_id & CONTENT_CONFIG_FLAG != 0 {
+      if bytes.len() < i + CHANNEL_CONFIG_HEADER_SIZE {
+        return Err(IldaError::InvalidData);
+      }
+      let words = bytes[i] as usize;
+      let flags = bytes[i + 1];
+      let service_id = bytes[i + 2];
+      let service_mode = ServiceMode::from_u8(bytes[i + 3])?;
+      i += CHANNEL_CONFIG_HEADER_SIZE;
+
+      let end = i + words * 4;
+      if bytes.len() < end {
+        return Err(IldaError::InvalidData);
+      }
+      let descriptors = bytes[i..end].chunks(2).map(read_u16).collect();
+      i = end;
+
+      Some(ChannelConfig {
+        service_id,
+        service_mode,
+        descriptors,
+        close: flags & CONFIG_FLAG_CLOSE != 0,
+      })
+    } else {
+      None
+    };
+
+    let chunk = match chunk_type {
+      0x00 => None,
+      _ => {
+        if bytes.len() < i + SAMPLE_CHUNK_HEADER_SIZE {
+          return Err(IldaError::InvalidData);
+        }
+        let header = read_u32(&bytes[i .. i + SAMPLE_CHUNK_HEADER_SIZE]);
+        Some(SampleChunk {
+          service_mode: ServiceMode::from_chunk_type(chunk_type),
+          flags: (header >> 24) as u8,
+          duration: header & MAX_DURATION,
+          samples: bytes[i + SAMPLE_CHUNK_HEADER_SIZE ..].to_vec(),
+        })
+      },
+    };
+
+    Ok(ChannelMessage { channel_id, timestamp, config, chunk })
+  }
+
+  /// Serialize the channel message, excluding the packet header.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut content_id = CONTENT_CHANNEL_MESSAGE
+        | (((self.channel_id as u16) << 8) & CONTENT_CHANNEL_MASK);
+
+    if self.config.is_some() {
+      content_id |= CONTENT_CONFIG_FLAG;
+    }
+
+    if let Some(ref chunk) = self.chunk {
+      content_id |= chunk.service_mode.chunk_type() as u16;
+    }
+
+    let mut out = vec![0, 0];
+    out.extend_from_slice(&content_id.to_be_bytes());
+    out.extend_from_slice(&self.timestamp.to_be_bytes());
+
+    if let Some(ref config) = self.config {
+      let words = (config.descriptors.len() + 1) / 2;
+      let mut flags = CONFIG_FLAG_ROUTING;
+      if config.close {
+        flags |= CONFIG_FLAG_CLOSE;
+      }
+      out.extend_from_slice(&[words as u8, flags, config.service_id,
+          config.service_mode.to_u8()]);
+      for descriptor in config.descriptors.iter() {
+        out.extend_from_slice(&descriptor.to_be_bytes());
+      }
+      if config.descriptors.len() % 2 == 1 {
+        out.extend_from_slice(&[0, 0]);
+      }
+    }
+
+    if let Some(ref chunk) = self.chunk {
+      let header = ((chunk.flags as u32) << 24)
+          | chunk.duration.min(MAX_DURATION);
+      out.extend_from_slice(&header.to_be_bytes());
+      out.extend_from_slice(&chunk.samples);
+    }
+
+    let total = (out.len() as u16).to_be_bytes();
+    out[0] = total[0];
+    out[1] = total[1];
+    out
+  }
+}
+
+/// Encode points as `XYRGB_DESCRIPTORS` samples. Blank points are sent with
+/// all colors off.
+pub fn encode_points(points: &[SimplePoint]) -> Vec<u8> {
+  let mut out = Vec::with_capacity(points.len() * XYRGB_SAMPLE_SIZE);
+  for point in points {
+    out.extend_from_slice(&point.x.to_be_bytes());
+    out.extend_from_slice(&point.y.to_be_bytes());
+    if point.is_blank {
+      out.extend_from_slice(&[0, 0, 0]);
+    } else {
+      out.extend_from_slice(&[point.r, point.g, point.b]);
+    }
+  }
+  out
+}
+
+/// Decode `XYRGB_DESCRIPTORS` samples. Points with all colors off are
+/// treated as blank.
+pub fn decode_points(samples: &[u8]) -> Result<Vec<SimplePoint>, IldaError> {
+  if samples.len() % XYRGB_SAMPLE_SIZE != 0 {
+    return Err(IldaError::InvalidData);
+  }
+
+  Ok(samples.chunks(XYRGB_SAMPLE_SIZE)
+      .map(|sample| SimplePoint {
+        x: read_u16(&sample[0..2]) as i16,
+        y: read_u16(&sample[2..4]) as i16,
+        r: sample[4],
+        g: sample[5],
+        b: sample[6],
+        is_blank: sample[4] == 0 && sample[5] == 0 && sample[6] == 0,
+      })
+      .collect())
+}
+
+impl Encoder {
+  /// Create an encoder for 16-bit XY and 8-bit RGB samples on the given
+  /// channel.
+  pub fn new(channel_id: u8, service_mode: ServiceMode) -> Encoder {
+    let mut encoder = Encoder {
+      channel_id: channel_id & 0x3f,
+      config: ChannelConfig::xyrgb(service_mode),
+      max_samples: 0,
+      config_interval: 50,
+      messages_since_config: None,
+    };
+    encoder.max_samples = encoder.message_capacity();
+    encoder
+  }
+
+  /// Limit the number of samples per message, eg. to stay under a network's
+  /// MTU. Only wave mode can split a frame across several messages.
+  pub fn set_max_samples(&mut self, max_samples: usize) {
+    self.max_samples = max_samples.clamp(1, self.message_capacity());
+  }
+
+  // The most samples that fit in one message along with its configuration.
+  fn message_capacity(&self) -> usize {
+    let overhead = CHANNEL_MESSAGE_HEADER_SIZE + CHANNEL_CONFIG_HEADER_SIZE
+        + self.config.descriptors.len() * 2 + SAMPLE_CHUNK_HEADER_SIZE;
+    (MAX_MESSAGE_SIZE - overhead) / XYRGB_SAMPLE_SIZE
+  }
+
+  /// Resend the channel configuration every `interval` messages.
+  pub fn set_config_interval(&mut self, interval: usize) {
+    self.config_interval = interval.max(1);
+  }
+
+  /// Encode a frame to be scanned over `duration` microseconds, starting at
+  /// `timestamp`. Frame mode yields exactly one message and fails with
+  /// `IldaError::Unsupported` if the frame doesn't fit in one; wave mode
+  /// splits the frame into as many messages as needed.
+  pub fn encode_frame(&mut self, frame: &Frame, duration: u32, timestamp: u32)
+      -> Result<Vec<ChannelMessage>, IldaError> {
+    let points = frame.get_points();
+
+    if points.is_empty() {
+      return Err(IldaError::NoData);
+    }
+
+    match self.config.service_mode {
+      ServiceMode::Frame => {
+        if points.len() > self.max_samples {
+          return Err(IldaError::Unsupported);
+        }
+        let chunk = SampleChunk {
+          service_mode: ServiceMode::Frame,
+          flags: 0,
+          duration,
+          samples: encode_points(points),
+        };
+        Ok(vec![self.message(timestamp, Some(chunk))])
+      },
+      ServiceMode::Wave => {
+        // Each chunk ends at its share of the duration, rounded down, so the
+        // rounding never accumulates and the chunks add up to `duration`.
+        let mut messages = Vec::new();
+        let mut sent = 0u64;
+        let mut elapsed = 0u64;
+        for batch in points.chunks(self.max_samples) {
+          sent += batch.len() as u64;
+          let end = duration as u64 * sent / points.len() as u64;
+          let chunk = SampleChunk {
+            service_mode: ServiceMode::Wave,
+            flags: 0,
+            duration: (end - elapsed) as u32,
+            samples: encode_points(batch),
+          };
+          let time = timestamp.wrapping_add(elapsed as u32);
+          messages.push(self.message(time, Some(chunk)));
+          elapsed = end;
+        }
+        Ok(messages)
+      },
+    }
+  }
+
+  /// Encode a message that scans a single frame exactly once, in frame mode.
+  pub fn encode_frame_once(&mut self, frame: &Frame, duration: u32,
+      timestamp: u32) -> Result<Vec<ChannelMessage>, IldaError> {
+    let mut messages = self.encode_frame(frame, duration, timestamp)?;
+    if self.config.service_mode == ServiceMode::Frame {
+      for message in messages.iter_mut() {
+        if let Some(ref mut chunk) = message.chunk {
+          chunk.flags |= CHUNK_FLAG_ONCE;
+        }
+      }
+    }
+    Ok(messages)
+  }
+
+  /// Encode a message closing the channel.
+  pub fn encode_close(&mut self, timestamp: u32) -> ChannelMessage {
+    let mut config = self.config.clone();
+    config.close = true;
+    self.messages_since_config = None;
+    ChannelMessage {
+      channel_id: self.channel_id,
+      timestamp,
+      config: Some(config),
+      chunk: None,
+    }
+  }
+
+  fn message(&mut self, timestamp: u32, chunk: Option<SampleChunk>)
+      -> ChannelMessage {
+    let config = match self.messages_since_config {
+      Some(count) if count + 1 < self.config_interval => {
+        self.messages_since_config = Some(count + 1);
+        None
+      },
+      _ => {
+        self.messages_since_config = Some(0);
+        Some(self.config.clone())
+      },
+    };
+
+    ChannelMessage {
+      channel_id: self.channel_id,
+      timestamp,
+      config,
+      chunk,
+    }
+  }
+}
+
+/// Broadcast a scan request and collect responses until `timeout` passes
+/// without a new one.
+pub fn scan<A: ToSocketAddrs>(socket: &UdpSocket, address: A,
+    timeout: Duration) -> Result<Vec<(SocketAddr, ScanResponse)>, IldaError> {
+  socket.set_broadcast(true)?;
+  socket.set_read_timeout(Some(timeout))?;
+  socket.send_to(&PacketHeader::new(PacketCommand::ScanRequest, 0).to_bytes(),
+      address)?;
+
+  let mut found = Vec::new();
+  let mut buffer = [0u8; 128];
+
+  loop {
+    let (len, from) = match socket.recv_from(&mut buffer) {
+      Ok(received) => received,
+      Err(ref e) if e.kind() == ErrorKind::WouldBlock
+          || e.kind() == ErrorKind::TimedOut => break,
+      Err(e) => return Err(e.into()),
+    };
+
+    // Other traffic on the port is skipped rather than ending the scan.
+    let is_response = PacketHeader::read_bytes(&buffer[..len])
+        .map(|header| header.command == PacketCommand::ScanResponse)
+        .unwrap_or(false);
+    if is_response {
+      if let Ok(response) =
+          ScanResponse::read_bytes(&buffer[PACKET_HEADER_SIZE..len]) {
+        found.push((from, response));
+      }
+    }
+  }
+
+  Ok(found)
+}
+
+impl Sender {
+  /// Create a sender on a fresh socket.
+  pub fn new<A: ToSocketAddrs>(target: A, encoder: Encoder)
+      -> Result<Sender, IldaError> {
+    let target = target.to_socket_addrs()?
+        .next()
+        .ok_or(IldaError::InvalidData)?;
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    Ok(Sender {
+      socket,
+      target,
+      encoder,
+      sequence: 0,
+      start: Instant::now(),
+    })
+  }
+
+  /// Send a frame to be scanned over the given duration.
+  pub fn send_frame(&mut self, frame: &Frame, duration: Duration)
+      -> Result<(), IldaError> {
+    let timestamp = self.timestamp();
+    let duration = duration.as_micros().min(MAX_DURATION as u128) as u32;
+    let messages = self.encoder.encode_frame(frame, duration, timestamp)?;
+    for message in messages {
+      self.send(PacketCommand::ChannelMessage, &message)?;
+    }
+    Ok(())
+  }
+
+  /// Close the channel.
+  pub fn close(&mut self) -> Result<(), IldaError> {
+    let message = self.encoder.encode_close(self.timestamp());
+    self.send(PacketCommand::ChannelClose, &message)
+  }
+
+  fn send(&mut self, command: PacketCommand, message: &ChannelMessage)
+      -> Result<(), IldaError> {
+    let mut packet = PacketHeader::new(command, self.sequence).to_bytes()
+        .to_vec();
+    packet.extend_from_slice(&message.to_bytes());
+    self.sequence = self.sequence.wrapping_add(1);
+    self.socket.send_to(&packet, self.target)?;
+    Ok(())
+  }
+
+  fn timestamp(&self) -> u32 {
+    self.start.elapsed().as_micros() as u32
+  }
+}
+
+impl Receiver {
+  /// Listen on the given address.
+  pub fn bind<A: ToSocketAddrs>(address: A, host_name: &str)
+      -> Result<Receiver, IldaError> {
+    Ok(Receiver {
+      socket: UdpSocket::bind(address)?,
+      unit_id: [0u8; 16],
+      host_name: host_name.to_string(),
+      configs: HashMap::new(),
+    })
+  }
+
+  /// The address the receiver is listening on.
+  pub fn local_addr(&self) -> Result<SocketAddr, IldaError> {
+    Ok(self.socket.local_addr()?)
+  }
+
+  /// Wait for the next packet and decode it. Scan requests are answered
+  /// automatically.
+  pub fn receive(&mut self, timeout: Duration) -> Result<Received, IldaError> {
+    self.socket.set_read_timeout(Some(timeout))?;
+
+    let mut buffer = vec![0u8; 0x10000];
+    let (len, from) = self.socket.recv_from(&mut buffer)?;
+    let header = PacketHeader::read_bytes(&buffer[..len])?;
+
+    match header.command {
+      PacketCommand::ScanRequest => {
+        let response = ScanResponse {
+          protocol_version: 0x10,
+          status: 0,
+          unit_id: self.unit_id,
+          host_name: self.host_name.clone(),
+        };
+        let mut packet =
+            PacketHeader::new(PacketCommand::ScanResponse, header.sequence)
+                .to_bytes().to_vec();
+        packet.extend_from_slice(&response.to_bytes());
+        self.socket.send_to(&packet, from)?;
+        Ok(Received::Scan(from))
+      },
+      PacketCommand::ChannelMessage
+          | PacketCommand::ChannelMessageAckRequest
+          | PacketCommand::ChannelClose
+          | PacketCommand::ChannelCloseAckRequest => {
+        let message =
+            ChannelMessage::read_bytes(&buffer[PACKET_HEADER_SIZE..len])?;
+        self.decode(message)
+      },
+      _ => Ok(Received::Other(header)),
+    }
+  }
+
+  fn decode(&mut self, message: ChannelMessage) -> Result<Received, IldaError> {
+    let channel_id = message.channel_id;
+
+    if let Some(config) = message.config {
+      if config.close {
+        self.configs.remove(&channel_id);
+        return Ok(Received::Close(channel_id));
+      }
+      self.configs.insert(channel_id, config);
+    }
+
+    // Samples can't be interpreted until the channel has been configured.
+    let config = self.configs.get(&channel_id).ok_or(IldaError::InvalidData)?;
+
+    let chunk = match message.chunk {
+      Some(chunk) => chunk,
+      None => return Ok(Received::Points {
+        channel_id,
+        service_mode: config.service_mode,
+        duration: 0,
+        points: Vec::new(),
+      }),
+    };
+
+    if config.sample_size().is_none() {
+      return Err(IldaError::Unsupported);
+    }
+
+    Ok(Received::Points {
+      channel_id,
+      service_mode: config.service_mode,
+      duration: chunk.duration,
+      points: decode_points(&chunk.samples)?,
+    })
+  }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+  ((bytes[0] as u16) << 8) | (bytes[1] as u16)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  ((bytes[0] as u32) << 24)
+      | ((bytes[1] as u32) << 16)
+      | ((bytes[2] as u32) << 8)
+      | (bytes[3] as u32)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  fn frame(count: usize) -> Frame {
+    Frame::new((0..count)
+        .map(|i| SimplePoint {
+          x: i as i16 * 50,
+          y: -(i as i16),
+          r: i as u8,
+          g: 255,
+          b: 0,
+          is_blank: false,
+        })
+        .collect())
+  }
+
+  fn receive_points(receiver: &mut Receiver)
+      -> (ServiceMode, Vec<SimplePoint>) {
+    match receiver.receive(Duration::from_secs(5)).unwrap() {
+      Received::Points { service_mode, points, .. } => (service_mode, points),
+      other => panic!("unexpected packet: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_packet_header() {
+    let header = PacketHeader::new(PacketCommand::ChannelMessage, 0x1234);
+    let bytes = header.to_bytes();
+    assert_eq!([0x40, 0x00, 0x12, 0x34], bytes);
+    assert_eq!(header, PacketHeader::read_bytes(&bytes).unwrap());
+
+    let unknown = PacketHeader::read_bytes(&[0x99, 0, 0, 0]).unwrap();
+    assert_eq!(PacketCommand::Unknown(0x99), unknown.command);
+    assert!(PacketHeader::read_bytes(&[0x40]).is_err());
+  }
+
+  #[test]
+  fn test_scan_response() {
+    let response = ScanResponse {
+      protocol_version: 0x10,
+      status: 1,
+      unit_id: [7u8; 16],
+      host_name: "projector".to_string(),
+    };
+    let bytes = response.to_bytes();
+    assert_eq!(SCAN_RESPONSE_SIZE, bytes.len());
+    assert_eq!(response, ScanResponse::read_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_channel_message_layout() {
+    let mut encoder = Encoder::new(3, ServiceMode::Frame);
+    let messages = encoder.encode_frame(&frame(2), 1000, 42).unwrap();
+    assert_eq!(1, messages.len());
+
+    let bytes = messages[0].to_bytes();
+    let expected_len = CHANNEL_MESSAGE_HEADER_SIZE + CHANNEL_CONFIG_HEADER_SIZE
+        + 16 + SAMPLE_CHUNK_HEADER_SIZE + 2 * XYRGB_SAMPLE_SIZE;
+    assert_eq!(expected_len, bytes.len());
+    assert_eq!(expected_len as u16, read_u16(&bytes[0..2]));
+    assert_eq!(0xc302, read_u16(&bytes[2..4])); // Message, config, ch 3, frame
+    assert_eq!(42, read_u32(&bytes[4..8]));
+    assert_eq!(&[4, CONFIG_FLAG_ROUTING, 0, 0x02], &bytes[8..12]);
+    assert_eq!(1000, read_u32(&bytes[28..32]));
+
+    assert_eq!(messages[0], ChannelMessage::read_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_config_interval() {
+    let mut encoder = Encoder::new(0, ServiceMode::Frame);
+    encoder.set_config_interval(2);
+
+    let configs: Vec<_> = (0..5)
+        .map(|_| encoder.encode_frame(&frame(1), 0, 0).unwrap())
+        .map(|messages| messages[0].config.is_some())
+        .collect();
+    assert_eq!(vec![true, false, true, false, true], configs);
+  }
+
+  #[test]
+  fn test_wave_mode_splits_frames() {
+    let mut encoder = Encoder::new(0, ServiceMode::Wave);
+    encoder.set_max_samples(4);
+
+    let messages = encoder.encode_frame(&frame(10), 1000, 0).unwrap();
+    assert_eq!(3, messages.len());
+    assert_eq!(vec![400, 400, 200], messages.iter()
+        .map(|message| message.chunk.as_ref().unwrap().duration)
+        .collect::<Vec<_>>());
+    assert_eq!(vec![0, 400, 800], messages.iter()
+        .map(|message| message.timestamp)
+        .collect::<Vec<_>>());
+
+    let messages = encoder.encode_frame(&frame(3), 1000, 0).unwrap();
+    assert_eq!(vec![1000], messages.iter()
+        .map(|message| message.chunk.as_ref().unwrap().duration)
+        .collect::<Vec<_>>());
+
+    // Durations that don't divide evenly still add up to the frame's.
+    encoder.set_max_samples(1);
+    let messages = encoder.encode_frame(&frame(3), 1000, 0).unwrap();
+    assert_eq!(vec![333, 333, 334], messages.iter()
+        .map(|message| message.chunk.as_ref().unwrap().duration)
+        .collect::<Vec<_>>());
+    assert_eq!(vec![0, 333, 666], messages.iter()
+        .map(|message| message.timestamp)
+        .collect::<Vec<_>>());
+
+    // Chunks sent without a config keep the wave chunk type.
+    assert!(messages[1].config.is_none());
+    assert_eq!(0x8001, read_u16(&messages[1].to_bytes()[2..4]));
+    assert_eq!(messages[1],
+        ChannelMessage::read_bytes(&messages[1].to_bytes()).unwrap());
+
+    // The limit can be raised again, up to what fits in a message.
+    encoder.set_max_samples(usize::MAX);
+    assert_eq!(Encoder::new(0, ServiceMode::Wave).max_samples,
+        encoder.max_samples);
+
+    let mut frame_encoder = Encoder::new(0, ServiceMode::Frame);
+    frame_encoder.set_max_samples(4);
+    assert!(frame_encoder.encode_frame(&frame(10), 1000, 0).is_err());
+  }
+
+  #[test]
+  fn test_points_round_trip() {
+    let mut points = frame(3).get_points().clone();
+    points[1].is_blank = true;
+
+    let decoded = decode_points(&encode_points(&points)).unwrap();
+    assert_eq!(3, decoded.len());
+    assert_eq!(points[2].x, decoded[2].x);
+    assert_eq!(points[2].y, decoded[2].y);
+    assert_eq!(points[2].r, decoded[2].r);
+    assert!(decoded[1].is_blank);
+    assert!(!decoded[0].is_blank);
+
+    assert!(decode_points(&[0u8; 5]).is_err());
+  }
+
+  #[test]
+  fn test_loopback_frame_mode() {
+    let mut receiver = Receiver::bind("127.0.0.1:0", "loopback").unwrap();
+    let address = receiver.local_addr().unwrap();
+
+    let encoder = Encoder::new(1, ServiceMode::Frame);
+    let mut sender = Sender::new(address, encoder).unwrap();
+    let sent = frame(500);
+    sender.send_frame(&sent, Duration::from_millis(20)).unwrap();
+
+    let (mode, points) = receive_points(&mut receiver);
+    assert_eq!(ServiceMode::Frame, mode);
+    assert_eq!(500, points.len());
+    assert_eq!(sent.get_point(499).unwrap().x, points[499].x);
+
+    sender.close().unwrap();
+    match receiver.receive(Duration::from_secs(5)).unwrap() {
+      Received::Close(1) => {},
+      other => panic!("unexpected packet: {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_loopback_wave_mode() {
+    let mut receiver = Receiver::bind("127.0.0.1:0", "loopback").unwrap();
+    let address = receiver.local_addr().unwrap();
+
+    let mut encoder = Encoder::new(0, ServiceMode::Wave);
+    encoder.set_max_samples(100);
+    encoder.set_config_interval(10);
+    let mut sender = Sender::new(address, encoder).unwrap();
+    sender.send_frame(&frame(250), Duration::from_millis(10)).unwrap();
+
+    let mut points = Vec::new();
+    for _ in 0..3 {
+      let (mode, mut batch) = receive_points(&mut receiver);
+      assert_eq!(ServiceMode::Wave, mode);
+      points.append(&mut batch);
+    }
+
+    let xs: Vec<_> = points.iter().map(|point| point.x).collect();
+    let expected: Vec<_> = frame(250).get_points().iter()
+        .map(|point| point.x)
+        .collect();
+    assert_eq!(expected, xs);
+  }
+
+  #[test]
+  fn test_loopback_scan() {
+    let mut receiver = Receiver::bind("127.0.0.1:0", "loopback").unwrap();
+    let address = receiver.local_addr().unwrap();
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(&PacketHeader::new(PacketCommand::ScanRequest, 9)
+        .to_bytes(), address).unwrap();
+
+    match receiver.receive(Duration::from_secs(5)).unwrap() {
+      Received::Scan(_) => {},
+      other => panic!("unexpected packet: {:?}", other),
+    }
+
+    let mut buffer = [0u8; 128];
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (len, _) = client.recv_from(&mut buffer).unwrap();
+    let header = PacketHeader::read_bytes(&buffer[..len]).unwrap();
+    assert_eq!(PacketCommand::ScanResponse, header.command);
+    assert_eq!(9, header.sequence);
+    let response =
+        ScanResponse::read_bytes(&buffer[PACKET_HEADER_SIZE..len]).unwrap();
+    assert_eq!("loopback", response.host_name);
+  }
+
+  #[test]
+  fn test_scan_skips_invalid_packets() {
+    let device = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = device.local_addr().unwrap();
+    let responder = thread::spawn(move || {
+      let mut buffer = [0u8; 128];
+      let (_, from) = device.recv_from(&mut buffer).unwrap();
+      device.send_to(&[0xff, 0x00], from).unwrap();
+      let response = ScanResponse {
+        protocol_version: 0x10,
+        status: 0,
+        unit_id: [7; 16],
+        host_name: "device".to_string(),
+      };
+      let mut packet = PacketHeader::new(PacketCommand::ScanResponse, 0)
+          .to_bytes().to_vec();
+      packet.extend_from_slice(&response.to_bytes());
+      device.send_to(&packet, from).unwrap();
+    });
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let found = scan(&client, address, Duration::from_millis(500)).unwrap();
+    responder.join().unwrap();
+
+    assert_eq!(1, found.len());
+    assert_eq!(address, found[0].0);
+    assert_eq!("device", found[0].1.host_name);
+  }
+}
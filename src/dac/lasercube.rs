@@ -0,0 +1,796 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! LaserCube (LaserDock) WiFi protocol.
+//!
+//! A LaserCube listens on two UDP ports: one for commands (device info, scan
+//! rate, output enable, ring buffer control) and one for sample data. Sample
+//! packets carry up to `MAX_SAMPLES_PER_PACKET` points with 12-bit X and Y and
+//! 8-bit colors. Once enabled, the device answers every sample packet with the
+//! number of free slots left in its ring buffer, which is used for flow
+//! control. All multi-byte fields are little endian.
+//!
+//! A `StandIn` device speaks the same protocol on localhost so packet framing
+//! and flow control can be tested without hardware.
+
+use animation::Animation;
+use error::IldaError;
+use point::SimplePoint;
+use source::AnimationSource;
+use source::PointSource;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// UDP port for commands.
+pub const COMMAND_PORT : u16 = 45457;
+/// UDP port for sample data.
+pub const DATA_PORT : u16 = 45458;
+
+/// Most samples that fit in one data packet.
+pub const MAX_SAMPLES_PER_PACKET : usize = 140;
+/// Size of a single encoded sample in bytes.
+pub const SAMPLE_SIZE : usize = 8;
+/// Size of a sample packet header in bytes.
+pub const SAMPLE_HEADER_SIZE : usize = 4;
+/// Size of a device info response in bytes.
+pub const INFO_SIZE : usize = 24;
+/// Largest coordinate value.
+pub const MAX_COORDINATE : u16 = 0x0fff;
+
+const CMD_GET_FULL_INFO : u8 = 0x77;
+const CMD_ENABLE_BUFFER_SIZE_RESPONSE : u8 = 0x78;
+const CMD_SET_OUTPUT : u8 = 0x80;
+const CMD_SET_RATE : u8 = 0x82;
+const CMD_GET_RINGBUFFER_FREE : u8 = 0x8a;
+const CMD_CLEAR_RINGBUFFER : u8 = 0x8d;
+const CMD_SAMPLE_DATA : u8 = 0xa9;
+
+/// A point in the device's native resolution.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[allow(missing_docs)]
+pub struct Sample {
+  pub x: u16,
+  pub y: u16,
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+}
+
+/// Messages sent to the command port.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+  /// Request a `DeviceInfo`.
+  GetFullInfo,
+  /// Whether to answer sample packets with the ring buffer's free space.
+  EnableBufferSizeResponse(bool),
+  /// Turn laser output on or off.
+  SetOutput(bool),
+  /// Set the scan rate in points per second.
+  SetRate(u32),
+  /// Ask for the ring buffer's free space.
+  GetRingBufferFree,
+  /// Discard everything in the ring buffer.
+  ClearRingBuffer,
+}
+
+/// Device description returned by `Command::GetFullInfo`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+  /// Firmware major version.
+  pub firmware_major: u8,
+  /// Firmware minor version.
+  pub firmware_minor: u8,
+  /// Whether laser output is enabled.
+  pub output_enabled: bool,
+  /// Current scan rate in points per second.
+  pub rate: u32,
+  /// Fastest supported scan rate in points per second.
+  pub max_rate: u32,
+  /// Free slots in the ring buffer.
+  pub buffer_free: u16,
+  /// Total size of the ring buffer.
+  pub buffer_capacity: u16,
+  /// Device serial number.
+  pub serial_number: [u8; 6],
+}
+
+/// A decoded sample packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SamplePacket {
+  /// Wrapping packet counter.
+  pub message_number: u8,
+  /// Wrapping frame counter.
+  pub frame_number: u8,
+  /// Samples to append to the ring buffer.
+  pub samples: Vec<Sample>,
+}
+
+/// Streams samples to a LaserCube.
+pub struct Sender {
+  command_socket: UdpSocket,
+  data_socket: UdpSocket,
+  command_address: SocketAddr,
+  data_address: SocketAddr,
+  info: DeviceInfo,
+  buffer_free: usize,
+  // Sample packets sent whose buffer feedback hasn't arrived yet.
+  unanswered: usize,
+  message_number: u8,
+  frame_number: u8,
+}
+
+/// A stand-in LaserCube listening on localhost.
+pub struct StandIn {
+  command_address: SocketAddr,
+  data_address: SocketAddr,
+  state: Arc<Mutex<StandInState>>,
+  running: Arc<AtomicBool>,
+}
+
+impl<'a> From<&'a SimplePoint> for Sample {
+  fn from(point: &'a SimplePoint) -> Sample {
+    let x = ((point.x as i32 + 32768) >> 4) as u16;
+    let y = ((point.y as i32 + 32768) >> 4) as u16;
+
+    if point.is_blank {
+      return Sample { x, y, ..Sample::default() };
+    }
+
+    Sample { x, y, r: point.r, g: point.g, b: point.b }
+  }
+}
+
+impl Sample {
+  /// Serialize the sample.
+  pub fn to_bytes(&self) -> [u8; SAMPLE_SIZE] {
+    let x = self.x.min(MAX_COORDINATE).to_le_bytes();
+    let y = self.y.min(MAX_COORDINATE).to_le_bytes();
+    [self.r, self.g, self.b, 0, x[0], x[1], y[0], y[1]]
+  }
+
+  /// Read a sample from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<Sample, IldaError> {
+    if bytes.len() != SAMPLE_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    Ok(Sample {
+      x: read_u16(&bytes[4..6]),
+      y: read_u16(&bytes[6..8]),
+      r: bytes[0],
+      g: bytes[1],
+      b: bytes[2],
+    })
+  }
+}
+
+impl Command {
+  /// Serialize the command.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    match *self {
+      Command::GetFullInfo => vec![CMD_GET_FULL_INFO],
+      Command::EnableBufferSizeResponse(enable) =>
+          vec![CMD_ENABLE_BUFFER_SIZE_RESPONSE, enable as u8],
+      Command::SetOutput(enable) => vec![CMD_SET_OUTPUT, enable as u8],
+      Command::SetRate(rate) => {
+        let mut out = vec![CMD_SET_RATE];
+        out.extend_from_slice(&rate.to_le_bytes());
+        out
+      },
+      Command::GetRingBufferFree => vec![CMD_GET_RINGBUFFER_FREE],
+      Command::ClearRingBuffer => vec![CMD_CLEAR_RINGBUFFER],
+    }
+  }
+
+  /// Read a command from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<Command, IldaError> {
+    let code = *bytes.first().ok_or(IldaError::InvalidData)?;
+    let flag = || bytes.get(1).map(|&byte| byte != 0)
+        .ok_or(IldaError::InvalidData);
+
+    match code {
+      CMD_GET_FULL_INFO => Ok(Command::GetFullInfo),
+      CMD_ENABLE_BUFFER_SIZE_RESPONSE =>
+          Ok(Command::EnableBufferSizeResponse(flag()?)),
+      CMD_SET_OUTPUT => Ok(Command::SetOutput(flag()?)),
+      CMD_SET_RATE if bytes.len() >= 5 =>
+          Ok(Command::SetRate(read_u32(&bytes[1..5]))),
+      CMD_GET_RINGBUFFER_FREE => Ok(Command::GetRingBufferFree),
+      CMD_CLEAR_RINGBUFFER => Ok(Command::ClearRingBuffer),
+      _ => Err(IldaError::InvalidData),
+    }
+  }
+}
+
+impl DeviceInfo {
+  /// Read a device info response from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<DeviceInfo, IldaError> {
+    if bytes.len() < INFO_SIZE || bytes[0] != CMD_GET_FULL_INFO {
+      return Err(IldaError::InvalidData);
+    }
+
+    let mut serial_number = [0u8; 6];
+    serial_number.copy_from_slice(&bytes[18..24]);
+
+    Ok(DeviceInfo {
+      firmware_major: bytes[2],
+      firmware_minor: bytes[3],
+      output_enabled: bytes[4] != 0,
+      rate: read_u32(&bytes[6..10]),
+      max_rate: read_u32(&bytes[10..14]),
+      buffer_free: read_u16(&bytes[14..16]),
+      buffer_capacity: read_u16(&bytes[16..18]),
+      serial_number,
+    })
+  }
+
+  /// Serialize the device info response.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = vec![CMD_GET_FULL_INFO, 0, self.firmware_major,
+        self.firmware_minor, self.output_enabled as u8, 0];
+    out.extend_from_slice(&self.rate.to_le_bytes());
+    out.extend_from_slice(&self.max_rate.to_le_bytes());
+    out.extend_from_slice(&self.buffer_free.to_le_bytes());
+    out.extend_from_slice(&self.buffer_capacity.to_le_bytes());
+    out.extend_from_slice(&self.serial_number);
+    out
+  }
+}
+
+impl SamplePacket {
+  /// Serialize the packet. Fails if there are too many samples for one
+  /// packet.
+  pub fn to_bytes(&self) -> Result<Vec<u8>, IldaError> {
+    if self.samples.len() > MAX_SAMPLES_PER_PACKET {
+      return Err(IldaError::Unsupported);
+    }
+
+    let mut out = Vec::with_capacity(SAMPLE_HEADER_SIZE
+        + self.samples.len() * SAMPLE_SIZE);
+    out.extend_from_slice(&[CMD_SAMPLE_DATA, 0, self.message_number,
+        self.frame_number]);
+    for sample in self.samples.iter() {
+      out.extend_from_slice(&sample.to_bytes());
+    }
+    Ok(out)
+  }
+
+  /// Read a sample packet from raw bytes.
+  pub fn read_bytes(bytes: &[u8]) -> Result<SamplePacket, IldaError> {
+    if bytes.len() < SAMPLE_HEADER_SIZE || bytes[0] != CMD_SAMPLE_DATA
+        || (bytes.len() - SAMPLE_HEADER_SIZE) % SAMPLE_SIZE != 0 {
+      return Err(IldaError::InvalidData);
+    }
+
+    let samples = bytes[SAMPLE_HEADER_SIZE..].chunks(SAMPLE_SIZE)
+        .map(Sample::read_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(SamplePacket {
+      message_number: bytes[2],
+      frame_number: bytes[3],
+      samples,
+    })
+  }
+}
+
+// Read a ring buffer free space response.
+fn read_buffer_free(bytes: &[u8]) -> Option<u16> {
+  if bytes.len() >= 4 && bytes[0] == CMD_GET_RINGBUFFER_FREE {
+    Some(read_u16(&bytes[2..4]))
+  } else {
+    None
+  }
+}
+
+fn buffer_free_response(free: u16) -> Vec<u8> {
+  let free = free.to_le_bytes();
+  vec![CMD_GET_RINGBUFFER_FREE, 0, free[0], free[1]]
+}
+
+impl Sender {
+  /// Connect to a LaserCube at the standard ports.
+  pub fn connect(ip: IpAddr) -> Result<Sender, IldaError> {
+    Sender::connect_ports(SocketAddr::new(ip, COMMAND_PORT),
+        SocketAddr::new(ip, DATA_PORT))
+  }
+
+  /// Connect to a LaserCube at explicit command and data addresses. The
+  /// device is queried for its info and asked to report buffer space after
+  /// every sample packet.
+  pub fn connect_ports(command_address: SocketAddr, data_address: SocketAddr)
+      -> Result<Sender, IldaError> {
+    let bind = if command_address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let command_socket = UdpSocket::bind(bind)?;
+    let data_socket = UdpSocket::bind(bind)?;
+    command_socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    data_socket.set_read_timeout(Some(Duration::from_millis(1)))?;
+
+    command_socket.send_to(&Command::GetFullInfo.to_bytes(), command_address)?;
+    let mut buffer = [0u8; 64];
+    let (len, _) = command_socket.recv_from(&mut buffer)?;
+    let info = DeviceInfo::read_bytes(&buffer[..len])?;
+
+    let mut sender = Sender {
+      command_socket,
+      data_socket,
+      command_address,
+      data_address,
+      buffer_free: info.buffer_free as usize,
+      info,
+      unanswered: 0,
+      message_number: 0,
+      frame_number: 0,
+    };
+
+    sender.send_command(Command::EnableBufferSizeResponse(true))?;
+    Ok(sender)
+  }
+
+  /// The device info read when connecting.
+  pub fn get_info(&self) -> &DeviceInfo {
+    &self.info
+  }
+
+  /// Send a command that has no response.
+  pub fn send_command(&mut self, command: Command) -> Result<(), IldaError> {
+    self.command_socket.send_to(&command.to_bytes(), self.command_address)?;
+    Ok(())
+  }
+
+  /// Set the scan rate, limited to the device's maximum.
+  pub fn set_rate(&mut self, rate: u32) -> Result<(), IldaError> {
+    let rate = rate.clamp(1, self.info.max_rate.max(1));
+    self.info.rate = rate;
+    self.send_command(Command::SetRate(rate))
+  }
+
+  /// Turn laser output on or off.
+  pub fn set_output(&mut self, enable: bool) -> Result<(), IldaError> {
+    self.info.output_enabled = enable;
+    self.send_command(Command::SetOutput(enable))
+  }
+
+  /// Ask the device how much of its ring buffer is free. The answer
+  /// replaces any feedback still outstanding for earlier packets.
+  pub fn poll_buffer_free(&mut self) -> Result<usize, IldaError> {
+    self.send_command(Command::GetRingBufferFree)?;
+    let mut buffer = [0u8; 64];
+    loop {
+      let (len, _) = self.command_socket.recv_from(&mut buffer)?;
+      if let Some(free) = read_buffer_free(&buffer[..len]) {
+        self.buffer_free = free as usize;
+        self.unanswered = 0;
+        return Ok(self.buffer_free);
+      }
+    }
+  }
+
+  /// Stream an animation once at the given scan rate.
+  pub fn play_animation(&mut self, animation: &Animation, rate: u32)
+      -> Result<(), IldaError> {
+    let mut source = AnimationSource::new(animation.clone());
+    self.play(&mut source, rate)
+  }
+
+  /// Stream points from a source until it is exhausted, never sending more
+  /// samples than the device has reported room for.
+  pub fn play<S: PointSource>(&mut self, source: &mut S, rate: u32)
+      -> Result<(), IldaError> {
+    self.set_rate(rate)?;
+    self.set_output(true)?;
+    let rate = self.info.rate;
+
+    loop {
+      self.read_feedback();
+
+      let batch = MAX_SAMPLES_PER_PACKET.min(self.buffer_free);
+      if batch < MAX_SAMPLES_PER_PACKET / 2 {
+        // Wait for roughly one packet's worth to drain, then ask directly in
+        // case feedback packets were lost.
+        let wait = MAX_SAMPLES_PER_PACKET as u64 * 1_000_000 / rate as u64;
+        thread::sleep(Duration::from_micros(wait));
+        self.poll_buffer_free()?;
+        continue;
+      }
+
+      let points = source.next_points(batch, rate);
+      if points.is_empty() {
+        return Ok(());
+      }
+
+      let finished = points.len() < batch;
+      let packet = SamplePacket {
+        message_number: self.message_number,
+        frame_number: self.frame_number,
+        samples: points.iter().map(Sample::from).collect(),
+      };
+
+      self.data_socket.send_to(&packet.to_bytes()?, self.data_address)?;
+      self.message_number = self.message_number.wrapping_add(1);
+      self.buffer_free -= packet.samples.len();
+      self.unanswered += 1;
+
+      if finished {
+        self.frame_number = self.frame_number.wrapping_add(1);
+        return Ok(());
+      }
+    }
+  }
+
+  // Take the latest buffer feedback from the data socket, if any arrived.
+  fn read_feedback(&mut self) {
+    let mut buffer = [0u8; 64];
+    while let Ok((len, _)) = self.data_socket.recv_from(&mut buffer) {
+      if let Some(free) = read_buffer_free(&buffer[..len]) {
+        self.take_feedback(free as usize);
+      }
+    }
+  }
+
+  // Feedback doesn't say which packet it answers, and replies to earlier
+  // packets don't count the samples sent since. Until the reply to the
+  // latest packet arrives, only let feedback lower the local estimate.
+  fn take_feedback(&mut self, free: usize) {
+    self.unanswered = self.unanswered.saturating_sub(1);
+    if self.unanswered == 0 {
+      self.buffer_free = free;
+    } else {
+      self.buffer_free = self.buffer_free.min(free);
+    }
+  }
+}
+
+struct StandInState {
+  info: DeviceInfo,
+  buffered: usize,
+  received: Vec<Sample>,
+  overflowed: usize,
+  feedback: bool,
+  last_update: Instant,
+}
+
+impl StandInState {
+  // Drain the ring buffer at the current rate.
+  fn update(&mut self) {
+    let now = Instant::now();
+    if !self.info.output_enabled || self.info.rate == 0 {
+      self.last_update = now;
+      return;
+    }
+
+    let elapsed = now.duration_since(self.last_update).as_micros();
+    let due = (elapsed * self.info.rate as u128 / 1_000_000) as usize;
+    if due > 0 {
+      let used = due as u128 * 1_000_000 / self.info.rate as u128;
+      self.last_update += Duration::from_micros(used as u64);
+      self.buffered = self.buffered.saturating_sub(due);
+    }
+
+    let capacity = self.info.buffer_capacity as usize;
+    self.info.buffer_free = capacity.saturating_sub(self.buffered) as u16;
+  }
+}
+
+impl StandIn {
+  /// Start a stand-in with the given ring buffer size, listening on ephemeral
+  /// localhost ports.
+  pub fn start(buffer_capacity: u16, max_rate: u32)
+      -> Result<StandIn, IldaError> {
+    let command_socket = UdpSocket::bind("127.0.0.1:0")?;
+    let data_socket = UdpSocket::bind("127.0.0.1:0")?;
+    command_socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+    data_socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+
+    let state = Arc::new(Mutex::new(StandInState {
+      info: DeviceInfo {
+        firmware_major: 0,
+        firmware_minor: 13,
+        output_enabled: false,
+        rate: 30_000,
+        max_rate,
+        buffer_free: buffer_capacity,
+        buffer_capacity,
+        serial_number: [0x4c, 0x43, 0, 0, 0, 1],
+      },
+      buffered: 0,
+      received: Vec::new(),
+      overflowed: 0,
+      feedback: false,
+      last_update: Instant::now(),
+    }));
+
+    let stand_in = StandIn {
+      command_address: command_socket.local_addr()?,
+      data_address: data_socket.local_addr()?,
+      state,
+      running: Arc::new(AtomicBool::new(true)),
+    };
+
+    let (state, running) = (stand_in.state.clone(), stand_in.running.clone());
+    thread::spawn(move || StandIn::serve_commands(command_socket, state,
+        running));
+
+    let (state, running) = (stand_in.state.clone(), stand_in.running.clone());
+    thread::spawn(move || StandIn::serve_data(data_socket, state, running));
+
+    Ok(stand_in)
+  }
+
+  /// The address commands are accepted on.
+  pub fn command_address(&self) -> SocketAddr {
+    self.command_address
+  }
+
+  /// The address sample packets are accepted on.
+  pub fn data_address(&self) -> SocketAddr {
+    self.data_address
+  }
+
+  /// The device's current info.
+  pub fn info(&self) -> DeviceInfo {
+    let mut state = self.lock();
+    state.update();
+    state.info.clone()
+  }
+
+  /// Every sample accepted into the ring buffer, in order.
+  pub fn received_samples(&self) -> Vec<Sample> {
+    self.lock().received.clone()
+  }
+
+  /// Number of samples dropped because the ring buffer was full.
+  pub fn overflow_count(&self) -> usize {
+    self.lock().overflowed
+  }
+
+  fn lock(&self) -> ::std::sync::MutexGuard<'_, StandInState> {
+    self.state.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  fn serve_commands(socket: UdpSocket, state: Arc<Mutex<StandInState>>,
+      running: Arc<AtomicBool>) {
+    let mut buffer = [0u8; 64];
+    while running.load(Ordering::SeqCst) {
+      let (len, from) = match socket.recv_from(&mut buffer) {
+        Ok(received) => received,
+        Err(_) => continue,
+      };
+
+      let command = match Command::read_bytes(&buffer[..len]) {
+        Ok(command) => command,
+        Err(_) => continue,
+      };
+
+      let response = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.update();
+        match command {
+          Command::GetFullInfo => Some(state.info.to_bytes()),
+          Command::EnableBufferSizeResponse(enable) => {
+            state.feedback = enable;
+            None
+          },
+          Command::SetOutput(enable) => {
+            state.info.output_enabled = enable;
+            None
+          },
+          Command::SetRate(rate) => {
+            state.info.rate = rate.min(state.info.max_rate);
+            None
+          },
+          Command::GetRingBufferFree =>
+              Some(buffer_free_response(state.info.buffer_free)),
+          Command::ClearRingBuffer => {
+            state.buffered = 0;
+            state.update();
+            None
+          },
+        }
+      };
+
+      if let Some(response) = response {
+        let _r = socket.send_to(&response, from);
+      }
+    }
+  }
+
+  fn serve_data(socket: UdpSocket, state: Arc<Mutex<StandInState>>,
+      running: Arc<AtomicBool>) {
+    let mut buffer = [0u8; 2048];
+    while running.load(Ordering::SeqCst) {
+      let (len, from) = match socket.recv_from(&mut buffer) {
+        Ok(received) => received,
+        Err(_) => continue,
+      };
+
+      let packet = match SamplePacket::read_bytes(&buffer[..len]) {
+        Ok(packet) => packet,
+        Err(_) => continue,
+      };
+
+      let response = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.update();
+        let free = state.info.buffer_free as usize;
+        let accepted = packet.samples.len().min(free);
+        state.overflowed += packet.samples.len() - accepted;
+        state.received.extend_from_slice(&packet.samples[..accepted]);
+        state.buffered += accepted;
+        state.update();
+
+        if state.feedback {
+          Some(buffer_free_response(state.info.buffer_free))
+        } else {
+          None
+        }
+      };
+
+      if let Some(response) = response {
+        let _r = socket.send_to(&response, from);
+      }
+    }
+  }
+}
+
+impl Drop for StandIn {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::SeqCst);
+  }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+  (bytes[0] as u16) | ((bytes[1] as u16) << 8)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32)
+      | ((bytes[1] as u32) << 8)
+      | ((bytes[2] as u32) << 16)
+      | ((bytes[3] as u32) << 24)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use animation::Frame;
+
+  #[test]
+  fn test_sample_conversion() {
+    let point = SimplePoint { x: 0, y: -32768, r: 10, g: 20, b: 30,
+        is_blank: false };
+    let sample = Sample::from(&point);
+    assert_eq!(Sample { x: 0x800, y: 0, r: 10, g: 20, b: 30 }, sample);
+
+    let bytes = sample.to_bytes();
+    assert_eq!([10, 20, 30, 0, 0x00, 0x08, 0, 0], bytes);
+    assert_eq!(sample, Sample::read_bytes(&bytes).unwrap());
+
+    let blank = Sample::from(&SimplePoint { is_blank: true, ..point });
+    assert_eq!((0, 0, 0), (blank.r, blank.g, blank.b));
+  }
+
+  #[test]
+  fn test_commands() {
+    let commands = [
+      Command::GetFullInfo,
+      Command::EnableBufferSizeResponse(true),
+      Command::SetOutput(false),
+      Command::SetRate(40_000),
+      Command::GetRingBufferFree,
+      Command::ClearRingBuffer,
+    ];
+
+    for command in commands.iter() {
+      assert_eq!(*command, Command::read_bytes(&command.to_bytes()).unwrap());
+    }
+
+    assert_eq!(vec![0x82, 0x40, 0x9c, 0, 0], Command::SetRate(40_000)
+        .to_bytes());
+    assert!(Command::read_bytes(&[]).is_err());
+    assert!(Command::read_bytes(&[CMD_SET_RATE, 1]).is_err());
+  }
+
+  #[test]
+  fn test_sample_packet() {
+    let packet = SamplePacket {
+      message_number: 255,
+      frame_number: 3,
+      samples: vec![Sample::default(); 2],
+    };
+    let bytes = packet.to_bytes().unwrap();
+    assert_eq!(SAMPLE_HEADER_SIZE + 2 * SAMPLE_SIZE, bytes.len());
+    assert_eq!(&[CMD_SAMPLE_DATA, 0, 255, 3], &bytes[..4]);
+    assert_eq!(packet, SamplePacket::read_bytes(&bytes).unwrap());
+
+    let too_big = SamplePacket {
+      samples: vec![Sample::default(); MAX_SAMPLES_PER_PACKET + 1],
+      ..packet
+    };
+    assert!(too_big.to_bytes().is_err());
+    assert!(SamplePacket::read_bytes(&bytes[..7]).is_err());
+  }
+
+  #[test]
+  fn test_device_info() {
+    let info = DeviceInfo {
+      firmware_major: 1,
+      firmware_minor: 2,
+      output_enabled: true,
+      rate: 30_000,
+      max_rate: 60_000,
+      buffer_free: 100,
+      buffer_capacity: 6000,
+      serial_number: [1, 2, 3, 4, 5, 6],
+    };
+    let bytes = info.to_bytes();
+    assert_eq!(INFO_SIZE, bytes.len());
+    assert_eq!(info, DeviceInfo::read_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_stale_feedback() {
+    let stand_in = StandIn::start(300, 200_000).unwrap();
+    let mut sender = Sender::connect_ports(stand_in.command_address(),
+        stand_in.data_address()).unwrap();
+
+    // Two packets in flight; the first reply predates the second packet.
+    sender.buffer_free = 100;
+    sender.unanswered = 2;
+    sender.take_feedback(250);
+    assert_eq!(100, sender.buffer_free);
+    sender.take_feedback(40);
+    assert_eq!(40, sender.buffer_free);
+
+    sender.unanswered = 1;
+    sender.take_feedback(280);
+    assert_eq!(280, sender.buffer_free);
+    sender.take_feedback(290);
+    assert_eq!(290, sender.buffer_free);
+  }
+
+  #[test]
+  fn test_stream_to_stand_in() {
+    let points: Vec<_> = (0..1000)
+        .map(|i| SimplePoint { x: i * 16, y: 0, r: 255, g: 0, b: 0,
+            is_blank: false })
+        .collect();
+    let animation = Animation::new(vec![
+      Frame::new(points[..600].to_vec()),
+      Frame::new(points[600..].to_vec()),
+    ]);
+
+    let stand_in = StandIn::start(300, 200_000).unwrap();
+    let mut sender = Sender::connect_ports(stand_in.command_address(),
+        stand_in.data_address()).unwrap();
+    assert_eq!(300, sender.get_info().buffer_capacity);
+
+    sender.play_animation(&animation, 100_000).unwrap();
+
+    // Give the last packet time to land.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while stand_in.received_samples().len() < points.len()
+        && Instant::now() < deadline {
+      thread::sleep(Duration::from_millis(5));
+    }
+
+    assert_eq!(0, stand_in.overflow_count());
+    assert!(stand_in.info().output_enabled);
+    assert_eq!(100_000, stand_in.info().rate);
+
+    let xs: Vec<_> = stand_in.received_samples().iter()
+        .map(|sample| sample.x)
+        .collect();
+    let expected: Vec<_> = points.iter()
+        .map(|point| Sample::from(point).x)
+        .collect();
+    assert_eq!(expected, xs);
+  }
+}
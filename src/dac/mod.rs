@@ -0,0 +1,11 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Output drivers for laser DACs. Each driver pulls its points from a
+//! `source::PointSource`, so any content that can be turned into a point
+//! stream can be sent to any supported hardware.
+
+pub mod audio;
+pub mod etherdream;
+pub mod helios;
+pub mod idn;
+pub mod lasercube;
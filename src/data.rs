@@ -17,8 +17,14 @@ pub const TRUE_COLOR_2D_DATA_SIZE: usize = 8;
 /// Size of an ILDA True Color 3D point data section in bytes.
 pub const TRUE_COLOR_3D_DATA_SIZE: usize = 10;
 
+/// Status code bit set on blanking points.
+pub const BLANK_BIT: u8 = 64;
+/// Status code bit set on the last point of a frame.
+pub const LAST_POINT_BIT: u8 = 128;
+
 /// The payload encoding formats currently supported.
 #[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Format {
   Unknown,
   ColorPalette,
@@ -28,20 +34,68 @@ pub enum Format {
   TrueColor3d,
 }
 
+impl Format {
+  /// The format code written in headers, if the format is known.
+  pub fn code(&self) -> Option<u8> {
+    match *self {
+      Format::Indexed3d => Some(0),
+      Format::Indexed2d => Some(1),
+      Format::ColorPalette => Some(2),
+      Format::TrueColor3d => Some(4),
+      Format::TrueColor2d => Some(5),
+      Format::Unknown => None,
+    }
+  }
+
+  /// The size in bytes of each record in the format, if the format is known.
+  pub fn record_size(&self) -> Option<usize> {
+    match *self {
+      Format::Indexed3d => Some(INDEXED_3D_DATA_SIZE),
+      Format::Indexed2d => Some(INDEXED_2D_DATA_SIZE),
+      Format::ColorPalette => Some(COLOR_PALETTE_SIZE),
+      Format::TrueColor3d => Some(TRUE_COLOR_3D_DATA_SIZE),
+      Format::TrueColor2d => Some(TRUE_COLOR_2D_DATA_SIZE),
+      Format::Unknown => None,
+    }
+  }
+
+  /// Whether points in the format are colored through a palette.
+  pub fn is_indexed(&self) -> bool {
+    *self == Format::Indexed2d || *self == Format::Indexed3d
+  }
+
+  /// Whether points in the format have a Z coordinate.
+  pub fn is_3d(&self) -> bool {
+    *self == Format::Indexed3d || *self == Format::TrueColor3d
+  }
+}
+
 /// A Raw ILDA header.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Header {
-  /// The first reserved portion of the ILDA header.
-  pub reserved: u16,
+  /// The reserved bytes between "ILDA" and the format code.
+  pub reserved: [u8; 3],
 
   /// The format, or type, of the header.
   pub format_code: u8,
 
-  /// The name of the frame or color palette.
-  pub name: Option<String>, // TODO: Make this fixed-width.
+  /// The name of the frame or color palette, decoded with `decode_name`.
+  pub name: Option<String>,
 
-  /// The name of the company.
-  pub company_name: Option<String>, // TODO: Make this fixed-width.
+  /// The name of the company, decoded with `decode_name`.
+  pub company_name: Option<String>,
+
+  /// The name field exactly as read, including any bytes that don't survive
+  /// decoding. It's written back as is while `name` still decodes from it,
+  /// and otherwise `name` is encoded in its place.
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub raw_name: [u8; 8],
+
+  /// The company name field exactly as read. It's written back the same way
+  /// as `raw_name`.
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub raw_company_name: [u8; 8],
 
   /// The number of records (eg. points) following this header.
   /// If 0, this is the EOF header.
@@ -64,6 +118,41 @@ pub struct Header {
 }
 
 impl Header {
+  /// Create a header for the given format, with every other field zeroed.
+  pub fn new(format_code: u8) -> Header {
+    Header {
+      reserved: [0; 3],
+      format_code,
+      name: None,
+      company_name: None,
+      raw_name: [0; 8],
+      raw_company_name: [0; 8],
+      record_count: 0,
+      number: 0,
+      total_frames: 0,
+      projector_number: 0,
+      reserved_2: 0,
+    }
+  }
+
+  /// Serialize the header. Names are encoded with `encode_name` unless
+  /// their raw fields still match them.
+  pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+    let mut out = [0u8; HEADER_SIZE];
+    out[0..4].copy_from_slice(b"ILDA");
+    out[4..7].copy_from_slice(&self.reserved);
+    out[7] = self.format_code;
+    out[8..16].copy_from_slice(&name_field(&self.raw_name, &self.name));
+    out[16..24].copy_from_slice(
+        &name_field(&self.raw_company_name, &self.company_name));
+    out[24..26].copy_from_slice(&self.record_count.to_be_bytes());
+    out[26..28].copy_from_slice(&self.number.to_be_bytes());
+    out[28..30].copy_from_slice(&self.total_frames.to_be_bytes());
+    out[30] = self.projector_number;
+    out[31] = self.reserved_2;
+    out
+  }
+
   /// Returns the format of the header.
   pub fn get_format(&self) -> Format {
     match self.format_code {
@@ -79,6 +168,7 @@ impl Header {
 
 /// 3D Coordinates with Indexed Color (format 0)
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IndexedPoint3d {
   /// X coordinate
   pub x: i16,
@@ -117,15 +207,37 @@ impl IndexedPoint3d {
     Ok(out)
   }
 
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; INDEXED_3D_DATA_SIZE] {
+    let (x, y, z) = (self.x.to_be_bytes(), self.y.to_be_bytes(),
+        self.z.to_be_bytes());
+    [x[0], x[1], y[0], y[1], z[0], z[1], self.status_code, self.color_index]
+  }
+
   /// Whether the point is a blanking point.
   pub fn is_blank(&self) -> bool {
-    // 7th high order bit is the blanking bit.
-    self.status_code & 64 == 64
+    self.status_code & BLANK_BIT == BLANK_BIT
+  }
+
+  /// Set or clear the blanking bit.
+  pub fn set_blank(&mut self, blank: bool) {
+    set_bit(&mut self.status_code, BLANK_BIT, blank);
+  }
+
+  /// Whether the point is marked as the last point of its frame.
+  pub fn is_last_point(&self) -> bool {
+    self.status_code & LAST_POINT_BIT == LAST_POINT_BIT
+  }
+
+  /// Set or clear the last point bit.
+  pub fn set_last_point(&mut self, last: bool) {
+    set_bit(&mut self.status_code, LAST_POINT_BIT, last);
   }
 }
 
 /// 2D Coordinates with Indexed Color (format 1)
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct IndexedPoint2d {
   /// X coordinate
   pub x: i16,
@@ -161,15 +273,36 @@ impl IndexedPoint2d {
     Ok(out)
   }
 
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; INDEXED_2D_DATA_SIZE] {
+    let (x, y) = (self.x.to_be_bytes(), self.y.to_be_bytes());
+    [x[0], x[1], y[0], y[1], self.status_code, self.color_index]
+  }
+
   /// Whether the point is a blanking point.
   pub fn is_blank(&self) -> bool {
-    // 7th high order bit is the blanking bit.
-    self.status_code & 64 == 64
+    self.status_code & BLANK_BIT == BLANK_BIT
+  }
+
+  /// Set or clear the blanking bit.
+  pub fn set_blank(&mut self, blank: bool) {
+    set_bit(&mut self.status_code, BLANK_BIT, blank);
+  }
+
+  /// Whether the point is marked as the last point of its frame.
+  pub fn is_last_point(&self) -> bool {
+    self.status_code & LAST_POINT_BIT == LAST_POINT_BIT
+  }
+
+  /// Set or clear the last point bit.
+  pub fn set_last_point(&mut self, last: bool) {
+    set_bit(&mut self.status_code, LAST_POINT_BIT, last);
   }
 }
 
 /// Color Palette (format 2)
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ColorPalette {
   /// Red.
   pub r: u8,
@@ -200,10 +333,16 @@ impl ColorPalette {
 
     Ok(out)
   }
+
+  /// Serialize the palette entry.
+  pub fn to_bytes(&self) -> [u8; COLOR_PALETTE_SIZE] {
+    [self.r, self.g, self.b]
+  }
 }
 
 /// 3D Coordinates with True Color (format 4)
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TrueColorPoint3d {
   /// X coordinate
   pub x: i16,
@@ -239,24 +378,47 @@ impl TrueColorPoint3d {
         y: read_i16(&bytes[j+2 .. j+4]),
         z: read_i16(&bytes[j+4 .. j+6]),
         status_code: bytes[j+6],
-        b: bytes[7],
-        g: bytes[8],
-        r: bytes[9],
+        b: bytes[j+7],
+        g: bytes[j+8],
+        r: bytes[j+9],
       });
     }
 
     Ok(out)
   }
 
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; TRUE_COLOR_3D_DATA_SIZE] {
+    let (x, y, z) = (self.x.to_be_bytes(), self.y.to_be_bytes(),
+        self.z.to_be_bytes());
+    [x[0], x[1], y[0], y[1], z[0], z[1], self.status_code, self.b, self.g,
+        self.r]
+  }
+
   /// Whether the point is a blanking point.
   pub fn is_blank(&self) -> bool {
-    // 7th high order bit is the blanking bit.
-    self.status_code & 64 == 64
+    self.status_code & BLANK_BIT == BLANK_BIT
+  }
+
+  /// Set or clear the blanking bit.
+  pub fn set_blank(&mut self, blank: bool) {
+    set_bit(&mut self.status_code, BLANK_BIT, blank);
+  }
+
+  /// Whether the point is marked as the last point of its frame.
+  pub fn is_last_point(&self) -> bool {
+    self.status_code & LAST_POINT_BIT == LAST_POINT_BIT
+  }
+
+  /// Set or clear the last point bit.
+  pub fn set_last_point(&mut self, last: bool) {
+    set_bit(&mut self.status_code, LAST_POINT_BIT, last);
   }
 }
 
 /// 3D Coordinates with True Color (format 5)
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TrueColorPoint2d {
   /// X coordinate
   pub x: i16,
@@ -298,16 +460,37 @@ impl TrueColorPoint2d {
     Ok(out)
   }
 
+  /// Serialize the point.
+  pub fn to_bytes(&self) -> [u8; TRUE_COLOR_2D_DATA_SIZE] {
+    let (x, y) = (self.x.to_be_bytes(), self.y.to_be_bytes());
+    [x[0], x[1], y[0], y[1], self.status_code, self.b, self.g, self.r]
+  }
+
   /// Whether the point is a blanking point.
   pub fn is_blank(&self) -> bool {
-    // 7th high order bit is the blanking bit.
-    self.status_code & 64 == 64
+    self.status_code & BLANK_BIT == BLANK_BIT
+  }
+
+  /// Set or clear the blanking bit.
+  pub fn set_blank(&mut self, blank: bool) {
+    set_bit(&mut self.status_code, BLANK_BIT, blank);
+  }
+
+  /// Whether the point is marked as the last point of its frame.
+  pub fn is_last_point(&self) -> bool {
+    self.status_code & LAST_POINT_BIT == LAST_POINT_BIT
+  }
+
+  /// Set or clear the last point bit.
+  pub fn set_last_point(&mut self, last: bool) {
+    set_bit(&mut self.status_code, LAST_POINT_BIT, last);
   }
 }
 
 /// ILDA header and data records.
 #[allow(missing_docs)]
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum IldaEntry {
   HeaderEntry(Header),
   TcPoint3dEntry(TrueColorPoint3d),
@@ -317,16 +500,175 @@ pub enum IldaEntry {
   IdxPoint2dEntry(IndexedPoint2d),
 }
 
+impl IldaEntry {
+  /// The status code of a point entry, or `None` for headers and colors.
+  pub fn status_code(&self) -> Option<u8> {
+    match *self {
+      IldaEntry::IdxPoint3dEntry(ref point) => Some(point.status_code),
+      IldaEntry::IdxPoint2dEntry(ref point) => Some(point.status_code),
+      IldaEntry::TcPoint3dEntry(ref point) => Some(point.status_code),
+      IldaEntry::TcPoint2dEntry(ref point) => Some(point.status_code),
+      IldaEntry::HeaderEntry(_) | IldaEntry::ColorPaletteEntry(_) => None,
+    }
+  }
+
+  /// Serialize the entry.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    match *self {
+      IldaEntry::HeaderEntry(ref header) => header.to_bytes().to_vec(),
+      IldaEntry::TcPoint3dEntry(ref point) => point.to_bytes().to_vec(),
+      IldaEntry::TcPoint2dEntry(ref point) => point.to_bytes().to_vec(),
+      IldaEntry::ColorPaletteEntry(ref color) => color.to_bytes().to_vec(),
+      IldaEntry::IdxPoint3dEntry(ref point) => point.to_bytes().to_vec(),
+      IldaEntry::IdxPoint2dEntry(ref point) => point.to_bytes().to_vec(),
+    }
+  }
+}
+
 // FIXME:
 // Reads in as little endian from big endian source. Not cross-platform.
 fn read_i16(bytes: &[u8]) -> i16 {
   (((bytes[0] as u16) << 8) | (bytes[1] as u16)) as i16
 }
 
+// Set or clear a bit of a status code.
+fn set_bit(status_code: &mut u8, bit: u8, set: bool) {
+  if set {
+    *status_code |= bit;
+  } else {
+    *status_code &= !bit;
+  }
+}
+
+/// Decode a name field. Names are Latin-1 (ISO 8859-1), so each byte is the
+/// character with the same code point. The name ends at the first NUL, and
+/// control characters are dropped. Empty names are `None`.
+pub fn decode_name(field: &[u8]) -> Option<String> {
+  let name: String = field.iter()
+      .take_while(|&&byte| byte != 0)
+      .filter(|&&byte| byte >= 0x20)
+      .map(|&byte| byte as char)
+      .collect();
+  match name.len() {
+    0 => None,
+    _ => Some(name),
+  }
+}
+
+/// Encode a name field as Latin-1. Characters outside Latin-1 are written as
+/// `?`, and names longer than eight characters are truncated.
+pub fn encode_name(name: Option<&str>) -> [u8; 8] {
+  let mut field = [0u8; 8];
+  if let Some(name) = name {
+    for (slot, c) in field.iter_mut().zip(name.chars()) {
+      *slot = if (c as u32) < 0x100 { c as u8 } else { b'?' };
+    }
+  }
+  field
+}
+
+// Keep the raw field when the name is unchanged, so that any bytes after the
+// NUL survive a round trip.
+fn name_field(raw: &[u8; 8], name: &Option<String>) -> [u8; 8] {
+  if decode_name(raw) == *name {
+    *raw
+  } else {
+    encode_name(name.as_deref())
+  }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::field_reassign_with_default,
+    clippy::redundant_field_names)]
 mod tests {
   use super::*;
 
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serde_entries() {
+    let point = TrueColorPoint2d {
+      x: -5,
+      r: 255,
+      status_code: 64,
+      ..Default::default()
+    };
+    let entries = vec![
+      IldaEntry::HeaderEntry(Header::new(5)),
+      IldaEntry::TcPoint2dEntry(point),
+    ];
+
+    let json = ::serde_json::to_value(&entries).unwrap();
+    assert_eq!(5, json[0]["HeaderEntry"]["format_code"]);
+    assert_eq!(-5, json[1]["TcPoint2dEntry"]["x"]);
+    assert_eq!(64, json[1]["TcPoint2dEntry"]["status_code"]);
+
+    let read: Vec<IldaEntry> = ::serde_json::from_value(json).unwrap();
+    let bytes: Vec<u8> = read.iter().flat_map(|e| e.to_bytes()).collect();
+    let expected: Vec<u8> = entries.iter().flat_map(|e| e.to_bytes()).collect();
+    assert_eq!(expected, bytes);
+  }
+
+  #[test]
+  fn test_decode_name() {
+    assert_eq!(decode_name(&[0, 0, 0, 0]), None);
+    assert_eq!(decode_name(&[0, 100, 100, 100]), None);
+    assert_eq!(decode_name(&[102, 111, 111]), Some("foo".to_string()));
+    assert_eq!(decode_name(&[102, 111, 111, 0, 111]),
+               Some("foo".to_string()));
+    assert_eq!(decode_name(&[99, 97, 102, 0xe9, 31, 1]),
+               Some("caf\u{e9}".to_string()));
+  }
+
+  #[test]
+  fn test_encode_name() {
+    assert_eq!(*b"caf\xe9?\0\0\0", encode_name(Some("caf\u{e9}\u{263a}")));
+    assert_eq!(*b"12345678", encode_name(Some("123456789")));
+    assert_eq!([0; 8], encode_name(None));
+  }
+
+  #[test]
+  fn test_header_raw_fields() {
+    let mut bytes = Header::new(5).to_bytes();
+    bytes[4..7].copy_from_slice(&[1, 2, 3]);
+    bytes[8..16].copy_from_slice(b"ab\0junk\x01");
+    bytes[31] = 9;
+
+    let mut header = Header::new(5);
+    header.reserved = [1, 2, 3];
+    header.raw_name.copy_from_slice(&bytes[8..16]);
+    header.name = decode_name(&header.raw_name);
+    header.reserved_2 = 9;
+    assert_eq!(bytes, header.to_bytes());
+
+    // Once the name changes, it's encoded in place of the raw field.
+    header.name = Some("xyz".to_string());
+    assert_eq!(b"xyz\0\0\0\0\0", &header.to_bytes()[8..16]);
+  }
+
+  #[test]
+  fn test_status_setters() {
+    let mut point = TrueColorPoint3d {
+      status_code: 1,
+      ..Default::default()
+    };
+    point.set_last_point(true);
+    assert!(point.is_last_point());
+    assert!(!point.is_blank());
+    point.set_blank(true);
+    assert_eq!(1 | BLANK_BIT | LAST_POINT_BIT, point.status_code);
+    point.set_last_point(false);
+    assert!(!point.is_last_point());
+    assert_eq!(1 | BLANK_BIT, point.status_code);
+
+    let mut point = IndexedPoint2d {
+      status_code: 255,
+      ..Default::default()
+    };
+    assert!(point.is_last_point());
+    point.set_blank(false);
+    assert_eq!(255 - BLANK_BIT, point.status_code);
+  }
+
   #[test]
   fn test_indexed_2d_blanking_bit() {
     let mut point = IndexedPoint2d::default();
@@ -366,6 +708,46 @@ mod tests {
     assert_eq!(true, point.is_blank());
   }
 
+  #[test]
+  fn test_header_to_bytes() {
+    let mut header = Header::new(5);
+    header.name = Some("frame".to_string());
+    header.company_name = Some("longcompany".to_string());
+    header.record_count = 300;
+    header.number = 2;
+    header.total_frames = 3;
+    header.projector_number = 1;
+
+    let bytes = header.to_bytes();
+    assert_eq!(b"ILDA", &bytes[0..4]);
+    assert_eq!(5, bytes[7]);
+    assert_eq!(b"frame\0\0\0", &bytes[8..16]);
+    assert_eq!(b"longcomp", &bytes[16..24]);
+    assert_eq!(&[1, 44], &bytes[24..26]);
+    assert_eq!(&[0, 2], &bytes[26..28]);
+    assert_eq!(&[0, 3], &bytes[28..30]);
+    assert_eq!(1, bytes[30]);
+  }
+
+  #[test]
+  fn test_point_round_trip() {
+    let point = TrueColorPoint3d { x: -2, y: 300, z: 5, status_code: 64,
+        r: 1, g: 2, b: 3 };
+    let mut bytes = point.to_bytes().to_vec();
+    bytes.extend_from_slice(&TrueColorPoint3d::default().to_bytes());
+    let points = TrueColorPoint3d::read_bytes(&bytes).unwrap();
+    assert_eq!((-2, 300, 5, 64), (points[0].x, points[0].y, points[0].z,
+        points[0].status_code));
+    assert_eq!((1, 2, 3), (points[0].r, points[0].g, points[0].b));
+    assert_eq!((0, 0, 0), (points[1].r, points[1].g, points[1].b));
+
+    let point = IndexedPoint2d { x: 10, y: -10, status_code: 128,
+        color_index: 7 };
+    let points = IndexedPoint2d::read_bytes(&point.to_bytes()).unwrap();
+    assert_eq!((10, -10, 128, 7), (points[0].x, points[0].y,
+        points[0].status_code, points[0].color_index));
+  }
+
   #[test]
   fn test_truecolor_3d_blanking_bit() {
     let mut point = TrueColorPoint3d::default();
@@ -0,0 +1,553 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Comparing two animations to see what changed between revisions.
+//!
+//! Frames are aligned like lines in a text diff: frames that match within
+//! the tolerances anchor the alignment, and the frames between anchors are
+//! paired up as changed frames, with any left over reported as added or
+//! removed. Points match if they're within the tolerances of each other, so
+//! small rounding differences from a re-export don't count as changes.
+
+use animation::Animation;
+use animation::Frame;
+use point::SimplePoint;
+use std::ops::Range;
+
+/// Diff settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffOptions {
+  /// Points closer than this many units are at the same position.
+  pub tolerance: f64,
+  /// Color channels differing by no more than this are the same color.
+  pub color_tolerance: u8,
+}
+
+impl Default for DiffOptions {
+  fn default() -> DiffOptions {
+    DiffOptions {
+      tolerance: 1.0,
+      color_tolerance: 1,
+    }
+  }
+}
+
+/// The differences between two animations.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+  /// Indices of frames in the old animation that were removed.
+  pub removed: Vec<usize>,
+  /// Indices of frames in the new animation that were added.
+  pub added: Vec<usize>,
+  /// Frames present in both animations that differ.
+  pub changed: Vec<FrameDiff>,
+}
+
+/// The differences between a frame and its revision.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameDiff {
+  /// Index of the frame in the old animation.
+  pub old_index: usize,
+  /// Index of the frame in the new animation.
+  pub new_index: usize,
+  /// What changed.
+  pub changes: Vec<Change>,
+}
+
+/// A single difference between two frames.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+  /// The frame name changed.
+  FrameName {
+    /// Old name.
+    old: Option<String>,
+    /// New name.
+    new: Option<String>,
+  },
+  /// The company name changed.
+  CompanyName {
+    /// Old name.
+    old: Option<String>,
+    /// New name.
+    new: Option<String>,
+  },
+  /// The projector number changed.
+  ProjectorNumber {
+    /// Old number.
+    old: u8,
+    /// New number.
+    new: u8,
+  },
+  /// The frame's color palette changed.
+  Palette,
+  /// The number of points changed.
+  PointCount {
+    /// Old count.
+    old: usize,
+    /// New count.
+    new: usize,
+  },
+  /// Points moved or changed color beyond the tolerances.
+  Points(Deviation),
+}
+
+/// How far the points of a frame are from those of its revision. Points are
+/// compared in order when the counts are equal, and otherwise each point is
+/// compared with the nearest point of the other frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Deviation {
+  /// The largest distance between matched points, in units.
+  pub max_distance: f64,
+  /// The mean distance between matched points, in units.
+  pub mean_distance: f64,
+  /// Points further than the tolerance from their match.
+  pub moved: usize,
+  /// Points whose color or blanking differs from their match.
+  pub recolored: usize,
+}
+
+impl Diff {
+  /// Whether the animations are the same within the tolerances.
+  pub fn is_empty(&self) -> bool {
+    self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+  }
+}
+
+// A step in aligning the frames of two animations.
+enum Step {
+  Same(usize, usize),
+  Removed(usize),
+  Added(usize),
+}
+
+/// Compare two animations.
+pub fn diff(old: &Animation, new: &Animation, options: &DiffOptions) -> Diff {
+  let old = old.get_frames();
+  let new = new.get_frames();
+  let mut diff = Diff::default();
+
+  let mut removed = Vec::new();
+  let mut added = Vec::new();
+  for step in align(old, new, options) {
+    match step {
+      Step::Removed(i) => removed.push(i),
+      Step::Added(j) => added.push(j),
+      Step::Same(i, j) => {
+        pair_up(&mut diff, &mut removed, &mut added, old, new, options);
+        let changes = metadata_changes(&old[i], &new[j]);
+        if !changes.is_empty() {
+          diff.changed.push(FrameDiff { old_index: i, new_index: j, changes });
+        }
+      },
+    }
+  }
+  pair_up(&mut diff, &mut removed, &mut added, old, new, options);
+
+  diff
+}
+
+/// Compare the points of two frames, or `None` if they match.
+pub fn compare_points(old: &[SimplePoint], new: &[SimplePoint],
+    options: &DiffOptions) -> Option<Deviation> {
+  if old.is_empty() && new.is_empty() {
+    return None;
+  }
+
+  // Each point's distance from its match, and whether the colors match.
+  let mut matches: Vec<(f64, bool)> = Vec::new();
+  if old.len() == new.len() {
+    for (a, b) in old.iter().zip(new.iter()) {
+      matches.push((distance(a, b), same_color(a, b, options)));
+    }
+  } else {
+    for (from, to) in [(old, new), (new, old)].iter() {
+      let to = Nearest::new(to);
+      for a in from.iter() {
+        matches.push(match to.find(a) {
+          Some(b) => (distance(a, b), same_color(a, b, options)),
+          None => (f64::INFINITY, false),
+        });
+      }
+    }
+  }
+
+  let moved = matches.iter().filter(|m| m.0 > options.tolerance).count();
+  let recolored = matches.iter().filter(|m| !m.1).count();
+  if moved == 0 && recolored == 0 {
+    return None;
+  }
+
+  let finite: Vec<f64> = matches.iter()
+      .map(|m| m.0)
+      .filter(|d| d.is_finite())
+      .collect();
+  let max_distance = finite.iter().cloned().fold(0.0, f64::max);
+  let mean_distance = if finite.is_empty() {
+    0.0
+  } else {
+    finite.iter().sum::<f64>() / finite.len() as f64
+  };
+
+  Some(Deviation { max_distance, mean_distance, moved, recolored })
+}
+
+// Align frames by a shortest edit script, using Myers' linear space
+// algorithm. Frames are only compared as the search needs them, so long
+// animations with few differences are cheap to align.
+fn align(old: &[Frame], new: &[Frame], options: &DiffOptions) -> Vec<Step> {
+  let same = |i: usize, j: usize| same_points(&old[i], &new[j], options);
+  let max_d = (old.len() + new.len() + 1) / 2 + 1;
+  let mut forward = Diagonals::new(max_d);
+  let mut backward = Diagonals::new(max_d);
+  let mut steps = Vec::with_capacity(old.len().max(new.len()));
+  conquer(&same, 0..old.len(), 0..new.len(), &mut forward, &mut backward,
+      &mut steps);
+  steps
+}
+
+// Furthest reaching x positions, indexed by diagonal k = x - y.
+struct Diagonals {
+  offset: isize,
+  x: Vec<usize>,
+}
+
+impl Diagonals {
+  fn new(max_d: usize) -> Diagonals {
+    Diagonals { offset: max_d as isize, x: vec![0; 2 * max_d + 1] }
+  }
+
+  fn get(&self, k: isize) -> usize {
+    self.x[(k + self.offset) as usize]
+  }
+
+  fn set(&mut self, k: isize, x: usize) {
+    self.x[(k + self.offset) as usize] = x;
+  }
+}
+
+// Align old[a] with new[b], splitting on the middle snake of the edit graph
+// once the common prefix and suffix are stripped.
+fn conquer<F>(same: &F, mut a: Range<usize>, mut b: Range<usize>,
+    forward: &mut Diagonals, backward: &mut Diagonals, steps: &mut Vec<Step>)
+    where F: Fn(usize, usize) -> bool {
+  while a.start < a.end && b.start < b.end && same(a.start, b.start) {
+    steps.push(Step::Same(a.start, b.start));
+    a.start += 1;
+    b.start += 1;
+  }
+
+  let mut suffix = 0;
+  while a.start < a.end && b.start < b.end && same(a.end - 1, b.end - 1) {
+    a.end -= 1;
+    b.end -= 1;
+    suffix += 1;
+  }
+
+  if a.start == a.end {
+    steps.extend(b.clone().map(Step::Added));
+  } else if b.start == b.end {
+    steps.extend(a.clone().map(Step::Removed));
+  } else {
+    match middle_snake(same, a.clone(), b.clone(), forward, backward) {
+      Some((x, y)) if (x, y) != (a.start, b.start) && (x, y) != (a.end, b.end)
+          => {
+        conquer(same, a.start..x, b.start..y, forward, backward, steps);
+        conquer(same, x..a.end, y..b.end, forward, backward, steps);
+      },
+      _ => {
+        steps.extend(a.clone().map(Step::Removed));
+        steps.extend(b.clone().map(Step::Added));
+      },
+    }
+  }
+
+  steps.extend((0..suffix).map(|i| Step::Same(a.end + i, b.end + i)));
+}
+
+// Find where the forward and backward searches for a shortest edit script
+// meet, returning a point on that path to split the problem at.
+fn middle_snake<F>(same: &F, a: Range<usize>, b: Range<usize>,
+    forward: &mut Diagonals, backward: &mut Diagonals)
+    -> Option<(usize, usize)>
+    where F: Fn(usize, usize) -> bool {
+  let (n, m) = (a.end - a.start, b.end - b.start);
+  let delta = n as isize - m as isize;
+  let odd = delta & 1 == 1;
+  forward.set(1, 0);
+  backward.set(1, 0);
+
+  let max_d = ((n + m + 1) / 2 + 1) as isize;
+  for d in 0..max_d {
+    let mut k = d;
+    while k >= -d {
+      let mut x = if k == -d
+          || (k != d && forward.get(k - 1) < forward.get(k + 1)) {
+        forward.get(k + 1)
+      } else {
+        forward.get(k - 1) + 1
+      };
+      let mut y = (x as isize - k) as usize;
+      let (x0, y0) = (x, y);
+      while x < n && y < m && same(a.start + x, b.start + y) {
+        x += 1;
+        y += 1;
+      }
+      forward.set(k, x);
+      if odd && (k - delta).abs() < d
+          && x + backward.get(-(k - delta)) >= n {
+        return Some((a.start + x0, b.start + y0));
+      }
+      k -= 2;
+    }
+
+    let mut k = d;
+    while k >= -d {
+      let mut x = if k == -d
+          || (k != d && backward.get(k - 1) < backward.get(k + 1)) {
+        backward.get(k + 1)
+      } else {
+        backward.get(k - 1) + 1
+      };
+      let mut y = (x as isize - k) as usize;
+      while x < n && y < m && same(a.end - x - 1, b.end - y - 1) {
+        x += 1;
+        y += 1;
+      }
+      backward.set(k, x);
+      if !odd && (k - delta).abs() <= d
+          && x + forward.get(-(k - delta)) >= n {
+        return Some((a.end - x, b.end - y));
+      }
+      k -= 2;
+    }
+  }
+  None
+}
+
+// Pair removed and added frames between two anchors as changed frames, and
+// record the rest as removed or added.
+fn pair_up(diff: &mut Diff, removed: &mut Vec<usize>, added: &mut Vec<usize>,
+    old: &[Frame], new: &[Frame], options: &DiffOptions) {
+  let pairs = removed.len().min(added.len());
+  for (&i, &j) in removed.iter().zip(added.iter()) {
+    let (a, b) = (&old[i], &new[j]);
+    let mut changes = metadata_changes(a, b);
+    if a.point_count() != b.point_count() {
+      changes.push(Change::PointCount {
+        old: a.point_count(),
+        new: b.point_count(),
+      });
+    }
+    if let Some(deviation) =
+        compare_points(a.get_points(), b.get_points(), options) {
+      changes.push(Change::Points(deviation));
+    }
+    diff.changed.push(FrameDiff { old_index: i, new_index: j, changes });
+  }
+  diff.removed.extend_from_slice(&removed[pairs..]);
+  diff.added.extend_from_slice(&added[pairs..]);
+  removed.clear();
+  added.clear();
+}
+
+fn metadata_changes(old: &Frame, new: &Frame) -> Vec<Change> {
+  let mut changes = Vec::new();
+  if old.get_frame_name() != new.get_frame_name() {
+    changes.push(Change::FrameName {
+      old: old.get_frame_name().map(String::from),
+      new: new.get_frame_name().map(String::from),
+    });
+  }
+  if old.get_company_name() != new.get_company_name() {
+    changes.push(Change::CompanyName {
+      old: old.get_company_name().map(String::from),
+      new: new.get_company_name().map(String::from),
+    });
+  }
+  if old.get_projector_number() != new.get_projector_number() {
+    changes.push(Change::ProjectorNumber {
+      old: old.get_projector_number(),
+      new: new.get_projector_number(),
+    });
+  }
+  if old.get_palette() != new.get_palette() {
+    changes.push(Change::Palette);
+  }
+  changes
+}
+
+fn same_points(old: &Frame, new: &Frame, options: &DiffOptions) -> bool {
+  old.point_count() == new.point_count()
+      && compare_points(old.get_points(), new.get_points(), options).is_none()
+}
+
+fn same_color(a: &SimplePoint, b: &SimplePoint, options: &DiffOptions)
+    -> bool {
+  let close = |x: u8, y: u8| x.abs_diff(y) <= options.color_tolerance;
+  a.is_blank == b.is_blank && close(a.r, b.r) && close(a.g, b.g)
+      && close(a.b, b.b)
+}
+
+// Points ordered by x, so the nearest point can be found by scanning
+// outwards from the query's x until no closer point is possible.
+struct Nearest<'a> {
+  points: &'a [SimplePoint],
+  order: Vec<usize>,
+}
+
+impl<'a> Nearest<'a> {
+  fn new(points: &'a [SimplePoint]) -> Nearest<'a> {
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by_key(|&i| points[i].x);
+    Nearest { points, order }
+  }
+
+  // The nearest point, preferring the earliest one on ties.
+  fn find(&self, point: &SimplePoint) -> Option<&'a SimplePoint> {
+    let start = self.order.partition_point(|&i| self.points[i].x < point.x);
+    let mut best: Option<(f64, usize)> = None;
+    {
+      let mut consider = |i: usize| -> bool {
+        let dx = (self.points[i].x as f64 - point.x as f64).abs();
+        if best.is_some_and(|(d, _)| dx > d) {
+          return false;
+        }
+        let d = distance(point, &self.points[i]);
+        if best.map_or(true, |(bd, bi)| d < bd || (d == bd && i < bi)) {
+          best = Some((d, i));
+        }
+        true
+      };
+      for &i in self.order[start..].iter() {
+        if !consider(i) {
+          break;
+        }
+      }
+      for &i in self.order[..start].iter().rev() {
+        if !consider(i) {
+          break;
+        }
+      }
+    }
+    best.map(|(_, i)| &self.points[i])
+  }
+}
+
+fn distance(a: &SimplePoint, b: &SimplePoint) -> f64 {
+  let dx = a.x as f64 - b.x as f64;
+  let dy = a.y as f64 - b.y as f64;
+  (dx * dx + dy * dy).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(x: i16, y: i16) -> SimplePoint {
+    SimplePoint { x, y, r: 255, g: 255, b: 255, is_blank: false }
+  }
+
+  fn frame(offset: i16) -> Frame {
+    Frame::new(vec![point(offset, 0), point(offset + 100, 0),
+        point(offset, 100)])
+  }
+
+  #[test]
+  fn test_equal_within_tolerance() {
+    let old = Animation::new(vec![frame(0), frame(1000)]);
+    let mut nudged = frame(1000);
+    let mut points = nudged.get_points().to_vec();
+    points[1].x += 1;
+    points[2].g -= 1;
+    nudged.set_points(points);
+    let new = Animation::new(vec![frame(0), nudged]);
+
+    assert!(diff(&old, &new, &DiffOptions::default()).is_empty());
+
+    let strict = DiffOptions { tolerance: 0.0, color_tolerance: 0 };
+    let diff = diff(&old, &new, &strict);
+    assert_eq!(1, diff.changed.len());
+    assert_eq!(vec![Change::Points(Deviation {
+      max_distance: 1.0,
+      mean_distance: 1.0 / 3.0,
+      moved: 1,
+      recolored: 1,
+    })], diff.changed[0].changes);
+  }
+
+  #[test]
+  fn test_added_and_removed_frames() {
+    let old = Animation::new(vec![frame(0), frame(1000), frame(2000)]);
+    let new = Animation::new(vec![frame(0), frame(3000), frame(2000),
+        frame(4000)]);
+
+    let diff = diff(&old, &new, &DiffOptions::default());
+    assert_eq!(Vec::<usize>::new(), diff.removed);
+    assert_eq!(vec![3], diff.added);
+    assert_eq!(1, diff.changed.len());
+    assert_eq!((1, 1), (diff.changed[0].old_index, diff.changed[0].new_index));
+
+    let old = Animation::new(vec![frame(0), frame(1000), frame(2000)]);
+    let new = Animation::new(vec![frame(0), frame(2000)]);
+    let diff = super::diff(&old, &new, &DiffOptions::default());
+    assert_eq!(vec![1], diff.removed);
+    assert!(diff.added.is_empty() && diff.changed.is_empty());
+  }
+
+  #[test]
+  fn test_metadata_and_point_count() {
+    let mut renamed = frame(0);
+    renamed.set_frame_name(Some("new".to_string()));
+    renamed.set_projector_number(2);
+    let mut grown = frame(1000);
+    let mut points = grown.get_points().to_vec();
+    points.push(point(1000, 100));
+    grown.set_points(points);
+
+    let old = Animation::new(vec![frame(0), frame(1000)]);
+    let new = Animation::new(vec![renamed, grown]);
+    let diff = diff(&old, &new, &DiffOptions::default());
+
+    assert_eq!(2, diff.changed.len());
+    assert_eq!(vec![
+      Change::FrameName { old: None, new: Some("new".to_string()) },
+      Change::ProjectorNumber { old: 0, new: 2 },
+    ], diff.changed[0].changes);
+    // The extra point duplicates an existing one, so nothing moved.
+    assert_eq!(vec![Change::PointCount { old: 3, new: 4 }],
+        diff.changed[1].changes);
+  }
+
+  #[test]
+  fn test_nearest_prefers_earliest_on_ties() {
+    let points = vec![point(10, 0), point(-10, 0), point(0, 5), point(0, -5)];
+    let nearest = Nearest::new(&points);
+    let find = |x, y| nearest.find(&point(x, y)).map(|p| (p.x, p.y));
+    assert_eq!(find(0, 0), Some((0, 5)));
+    assert_eq!(find(9, 1), Some((10, 0)));
+    assert_eq!(find(-30, 0), Some((-10, 0)));
+    assert!(Nearest::new(&[]).find(&point(0, 0)).is_none());
+  }
+
+  #[test]
+  fn test_align_long_animations() {
+    let old: Vec<Frame> = (0..5_000)
+        .map(|i| frame((i * 10 - 25_000) as i16))
+        .collect();
+    let mut new = old.clone();
+    new.remove(1_000);
+    new.insert(3_000, frame(-505));
+    new[4_000] = frame(-1005);
+
+    let steps = align(&old, &new, &DiffOptions::default());
+    let removed: Vec<usize> = steps.iter().filter_map(|s| match *s {
+      Step::Removed(i) => Some(i),
+      _ => None,
+    }).collect();
+    let added: Vec<usize> = steps.iter().filter_map(|s| match *s {
+      Step::Added(j) => Some(j),
+      _ => None,
+    }).collect();
+    assert_eq!(removed, vec![1_000, 4_000]);
+    assert_eq!(added, vec![3_000, 4_000]);
+  }
+}
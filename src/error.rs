@@ -12,6 +12,9 @@ pub enum IldaError {
   /// The ILDA file is too small to read.
   FileTooSmall,
 
+  /// A saved section index doesn't match the file it's used with.
+  IndexMismatch,
+
   /// Problems were encountered while reading the ILDA data.
   InvalidData,
 
@@ -25,29 +28,69 @@ pub enum IldaError {
     cause: io::Error
   },
 
+  /// A projector profile could not be read or written.
+  InvalidProfile {
+    /// Description of the problem.
+    cause: String
+  },
+
+  /// Text input, such as ILDA text or CSV, could not be parsed.
+  InvalidText {
+    /// The line number, starting from 1.
+    line: usize,
+    /// Description of the problem.
+    cause: String
+  },
+
   /// No data in the file, or nothing could be parsed.
   NoData,
 
+  /// A laser DAC refused a command.
+  Rejected,
+
+  /// The data is too large to be represented, such as a frame with more
+  /// than 65535 points.
+  TooLarge,
+
   /// Not yet supported.
   Unsupported
 }
 
-impl Error for IldaError {
-  fn description(&self) -> &str {
+impl IldaError {
+  fn name(&self) -> &str {
     match *self {
       IldaError::FileTooSmall => "FileTooSmall",
+      IldaError::IndexMismatch => "IndexMismatch",
       IldaError::InvalidData => "InvalidData",
       IldaError::InvalidHeader => "InvalidHeader",
       IldaError::IoError { .. } => "IoError",
+      IldaError::InvalidProfile { .. } => "InvalidProfile",
+      IldaError::InvalidText { .. } => "InvalidText",
       IldaError::NoData => "NoData",
+      IldaError::Rejected => "Rejected",
+      IldaError::TooLarge => "TooLarge",
       IldaError::Unsupported => "Unsupported",
     }
   }
 }
 
+impl Error for IldaError {
+  fn description(&self) -> &str {
+    self.name()
+  }
+}
+
 impl Display for IldaError {
   fn fmt(&self, f: &mut Formatter) -> Result {
-    write!(f, "{}", self.description())
+    match *self {
+      IldaError::InvalidProfile { ref cause } => {
+        write!(f, "{}: {}", self.name(), cause)
+      },
+      IldaError::InvalidText { line, ref cause } => {
+        write!(f, "{}: line {}: {}", self.name(), line, cause)
+      },
+      _ => write!(f, "{}", self.name()),
+    }
   }
 }
 
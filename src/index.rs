@@ -0,0 +1,517 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Random access to the frames of an ILDA file.
+//!
+//! A `SectionIndex` records where every section starts. It's built by
+//! reading only the headers, skipping over each section's records, so even
+//! very large files are indexed quickly. An `IndexedReader` then uses the
+//! index to seek straight to a frame and decode just that frame.
+//!
+//! Indexes can be saved next to the file they describe and loaded later, so
+//! they only need to be built once. A saved index remembers the length of
+//! its file and a checksum of every header, so that an index left over from
+//! a different version of the file is refused.
+//!
+//! ```
+//! # use ilda::index::IndexedReader;
+//! # use std::fs::File;
+//! let file = File::open("examples/files/ildatest.ild").unwrap();
+//! let mut reader = IndexedReader::new(file).unwrap();
+//!
+//! let frame = reader.read_frame(0).unwrap();
+//! assert_eq!(1191, frame.point_count());
+//! ```
+
+use animation::Animation;
+use animation::Frame;
+use data::Format;
+use data::HEADER_SIZE;
+use data::Header;
+use error::IldaError;
+use parser::read_bytes;
+use parser::read_header;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use view::IldaView;
+
+/// Identifies a saved index; "ILDX" in ASCII.
+const INDEX_MAGIC : [u8; 4] = [73u8, 76u8, 68u8, 88u8];
+
+/// Version of the saved index layout.
+const INDEX_VERSION : u8 = 3;
+
+/// Size of the saved index header: magic, version, source length, checksum
+/// and section count.
+const INDEX_HEADER_SIZE : usize = 25;
+
+/// Size of one saved section entry.
+const ENTRY_SIZE : usize = 14;
+
+/// Starting value of the FNV-1a header checksum.
+const FNV_OFFSET_BASIS : u64 = 0xcbf29ce484222325;
+
+/// The location and summary of a single section.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SectionEntry {
+  /// Byte offset of the section's header.
+  pub offset: u64,
+  /// The format code from the header.
+  pub format_code: u8,
+  /// The number of records following the header.
+  pub record_count: u16,
+  /// The frame or palette number from the header.
+  pub number: u16,
+  /// The projector number from the header.
+  pub projector_number: u8,
+}
+
+/// The sections of an ILDA file. Frames are numbered the same way as in
+/// `Animation`.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SectionIndex {
+  sections: Vec<SectionEntry>,
+  frames: Vec<usize>,
+  source_len: u64,
+  checksum: u64,
+}
+
+/// Reads individual frames from a seekable source using a section index.
+pub struct IndexedReader<R> {
+  reader: R,
+  index: SectionIndex,
+}
+
+impl SectionEntry {
+  fn from_header(offset: u64, header: &Header) -> SectionEntry {
+    SectionEntry {
+      offset,
+      format_code: header.format_code,
+      record_count: header.record_count,
+      number: header.number,
+      projector_number: header.projector_number,
+    }
+  }
+
+  /// The format of the section's records.
+  pub fn get_format(&self) -> Format {
+    Header::new(self.format_code).get_format()
+  }
+
+  /// Whether the section starts a frame. As in `Animation`, this includes
+  /// empty sections such as the terminating header.
+  pub fn is_frame(&self) -> bool {
+    !self.is_palette()
+  }
+
+  /// Whether the section is a color palette.
+  pub fn is_palette(&self) -> bool {
+    self.record_count > 0 && self.get_format() == Format::ColorPalette
+  }
+
+  /// Size of the section in bytes, including its header.
+  pub fn byte_len(&self) -> Result<u64, IldaError> {
+    let size = self.get_format().record_size()
+        .ok_or(IldaError::InvalidHeader)?;
+    Ok((HEADER_SIZE + size * self.record_count as usize) as u64)
+  }
+}
+
+impl SectionIndex {
+  // Build an index from a list of sections and a summary of their source.
+  fn new(sections: Vec<SectionEntry>, source_len: u64, checksum: u64)
+      -> SectionIndex {
+    let frames = sections.iter()
+        .enumerate()
+        .filter(|&(_, section)| section.is_frame())
+        .map(|(i, _)| i)
+        .collect();
+    SectionIndex { sections, frames, source_len, checksum }
+  }
+
+  /// Index a seekable source by reading its headers. The source is left
+  /// positioned after the last section read.
+  pub fn build<R: Read + Seek>(reader: &mut R)
+      -> Result<SectionIndex, IldaError> {
+    let mut sections = Vec::new();
+    let mut checksum = FNV_OFFSET_BASIS;
+    let mut offset = reader.seek(SeekFrom::Start(0))?;
+    let end = reader.seek(SeekFrom::End(0))?;
+
+    while offset < end {
+      let mut bytes = [0u8; HEADER_SIZE];
+      reader.seek(SeekFrom::Start(offset))?;
+      reader.read_exact(&mut bytes).map_err(|_| IldaError::InvalidHeader)?;
+      checksum = hash(checksum, &bytes);
+
+      let section = SectionEntry::from_header(offset, &read_header(&bytes)?);
+      let next = offset + section.byte_len()?;
+      if next > end {
+        return Err(IldaError::InvalidData);
+      }
+
+      sections.push(section);
+      offset = next;
+    }
+
+    if sections.is_empty() {
+      return Err(IldaError::FileTooSmall);
+    }
+    Ok(SectionIndex::new(sections, end, checksum))
+  }
+
+  /// Index ILDA data that's already in memory.
+  pub fn from_bytes(ilda_bytes: &[u8]) -> Result<SectionIndex, IldaError> {
+    let mut sections = Vec::new();
+    let mut checksum = FNV_OFFSET_BASIS;
+    for section in IldaView::new(ilda_bytes).sections() {
+      let section = section?;
+      let offset = section.offset();
+      checksum = hash(checksum, &ilda_bytes[offset .. offset + HEADER_SIZE]);
+      sections.push(SectionEntry::from_header(offset as u64,
+          &section.header()));
+    }
+
+    if sections.is_empty() {
+      return Err(IldaError::FileTooSmall);
+    }
+    Ok(SectionIndex::new(sections, ilda_bytes.len() as u64, checksum))
+  }
+
+  /// Every section, in file order.
+  pub fn get_sections(&self) -> &[SectionEntry] {
+    &self.sections
+  }
+
+  /// The number of frames.
+  pub fn frame_count(&self) -> usize {
+    self.frames.len()
+  }
+
+  /// The section holding the given frame, if it exists.
+  pub fn get_frame(&self, position: usize) -> Option<&SectionEntry> {
+    self.frames.get(position).map(|&i| &self.sections[i])
+  }
+
+  /// The palette section that applies to the given frame. Frames before the
+  /// first palette use the standard palette, and so have none.
+  pub fn get_frame_palette(&self, position: usize) -> Option<&SectionEntry> {
+    let section = *self.frames.get(position)?;
+    self.sections[..section].iter().rev().find(|s| s.is_palette())
+  }
+
+  /// The length in bytes of the indexed source.
+  pub fn source_len(&self) -> u64 {
+    self.source_len
+  }
+
+  /// Check that a seekable source is the one this index was built from,
+  /// failing with `IldaError::IndexMismatch` if it isn't.
+  pub fn verify<R: Read + Seek>(&self, reader: &mut R)
+      -> Result<(), IldaError> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len != self.source_len {
+      return Err(IldaError::IndexMismatch);
+    }
+    match read_checksum(reader, &self.sections) {
+      Ok(checksum) if checksum == self.checksum => Ok(()),
+      Ok(_) | Err(IldaError::InvalidHeader) => Err(IldaError::IndexMismatch),
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Save the index in a compact binary form.
+  pub fn write_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(
+        INDEX_HEADER_SIZE + self.sections.len() * ENTRY_SIZE);
+    out.extend_from_slice(&INDEX_MAGIC);
+    out.push(INDEX_VERSION);
+    out.extend_from_slice(&self.source_len.to_be_bytes());
+    out.extend_from_slice(&self.checksum.to_be_bytes());
+    out.extend_from_slice(&(self.sections.len() as u32).to_be_bytes());
+    for section in &self.sections {
+      out.extend_from_slice(&section.offset.to_be_bytes());
+      out.push(section.format_code);
+      out.extend_from_slice(&section.record_count.to_be_bytes());
+      out.extend_from_slice(&section.number.to_be_bytes());
+      out.push(section.projector_number);
+    }
+    out
+  }
+
+  /// Load an index saved with `write_bytes`.
+  pub fn read_bytes(bytes: &[u8]) -> Result<SectionIndex, IldaError> {
+    if bytes.len() < INDEX_HEADER_SIZE || bytes[0..4] != INDEX_MAGIC {
+      return Err(IldaError::InvalidHeader);
+    }
+    if bytes[4] != INDEX_VERSION {
+      return Err(IldaError::Unsupported);
+    }
+
+    let source_len = read_u64(&bytes[5..13]);
+    let checksum = read_u64(&bytes[13..21]);
+    let count = u32::from_be_bytes([bytes[21], bytes[22], bytes[23],
+        bytes[24]]);
+    let body = &bytes[INDEX_HEADER_SIZE..];
+    if body.len() != count as usize * ENTRY_SIZE {
+      return Err(IldaError::InvalidData);
+    }
+
+    let sections = body.chunks(ENTRY_SIZE)
+        .map(|b| {
+          SectionEntry {
+            offset: read_u64(&b[0..8]),
+            format_code: b[8],
+            record_count: u16::from_be_bytes([b[9], b[10]]),
+            number: u16::from_be_bytes([b[11], b[12]]),
+            projector_number: b[13],
+          }
+        })
+        .collect();
+    Ok(SectionIndex::new(sections, source_len, checksum))
+  }
+
+  /// Save the index to a file.
+  pub fn write_file(&self, filename: &str) -> Result<(), IldaError> {
+    let mut file = File::create(filename)?;
+    file.write_all(&self.write_bytes())?;
+    Ok(())
+  }
+
+  /// Load an index from a file.
+  pub fn read_file(filename: &str) -> Result<SectionIndex, IldaError> {
+    let mut contents = Vec::new();
+    File::open(filename)?.read_to_end(&mut contents)?;
+    SectionIndex::read_bytes(&contents)
+  }
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+  /// Index the source and wrap it.
+  pub fn new(mut reader: R) -> Result<IndexedReader<R>, IldaError> {
+    let index = SectionIndex::build(&mut reader)?;
+    Ok(IndexedReader { reader, index })
+  }
+
+  /// Wrap a source using an index built earlier. Fails with
+  /// `IldaError::IndexMismatch` if the index was built from something else.
+  pub fn with_index(mut reader: R, index: SectionIndex)
+      -> Result<IndexedReader<R>, IldaError> {
+    index.verify(&mut reader)?;
+    Ok(IndexedReader { reader, index })
+  }
+
+  /// The index in use.
+  pub fn get_index(&self) -> &SectionIndex {
+    &self.index
+  }
+
+  /// The number of frames.
+  pub fn frame_count(&self) -> usize {
+    self.index.frame_count()
+  }
+
+  /// Give back the underlying source.
+  pub fn into_inner(self) -> R {
+    self.reader
+  }
+
+  /// Position the source at the header of the given frame, returning its
+  /// byte offset.
+  pub fn seek_frame(&mut self, position: usize) -> Result<u64, IldaError> {
+    let offset = self.index.get_frame(position)
+        .ok_or(IldaError::NoData)?
+        .offset;
+    self.reader.seek(SeekFrom::Start(offset))?;
+    Ok(offset)
+  }
+
+  /// Read and decode the given frame. Indexed frames are colored with the
+  /// palette that precedes them in the file.
+  pub fn read_frame(&mut self, position: usize) -> Result<Frame, IldaError> {
+    let section = *self.index.get_frame(position).ok_or(IldaError::NoData)?;
+    let mut bytes = Vec::new();
+
+    if section.get_format().is_indexed() {
+      if let Some(palette) = self.index.get_frame_palette(position).cloned() {
+        self.read_section(&palette, &mut bytes)?;
+      }
+    }
+    self.read_section(&section, &mut bytes)?;
+
+    let entries = read_bytes(&bytes)?;
+    Animation::from_entries(entries)?
+        .into_frames()
+        .pop()
+        .ok_or(IldaError::NoData)
+  }
+
+  fn read_section(&mut self, section: &SectionEntry, out: &mut Vec<u8>)
+      -> Result<(), IldaError> {
+    let start = out.len();
+    out.resize(start + section.byte_len()? as usize, 0);
+    self.reader.seek(SeekFrom::Start(section.offset))?;
+    self.reader.read_exact(&mut out[start..])
+        .map_err(|_| IldaError::InvalidData)
+  }
+}
+
+// Checksum every header of a source.
+fn read_checksum<R: Read + Seek>(reader: &mut R, sections: &[SectionEntry])
+    -> Result<u64, IldaError> {
+  let mut checksum = FNV_OFFSET_BASIS;
+  for section in sections {
+    let mut bytes = [0u8; HEADER_SIZE];
+    reader.seek(SeekFrom::Start(section.offset))?;
+    reader.read_exact(&mut bytes).map_err(|_| IldaError::InvalidHeader)?;
+    checksum = hash(checksum, &bytes);
+  }
+  Ok(checksum)
+}
+
+// Continue a 64-bit FNV-1a hash over more bytes.
+fn hash(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, &byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+  let mut array = [0u8; 8];
+  array.copy_from_slice(bytes);
+  u64::from_be_bytes(array)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use data::ColorPalette;
+  use data::IldaEntry;
+  use data::IndexedPoint2d;
+  use std::io::Cursor;
+  use writer::write_bytes;
+
+  fn frame_entries(number: u16, points: u16) -> Vec<IldaEntry> {
+    let mut header = Header::new(1);
+    header.record_count = points;
+    header.number = number;
+    let mut entries = vec![IldaEntry::HeaderEntry(header)];
+    for i in 0..points {
+      entries.push(IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+        x: number as i16,
+        y: i as i16,
+        status_code: 0,
+        color_index: 1,
+      }));
+    }
+    entries
+  }
+
+  fn palette_entries(color: ColorPalette) -> Vec<IldaEntry> {
+    let mut header = Header::new(2);
+    header.record_count = 2;
+    vec![
+      IldaEntry::HeaderEntry(header),
+      IldaEntry::ColorPaletteEntry(ColorPalette { r: 0, g: 0, b: 0 }),
+      IldaEntry::ColorPaletteEntry(color),
+    ]
+  }
+
+  fn test_bytes() -> Vec<u8> {
+    let mut entries = frame_entries(0, 3);
+    entries.extend(palette_entries(ColorPalette { r: 10, g: 20, b: 30 }));
+    entries.extend(frame_entries(1, 1));
+    entries.extend(frame_entries(2, 4));
+    entries.push(IldaEntry::HeaderEntry(Header::new(1)));
+    write_bytes(&entries)
+  }
+
+  #[test]
+  fn test_build() {
+    let bytes = test_bytes();
+    let index = SectionIndex::build(&mut Cursor::new(&bytes)).unwrap();
+
+    assert_eq!(5, index.get_sections().len());
+    assert_eq!(4, index.frame_count());
+    assert_eq!(88, index.get_frame(1).unwrap().offset);
+    assert_eq!(2, index.get_frame(2).unwrap().number);
+    assert!(index.get_frame(4).is_none());
+    assert!(index.get_frame_palette(0).is_none());
+    assert_eq!(50, index.get_frame_palette(2).unwrap().offset);
+    assert_eq!(index, SectionIndex::from_bytes(&bytes).unwrap());
+  }
+
+  #[test]
+  fn test_read_frame() {
+    let bytes = test_bytes();
+    let animation = Animation::read_bytes(&bytes).unwrap();
+    let mut reader = IndexedReader::new(Cursor::new(&bytes)).unwrap();
+
+    for i in (0..3).rev() {
+      let frame = reader.read_frame(i).unwrap();
+      let expected = animation.get_frame(i).unwrap();
+      assert_eq!(expected.point_count(), frame.point_count());
+      let point = frame.get_point(0).unwrap();
+      assert_eq!(expected.get_point(0).unwrap().x, point.x);
+      assert_eq!(expected.get_point(0).unwrap().r, point.r);
+    }
+    assert_eq!(10, reader.read_frame(1).unwrap().get_point(0).unwrap().r);
+
+    assert_eq!(126, reader.seek_frame(2).unwrap());
+    assert_eq!(0, reader.read_frame(3).unwrap().point_count());
+    assert!(reader.seek_frame(4).is_err());
+    assert!(reader.read_frame(4).is_err());
+  }
+
+  #[test]
+  fn test_truncated() {
+    let mut bytes = test_bytes();
+    bytes.truncate(85);
+    let result = SectionIndex::build(&mut Cursor::new(&bytes));
+    assert!(matches!(result, Err(IldaError::InvalidData)));
+  }
+
+  #[test]
+  fn test_persist() {
+    let index = SectionIndex::from_bytes(&test_bytes()).unwrap();
+    let saved = index.write_bytes();
+    assert_eq!(INDEX_HEADER_SIZE + 5 * ENTRY_SIZE, saved.len());
+    assert_eq!(index, SectionIndex::read_bytes(&saved).unwrap());
+
+    assert!(SectionIndex::read_bytes(&saved[..20]).is_err());
+    assert!(SectionIndex::read_bytes(b"ILDA").is_err());
+  }
+
+  #[test]
+  fn test_with_index() {
+    let bytes = test_bytes();
+    let index = SectionIndex::from_bytes(&bytes).unwrap();
+    assert_eq!(bytes.len() as u64, index.source_len());
+
+    let mut reader =
+        IndexedReader::with_index(Cursor::new(&bytes), index.clone()).unwrap();
+    assert_eq!(4, reader.read_frame(2).unwrap().point_count());
+
+    // A different file, or the same length with another header.
+    let mut longer = bytes.clone();
+    longer.extend(write_bytes(&frame_entries(3, 1)));
+    let result = IndexedReader::with_index(Cursor::new(&longer), index.clone());
+    assert!(matches!(result, Err(IldaError::IndexMismatch)));
+
+    let mut renamed = bytes.clone();
+    renamed[8] = b'x';
+    let result =
+        IndexedReader::with_index(Cursor::new(&renamed), index.clone());
+    assert!(matches!(result, Err(IldaError::IndexMismatch)));
+
+    let mut renumbered = bytes.clone();
+    let middle = index.get_frame(1).unwrap().offset as usize;
+    renumbered[middle + 27] ^= 1;
+    let result = IndexedReader::with_index(Cursor::new(&renumbered), index);
+    assert!(matches!(result, Err(IldaError::IndexMismatch)));
+  }
+}
@@ -6,9 +6,9 @@
 //! sequentially in order to render them as a static figure or animation.
 //!
 //! This library contains both a high-level and low-level interface for reading
-//! ILDA files. The high-level interface is recommended, but the low level
-//! API may be used in the future to serialize frames back into binary ILDA
-//! files (TODO).
+//! ILDA files. The high-level interface is recommended. Either may be used to
+//! write binary ILDA files back out, with `Animation::write_file` or the
+//! `writer` module.
 
 #![deny(dead_code)]
 #![deny(missing_docs)]
@@ -16,16 +16,41 @@
 #![deny(unused_extern_crates)]
 #![deny(unused_imports)]
 #![deny(unused_qualifications)]
-#![deny(unused_qualifications)]
 
 extern crate point;
 
+#[cfg(test)] extern crate image;
+#[cfg(feature = "mmap")] extern crate memmap2;
+#[cfg(feature = "rayon")] extern crate rayon;
+#[cfg(feature = "serde")] extern crate serde;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde_derive;
+#[cfg(any(feature = "profile-files", all(test, feature = "serde")))]
+extern crate serde_json;
+#[cfg(feature = "profile-files")] extern crate toml;
+
 pub mod animation;
+pub mod convert;
+pub mod csv;
+pub mod dac;
 pub mod data;
+pub mod diff;
+pub mod index;
 pub mod limit;
+pub mod morph;
+pub mod optimize;
+pub mod palette;
 pub mod parser;
+pub mod playback;
+pub mod profile;
+pub mod projector;
+pub mod quantize;
+pub mod render;
+pub mod source;
+pub mod text;
+pub mod validate;
+pub mod view;
+pub mod writer;
 
-mod color;
 mod error;
 
 pub use error::IldaError;
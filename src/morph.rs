@@ -0,0 +1,195 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Tweening between two frames. Frames with differing point counts are
+//! resampled to a common length so that every point in the source frame has a
+//! corresponding point in the destination frame, and the in-between frames are
+//! produced by interpolating position and color along an easing curve.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+
+/// Easing curves that control the rate of change over a morph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+  /// Constant rate of change.
+  Linear,
+  /// Starts slowly and accelerates (quadratic).
+  EaseIn,
+  /// Starts quickly and decelerates (quadratic).
+  EaseOut,
+  /// Accelerates through the first half and decelerates through the second.
+  EaseInOut,
+}
+
+impl Easing {
+  /// Map a linear progress value in `[0, 1]` onto the curve.
+  pub fn apply(&self, t: f64) -> f64 {
+    let t = t.clamp(0.0, 1.0);
+    match *self {
+      Easing::Linear => t,
+      Easing::EaseIn => t * t,
+      Easing::EaseOut => t * (2.0 - t),
+      Easing::EaseInOut => {
+        if t < 0.5 {
+          2.0 * t * t
+        } else {
+          -1.0 + (4.0 - 2.0 * t) * t
+        }
+      },
+    }
+  }
+}
+
+/// Produce `steps` in-between frames that morph `from` into `to`. The source
+/// and destination frames themselves are not included in the output.
+///
+/// Both frames are resampled to the larger of the two point counts. Positions
+/// and colors are interpolated; the blanking bit switches over from the source
+/// to the destination halfway through the (eased) transition.
+pub fn morph(from: &Frame, to: &Frame, steps: usize, easing: Easing)
+    -> Result<Animation, IldaError> {
+  if steps == 0 || from.point_count() == 0 || to.point_count() == 0 {
+    return Err(IldaError::NoData);
+  }
+
+  let count = from.point_count().max(to.point_count());
+  let from_points = resample(from, count);
+  let to_points = resample(to, count);
+
+  let mut frames = Vec::with_capacity(steps);
+
+  for step in 1..(steps + 1) {
+    let t = easing.apply(step as f64 / (steps + 1) as f64);
+    let points = from_points.iter()
+        .zip(to_points.iter())
+        .map(|(a, b)| interpolate(a, b, t))
+        .collect();
+    frames.push(Frame::new(points));
+  }
+
+  Ok(Animation::new(frames))
+}
+
+/// Resample the points of a frame to exactly `count` points, preserving their
+/// order. Points are repeated when upsampling and dropped when downsampling,
+/// so blanking and colors are never invented.
+pub fn resample(frame: &Frame, count: usize) -> Vec<SimplePoint> {
+  let points = frame.get_points();
+
+  if points.is_empty() || count == 0 {
+    return Vec::new();
+  }
+
+  (0..count)
+      .map(|i| points[i * points.len() / count])
+      .collect()
+}
+
+/// Interpolate between two points, where `t` is in `[0, 1]`.
+pub fn interpolate(a: &SimplePoint, b: &SimplePoint, t: f64) -> SimplePoint {
+  SimplePoint {
+    x: lerp(a.x as f64, b.x as f64, t) as i16,
+    y: lerp(a.y as f64, b.y as f64, t) as i16,
+    r: lerp(a.r as f64, b.r as f64, t) as u8,
+    g: lerp(a.g as f64, b.g as f64, t) as u8,
+    b: lerp(a.b as f64, b.b as f64, t) as u8,
+    is_blank: if t < 0.5 { a.is_blank } else { b.is_blank },
+  }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+  (a + (b - a) * t).round()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_easing_endpoints() {
+    for easing in &[Easing::Linear, Easing::EaseIn, Easing::EaseOut,
+        Easing::EaseInOut] {
+      assert_eq!(0.0, easing.apply(0.0));
+      assert_eq!(1.0, easing.apply(1.0));
+      assert_eq!(0.0, easing.apply(-1.0));
+      assert_eq!(1.0, easing.apply(2.0));
+    }
+
+    assert_eq!(0.5, Easing::Linear.apply(0.5));
+    assert_eq!(0.25, Easing::EaseIn.apply(0.5));
+    assert_eq!(0.75, Easing::EaseOut.apply(0.5));
+    assert_eq!(0.5, Easing::EaseInOut.apply(0.5));
+  }
+
+  #[test]
+  fn test_resample() {
+    let frame = Frame::new(vec![point(0, 0), point(1, 1), point(2, 2)]);
+
+    let values: Vec<_> = resample(&frame, 6).iter().map(|p| p.r).collect();
+    assert_eq!(vec![0, 0, 1, 1, 2, 2], values);
+
+    let values: Vec<_> = resample(&frame, 2).iter().map(|p| p.r).collect();
+    assert_eq!(vec![0, 1], values);
+
+    assert!(resample(&frame, 0).is_empty());
+  }
+
+  #[test]
+  fn test_interpolate() {
+    let mut a = point(-100, 0);
+    let b = point(100, 200);
+    a.is_blank = true;
+
+    let mid = interpolate(&a, &b, 0.5);
+    assert_eq!(0, mid.x);
+    assert_eq!(100, mid.r);
+    assert!(!mid.is_blank);
+
+    let early = interpolate(&a, &b, 0.25);
+    assert_eq!(-50, early.x);
+    assert_eq!(50, early.r);
+    assert!(early.is_blank);
+  }
+
+  #[test]
+  fn test_morph() {
+    let from = Frame::new(vec![point(0, 0)]);
+    let to = Frame::new(vec![point(300, 255), point(400, 255)]);
+
+    let animation = morph(&from, &to, 2, Easing::Linear).unwrap();
+    assert_eq!(2, animation.frame_count());
+
+    let first = animation.get_frame(0).unwrap();
+    assert_eq!(2, first.point_count());
+    assert_eq!(100, first.get_point(0).unwrap().x);
+    assert_eq!(133, first.get_point(1).unwrap().x);
+    assert_eq!(85, first.get_point(0).unwrap().r);
+
+    let second = animation.get_frame(1).unwrap();
+    assert_eq!(200, second.get_point(0).unwrap().x);
+    assert_eq!(267, second.get_point(1).unwrap().x);
+  }
+
+  #[test]
+  fn test_morph_no_data() {
+    let empty = Frame::new(Vec::new());
+    let frame = Frame::new(vec![point(0, 0)]);
+
+    assert!(morph(&empty, &frame, 1, Easing::Linear).is_err());
+    assert!(morph(&frame, &empty, 1, Easing::Linear).is_err());
+    assert!(morph(&frame, &frame, 0, Easing::Linear).is_err());
+  }
+
+  fn point(x: i16, color: u8) -> SimplePoint {
+    SimplePoint {
+      x,
+      y: 0,
+      r: color,
+      g: color,
+      b: color,
+      is_blank: false,
+    }
+  }
+}
@@ -0,0 +1,301 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Path optimization for smoother, brighter output on real scanners.
+//!
+//! A frame is broken into lit paths. The paths can be reordered to shorten
+//! the blanked moves between them and their long lines subdivided, and the
+//! frame is then rebuilt with dwell points where the laser turns on and off
+//! so the galvos have time to settle.
+
+use animation::Animation;
+use animation::Frame;
+use point::SimplePoint;
+
+/// Optimization settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Optimizer {
+  /// Reorder paths, and reverse them if needed, to shorten blanked moves.
+  pub reorder: bool,
+  /// Subdivide lit lines longer than this many units.
+  pub max_lit_step: Option<u16>,
+  /// Subdivide blanked moves longer than this many units.
+  pub max_blank_step: Option<u16>,
+  /// Blank points held at the start of each path before turning on.
+  pub start_dwell: usize,
+  /// Lit points held at the end of each path before turning off.
+  pub end_dwell: usize,
+}
+
+impl Default for Optimizer {
+  fn default() -> Optimizer {
+    Optimizer {
+      reorder: true,
+      max_lit_step: None,
+      max_blank_step: None,
+      start_dwell: 3,
+      end_dwell: 2,
+    }
+  }
+}
+
+impl Optimizer {
+  /// Optimize the points of a frame. Frames with no lit points are returned
+  /// unchanged.
+  pub fn optimize_points(&self, points: &[SimplePoint]) -> Vec<SimplePoint> {
+    let mut paths = paths(points);
+    if paths.is_empty() {
+      return points.to_vec();
+    }
+
+    if self.reorder {
+      paths = reorder(paths);
+    }
+
+    if let Some(step) = self.max_lit_step {
+      paths = paths.iter().map(|path| subdivide(path, step)).collect();
+    }
+
+    let mut out = Vec::new();
+    for path in paths.iter() {
+      let start = path[0];
+
+      if let (Some(step), Some(&previous)) = (self.max_blank_step, out.last()) {
+        let travel = subdivide(&[blank(&previous), blank(&start)], step);
+        out.extend_from_slice(&travel[1..travel.len() - 1]);
+      }
+
+      for _ in 0..self.start_dwell.max(1) {
+        out.push(blank(&start));
+      }
+      out.extend_from_slice(path);
+      let end = path[path.len() - 1];
+      for _ in 0..self.end_dwell {
+        out.push(end);
+      }
+    }
+
+    out
+  }
+
+  /// Optimize a frame, keeping its name and other metadata.
+  pub fn optimize_frame(&self, frame: &Frame) -> Frame {
+    let mut out = frame.clone();
+    out.set_points(self.optimize_points(frame.get_points()));
+    out
+  }
+
+  /// Optimize every frame of an animation.
+  pub fn optimize_animation(&self, animation: &Animation) -> Animation {
+    Animation::new(animation.into_frame_iter()
+        .map(|frame| self.optimize_frame(frame))
+        .collect())
+  }
+}
+
+/// Split points into lit paths. Each path starts where the laser turns on,
+/// which may be the position of a blanked point, and every point in the path
+/// is lit.
+pub fn paths(points: &[SimplePoint]) -> Vec<Vec<SimplePoint>> {
+  let mut paths = Vec::new();
+  let mut current: Vec<SimplePoint> = Vec::new();
+
+  for (i, point) in points.iter().enumerate() {
+    if point.is_blank {
+      if !current.is_empty() {
+        paths.push(current);
+        current = Vec::new();
+      }
+      continue;
+    }
+
+    if current.is_empty() {
+      // The line into the first lit point starts at the previous position.
+      let mut anchor = *point;
+      if i > 0 {
+        anchor.x = points[i - 1].x;
+        anchor.y = points[i - 1].y;
+      }
+      current.push(anchor);
+      if i == 0 {
+        continue;
+      }
+    }
+    current.push(*point);
+  }
+
+  if !current.is_empty() {
+    paths.push(current);
+  }
+  paths
+}
+
+/// Order paths by nearest neighbor, starting with the first path and
+/// reversing paths whose far end is closer.
+pub fn reorder(paths: Vec<Vec<SimplePoint>>) -> Vec<Vec<SimplePoint>> {
+  let mut remaining = paths;
+  if remaining.is_empty() {
+    return remaining;
+  }
+
+  let mut ordered = vec![remaining.remove(0)];
+
+  while !remaining.is_empty() {
+    let position = {
+      let last = &ordered[ordered.len() - 1];
+      last[last.len() - 1]
+    };
+
+    let mut best = (0, false, u64::MAX);
+    for (i, path) in remaining.iter().enumerate() {
+      let to_start = distance_squared(&position, &path[0]);
+      let to_end = distance_squared(&position, &path[path.len() - 1]);
+      if to_start < best.2 {
+        best = (i, false, to_start);
+      }
+      if to_end < best.2 {
+        best = (i, true, to_end);
+      }
+    }
+
+    let path = remaining.remove(best.0);
+    ordered.push(if best.1 { reverse(&path) } else { path });
+  }
+
+  ordered
+}
+
+/// Reverse a path. Each line is drawn in the color of the point it travels
+/// to, so colors shift by one point to keep every line its original color.
+pub fn reverse(path: &[SimplePoint]) -> Vec<SimplePoint> {
+  let mut out: Vec<SimplePoint> = path.iter().rev().cloned().collect();
+  for i in (1..out.len()).rev() {
+    let (r, g, b) = (out[i - 1].r, out[i - 1].g, out[i - 1].b);
+    out[i].r = r;
+    out[i].g = g;
+    out[i].b = b;
+  }
+  out
+}
+
+/// Insert evenly spaced points so that no step is longer than `max_step`.
+/// Inserted points take the color and blanking of the point they lead to.
+pub fn subdivide(points: &[SimplePoint], max_step: u16)
+    -> Vec<SimplePoint> {
+  let max_step = max_step.max(1) as f64;
+  let mut out = Vec::with_capacity(points.len());
+
+  for (i, point) in points.iter().enumerate() {
+    if i > 0 {
+      let previous = points[i - 1];
+      let length = (distance_squared(&previous, point) as f64).sqrt();
+      let steps = (length / max_step).ceil() as usize;
+      for step in 1..steps {
+        let t = step as f64 / steps as f64;
+        let mut between = *point;
+        between.x = lerp(previous.x, point.x, t);
+        between.y = lerp(previous.y, point.y, t);
+        out.push(between);
+      }
+    }
+    out.push(*point);
+  }
+
+  out
+}
+
+fn blank(point: &SimplePoint) -> SimplePoint {
+  SimplePoint { x: point.x, y: point.y, r: 0, g: 0, b: 0, is_blank: true }
+}
+
+fn lerp(a: i16, b: i16, t: f64) -> i16 {
+  (a as f64 + (b as f64 - a as f64) * t).round() as i16
+}
+
+fn distance_squared(a: &SimplePoint, b: &SimplePoint) -> u64 {
+  let dx = a.x as i64 - b.x as i64;
+  let dy = a.y as i64 - b.y as i64;
+  (dx * dx + dy * dy) as u64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lit(x: i16, y: i16, r: u8) -> SimplePoint {
+    SimplePoint { x, y, r, g: 0, b: 0, is_blank: false }
+  }
+
+  fn dark(x: i16, y: i16) -> SimplePoint {
+    SimplePoint { x, y, r: 0, g: 0, b: 0, is_blank: true }
+  }
+
+  fn positions(points: &[SimplePoint]) -> Vec<(i16, i16, bool)> {
+    points.iter().map(|p| (p.x, p.y, p.is_blank)).collect()
+  }
+
+  #[test]
+  fn test_paths() {
+    let points = [lit(0, 0, 1), lit(10, 0, 2), dark(50, 0), lit(60, 0, 3),
+        dark(0, 0)];
+    let paths = paths(&points);
+
+    assert_eq!(2, paths.len());
+    assert_eq!(vec![(0, 0, false), (10, 0, false)], positions(&paths[0]));
+    assert_eq!(vec![(50, 0, false), (60, 0, false)], positions(&paths[1]));
+    assert_eq!(3, paths[1][0].r);
+  }
+
+  #[test]
+  fn test_reverse_keeps_line_colors() {
+    let path = [lit(0, 0, 1), lit(10, 0, 2), lit(20, 0, 3)];
+    let reversed = reverse(&path);
+    assert_eq!(vec![(20, 0, false), (10, 0, false), (0, 0, false)],
+        positions(&reversed));
+    // The line 20 -> 10 was drawn in 3, and 10 -> 0 in 2.
+    assert_eq!(vec![3, 3, 2], reversed.iter().map(|p| p.r).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn test_reorder() {
+    let paths = vec![
+      vec![lit(0, 0, 1), lit(10, 0, 1)],
+      vec![lit(1000, 0, 2), lit(2000, 0, 2)],
+      vec![lit(30, 0, 3), lit(20, 0, 3)],
+    ];
+    let ordered = reorder(paths);
+    assert_eq!(vec![0, 20, 1000],
+        ordered.iter().map(|p| p[0].x).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn test_subdivide() {
+    let points = subdivide(&[dark(0, 0), lit(100, 0, 5)], 30);
+    assert_eq!(vec![(0, 0, true), (25, 0, false), (50, 0, false),
+        (75, 0, false), (100, 0, false)], positions(&points));
+    assert_eq!(5, points[1].r);
+  }
+
+  #[test]
+  fn test_optimize_points() {
+    let optimizer = Optimizer {
+      reorder: false,
+      max_lit_step: None,
+      max_blank_step: Some(50),
+      start_dwell: 2,
+      end_dwell: 1,
+    };
+    let points = [dark(0, 0), lit(10, 0, 1), dark(100, 0), lit(110, 0, 2)];
+
+    assert_eq!(vec![
+      (0, 0, true), (0, 0, true), (0, 0, false), (10, 0, false),
+      (10, 0, false),
+      (55, 0, true),
+      (100, 0, true), (100, 0, true), (100, 0, false), (110, 0, false),
+      (110, 0, false),
+    ], positions(&optimizer.optimize_points(&points)));
+
+    let blank = [dark(1, 1)];
+    assert_eq!(positions(&blank),
+        positions(&optimizer.optimize_points(&blank)));
+  }
+}
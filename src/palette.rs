@@ -0,0 +1,287 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Color palettes for indexed color points (formats 0 and 1).
+//!
+//! Files may carry their own palette in a format 2 section. Otherwise, the
+//! standard 64 color ILDA palette applies. Palettes may also be kept in
+//! plain text files, with one `r g b` triple per line.
+
+use data::ColorPalette;
+use data::Header;
+use data::IldaEntry;
+use error::IldaError;
+use std::fs::File;
+use std::io::Read;
+
+/// The largest number of colors that can be indexed.
+pub const MAX_COLORS: usize = 256;
+
+/// The standard ILDA palette.
+const STANDARD_COLORS: [(u8, u8, u8); 64] = [
+  (255, 0, 0), (255, 16, 0), (255, 32, 0), (255, 48, 0), // Red
+  (255, 64, 0), (255, 80, 0), (255, 96, 0), (255, 112, 0),
+  (255, 128, 0), (255, 144, 0), (255, 160, 0), (255, 176, 0),
+  (255, 192, 0), (255, 208, 0), (255, 224, 0), (255, 240, 0),
+  (255, 255, 0), (224, 255, 0), (192, 255, 0), (160, 255, 0), // Yellow
+  (128, 255, 0), (96, 255, 0), (64, 255, 0), (32, 255, 0),
+  (0, 255, 0), (0, 255, 36), (0, 255, 73), (0, 255, 109), // Green
+  (0, 255, 146), (0, 255, 182), (0, 255, 219), (0, 255, 255), // Cyan
+  (0, 227, 255), (0, 198, 255), (0, 170, 255), (0, 142, 255),
+  (0, 113, 255), (0, 85, 255), (0, 56, 255), (0, 28, 255),
+  (0, 0, 255), (32, 0, 255), (64, 0, 255), (96, 0, 255), // Blue
+  (128, 0, 255), (160, 0, 255), (192, 0, 255), (224, 0, 255),
+  (255, 0, 255), (255, 32, 255), (255, 64, 255), (255, 96, 255), // Magenta
+  (255, 128, 255), (255, 160, 255), (255, 192, 255), (255, 224, 255),
+  (255, 255, 255), (255, 224, 224), (255, 192, 192), (255, 160, 160), // White
+  (255, 128, 128), (255, 96, 96), (255, 64, 64), (255, 32, 32),
+];
+
+/// An ordered list of up to 256 colors.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Palette {
+  colors: Vec<ColorPalette>,
+}
+
+impl Palette {
+  /// Create a palette from the given colors. Fails with
+  /// `IldaError::InvalidData` if there are more than 256, since colors past
+  /// the 256th can't be indexed.
+  pub fn new(colors: Vec<ColorPalette>) -> Result<Palette, IldaError> {
+    if colors.len() > MAX_COLORS {
+      return Err(IldaError::InvalidData);
+    }
+    Ok(Palette { colors })
+  }
+
+  /// Create a palette from colors read from a file, dropping any past the
+  /// 256th. Validation reports oversized palettes; readers just ignore the
+  /// colors that can't be indexed.
+  pub fn truncated(mut colors: Vec<ColorPalette>) -> Palette {
+    colors.truncate(MAX_COLORS);
+    Palette { colors }
+  }
+
+  /// The standard 64 color ILDA palette.
+  pub fn standard() -> Palette {
+    Palette {
+      colors: STANDARD_COLORS.iter()
+          .map(|&(r, g, b)| ColorPalette { r, g, b })
+          .collect(),
+    }
+  }
+
+  /// Read the first color palette (format 2) section from parsed entries.
+  pub fn from_entries(entries: &[IldaEntry]) -> Result<Palette, IldaError> {
+    let mut colors = Vec::new();
+    let mut in_palette = false;
+
+    for entry in entries {
+      match *entry {
+        IldaEntry::HeaderEntry(ref header) => {
+          if in_palette {
+            break;
+          }
+          in_palette = header.format_code == 2;
+        },
+        IldaEntry::ColorPaletteEntry(ref color) if in_palette => {
+          colors.push(color.clone());
+        },
+        _ => {},
+      }
+    }
+
+    if colors.is_empty() {
+      return Err(IldaError::NoData);
+    }
+
+    Ok(Palette::truncated(colors))
+  }
+
+  /// Parse a palette from text with one `r g b` triple per line. Values may
+  /// be separated by whitespace or commas. Blank lines and lines starting
+  /// with `#` are ignored.
+  pub fn from_text(text: &str) -> Result<Palette, IldaError> {
+    let mut colors = Vec::new();
+
+    for line in text.lines() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let values: Vec<u8> = line.split(|c: char| c == ',' || c.is_whitespace())
+          .filter(|value| !value.is_empty())
+          .map(|value| value.parse().map_err(|_| IldaError::InvalidData))
+          .collect::<Result<_, _>>()?;
+
+      if values.len() != 3 {
+        return Err(IldaError::InvalidData);
+      }
+
+      colors.push(ColorPalette { r: values[0], g: values[1], b: values[2] });
+    }
+
+    if colors.is_empty() {
+      return Err(IldaError::NoData);
+    }
+
+    Palette::new(colors)
+  }
+
+  /// Read a palette from a text file. See `from_text`.
+  pub fn read_text_file(filename: &str) -> Result<Palette, IldaError> {
+    let mut text = String::new();
+    File::open(filename)?.read_to_string(&mut text)?;
+    Palette::from_text(&text)
+  }
+
+  /// Write the palette as text, one `r g b` triple per line.
+  pub fn to_text(&self) -> String {
+    self.colors.iter()
+        .map(|color| format!("{} {} {}\n", color.r, color.g, color.b))
+        .collect()
+  }
+
+  /// Convert the palette into a color palette (format 2) section with the
+  /// given palette number.
+  pub fn to_entries(&self, number: u16) -> Vec<IldaEntry> {
+    let mut header = Header::new(2);
+    header.record_count = self.colors.len() as u16;
+    header.number = number;
+
+    let mut entries = Vec::with_capacity(self.colors.len() + 1);
+    entries.push(IldaEntry::HeaderEntry(header));
+    for color in self.colors.iter() {
+      entries.push(IldaEntry::ColorPaletteEntry(color.clone()));
+    }
+    entries
+  }
+
+  /// Get a reference to the colors in the palette.
+  pub fn get_colors(&self) -> &Vec<ColorPalette> {
+    &self.colors
+  }
+
+  /// Get the color at the given index, if it exists.
+  pub fn get(&self, index: u8) -> Option<&ColorPalette> {
+    self.colors.get(index as usize)
+  }
+
+  /// Get the color at the given index. Indices outside the palette are
+  /// white.
+  pub fn color(&self, index: u8) -> ColorPalette {
+    self.get(index)
+        .cloned()
+        .unwrap_or(ColorPalette { r: 255, g: 255, b: 255 })
+  }
+
+  /// Find the index of the closest color, by squared RGB distance.
+  pub fn nearest(&self, r: u8, g: u8, b: u8) -> u8 {
+    let distance = |color: &ColorPalette| {
+      let dr = color.r as i32 - r as i32;
+      let dg = color.g as i32 - g as i32;
+      let db = color.b as i32 - b as i32;
+      dr * dr + dg * dg + db * db
+    };
+
+    self.colors.iter()
+        .enumerate()
+        .min_by_key(|&(_, color)| distance(color))
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+  }
+
+  /// The number of colors in the palette.
+  pub fn len(&self) -> usize {
+    self.colors.len()
+  }
+
+  /// Whether the palette has no colors.
+  pub fn is_empty(&self) -> bool {
+    self.colors.is_empty()
+  }
+}
+
+impl Default for Palette {
+  fn default() -> Palette {
+    Palette::standard()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::read_bytes;
+  use writer::write_bytes;
+
+  #[test]
+  fn test_standard() {
+    let palette = Palette::standard();
+    assert_eq!(64, palette.len());
+
+    // Known colors
+    assert_eq!(ColorPalette { r: 255, g: 0, b: 0 }, palette.color(0));
+    assert_eq!(ColorPalette { r: 255, g: 255, b: 0 }, palette.color(16));
+    assert_eq!(ColorPalette { r: 0, g: 255, b: 0 }, palette.color(24));
+    assert_eq!(ColorPalette { r: 0, g: 255, b: 255 }, palette.color(31));
+    assert_eq!(ColorPalette { r: 0, g: 0, b: 255 }, palette.color(40));
+    assert_eq!(ColorPalette { r: 255, g: 0, b: 255 }, palette.color(48));
+    assert_eq!(ColorPalette { r: 255, g: 255, b: 255 }, palette.color(56));
+
+    // Unknown colors
+    assert_eq!(None, palette.get(64));
+    assert_eq!(ColorPalette { r: 255, g: 255, b: 255 }, palette.color(64));
+    assert_eq!(ColorPalette { r: 255, g: 255, b: 255 }, palette.color(127));
+  }
+
+  #[test]
+  fn test_nearest() {
+    let palette = Palette::standard();
+    assert_eq!(0, palette.nearest(250, 5, 0));
+    assert_eq!(40, palette.nearest(0, 0, 200));
+    assert_eq!(56, palette.nearest(255, 250, 250));
+    assert_eq!(0, Palette::new(Vec::new()).unwrap().nearest(1, 2, 3));
+  }
+
+  #[test]
+  fn test_too_many_colors() {
+    let colors = vec![ColorPalette { r: 1, g: 2, b: 3 }; MAX_COLORS + 1];
+    assert!(Palette::new(colors.clone()).is_err());
+    assert_eq!(MAX_COLORS, Palette::new(colors[1..].to_vec()).unwrap().len());
+    assert_eq!(MAX_COLORS, Palette::truncated(colors.clone()).len());
+
+    let text = "1 2 3\n".repeat(MAX_COLORS + 1);
+    assert!(Palette::from_text(&text).is_err());
+
+    // Files are read anyway, ignoring the extra colors.
+    let mut header = Header::new(2);
+    header.record_count = colors.len() as u16;
+    let mut entries = vec![IldaEntry::HeaderEntry(header)];
+    entries.extend(colors.into_iter().map(IldaEntry::ColorPaletteEntry));
+    assert_eq!(MAX_COLORS, Palette::from_entries(&entries).unwrap().len());
+  }
+
+  #[test]
+  fn test_text() {
+    let palette = Palette::from_text("# Test\n255 0 0\n\n0,255, 0\n").unwrap();
+    assert_eq!(2, palette.len());
+    assert_eq!(ColorPalette { r: 0, g: 255, b: 0 }, palette.color(1));
+    assert_eq!("255 0 0\n0 255 0\n", palette.to_text());
+
+    assert!(Palette::from_text("255 0").is_err());
+    assert!(Palette::from_text("256 0 0").is_err());
+    assert!(Palette::from_text("# Nothing").is_err());
+  }
+
+  #[test]
+  fn test_entries_round_trip() {
+    let palette = Palette::from_text("1 2 3\n4 5 6\n7 8 9").unwrap();
+    let mut entries = palette.to_entries(0);
+    entries.push(IldaEntry::HeaderEntry(Header::new(2)));
+
+    let read = read_bytes(&write_bytes(&entries)).unwrap();
+    assert_eq!(palette, Palette::from_entries(&read).unwrap());
+    assert!(Palette::from_entries(&read[..1]).is_err());
+  }
+}
@@ -17,6 +17,7 @@ use data::TRUE_COLOR_2D_DATA_SIZE;
 use data::TRUE_COLOR_3D_DATA_SIZE;
 use data::TrueColorPoint2d;
 use data::TrueColorPoint3d;
+use data::decode_name;
 use error::IldaError;
 use std::fs::File;
 use std::io::Read;
@@ -38,7 +39,7 @@ pub fn read_bytes(ilda_bytes: &[u8]) -> Result<Vec<IldaEntry>, IldaError> {
     return Err(IldaError::FileTooSmall);
   }
 
-  enum NextRead { Header, I3d, I2d, Color, Tc3d, Tc2d };
+  enum NextRead { Header, I3d, I2d, Color, Tc3d, Tc2d }
 
   let mut vec = Vec::new();
   let mut i : usize = 0;
@@ -49,8 +50,9 @@ pub fn read_bytes(ilda_bytes: &[u8]) -> Result<Vec<IldaEntry>, IldaError> {
   while i < ilda_bytes.len() {
     match next_read {
       NextRead::Header => {
-        let header = read_header(&ilda_bytes[i .. i + HEADER_SIZE])
-            .map_err(|_| IldaError::InvalidHeader)?;
+        let header = ilda_bytes.get(i .. i + HEADER_SIZE)
+            .ok_or(IldaError::InvalidHeader)
+            .and_then(read_header)?;
         next_read = match header.get_format() {
           Format::Indexed3d => NextRead::I3d,
           Format::Indexed2d => NextRead::I2d,
@@ -65,118 +67,143 @@ pub fn read_bytes(ilda_bytes: &[u8]) -> Result<Vec<IldaEntry>, IldaError> {
       },
       NextRead::I3d => {
         let end = INDEXED_3D_DATA_SIZE * frames_to_read as usize;
-        let points = IndexedPoint3d::read_bytes(&ilda_bytes[i .. i + end])?;
-        let mut entries = points.iter()
-          .map(|x| IldaEntry::IdxPoint3dEntry(x.clone()))
-          .collect();
-        vec.append(&mut entries);
+        let bytes = records(ilda_bytes, i, end)?;
+        let points = IndexedPoint3d::read_bytes(bytes)?;
+        vec.extend(points.into_iter().map(IldaEntry::IdxPoint3dEntry));
         next_read = NextRead::Header;
         i += end;
       },
       NextRead::I2d => {
         let end = INDEXED_2D_DATA_SIZE * frames_to_read as usize;
-        let points = IndexedPoint2d::read_bytes(&ilda_bytes[i .. i + end])?;
-        let mut entries = points.iter()
-          .map(|x| IldaEntry::IdxPoint2dEntry(x.clone()))
-          .collect();
-        vec.append(&mut entries);
+        let bytes = records(ilda_bytes, i, end)?;
+        let points = IndexedPoint2d::read_bytes(bytes)?;
+        vec.extend(points.into_iter().map(IldaEntry::IdxPoint2dEntry));
         next_read = NextRead::Header;
         i += end;
       },
       NextRead::Color => {
         let end = COLOR_PALETTE_SIZE * frames_to_read as usize;
-        let points = ColorPalette::read_bytes(&ilda_bytes[i .. i + end])?;
-        let mut entries = points.iter()
-          .map(|x| IldaEntry::ColorPaletteEntry(x.clone()))
-          .collect();
-        vec.append(&mut entries);
+        let bytes = records(ilda_bytes, i, end)?;
+        let points = ColorPalette::read_bytes(bytes)?;
+        vec.extend(points.into_iter().map(IldaEntry::ColorPaletteEntry));
         next_read = NextRead::Header;
         i += end;
       },
       NextRead::Tc3d => {
         let end = TRUE_COLOR_3D_DATA_SIZE * frames_to_read as usize;
-        let points = TrueColorPoint3d::read_bytes(&ilda_bytes[i .. i + end])?;
-        let mut entries = points.iter()
-          .map(|x| IldaEntry::TcPoint3dEntry(x.clone()))
-          .collect();
-        vec.append(&mut entries);
+        let bytes = records(ilda_bytes, i, end)?;
+        let points = TrueColorPoint3d::read_bytes(bytes)?;
+        vec.extend(points.into_iter().map(IldaEntry::TcPoint3dEntry));
         next_read = NextRead::Header;
         i += end;
       },
       NextRead::Tc2d => {
         let end = TRUE_COLOR_2D_DATA_SIZE * frames_to_read as usize;
-        let points = TrueColorPoint2d::read_bytes(&ilda_bytes[i .. i + end])?;
-        let mut entries = points.iter()
-          .map(|x| IldaEntry::TcPoint2dEntry(x.clone()))
-          .collect();
-        vec.append(&mut entries);
+        let bytes = records(ilda_bytes, i, end)?;
+        let points = TrueColorPoint2d::read_bytes(bytes)?;
+        vec.extend(points.into_iter().map(IldaEntry::TcPoint2dEntry));
         next_read = NextRead::Header;
         i += end;
       },
     };
   }
 
+  // A header at the very end of the data still expects its records.
+  if frames_to_read > 0 && !matches!(next_read, NextRead::Header) {
+    return Err(IldaError::InvalidData);
+  }
+
   Ok(vec)
 }
 
-fn read_header(header_bytes: &[u8]) -> Result<Header, IldaError> {
-  if header_bytes.len() != 32 || &header_bytes[0..4] != &ILDA_HEADER {
+// The bytes of a section's records, if the data isn't truncated.
+fn records(bytes: &[u8], start: usize, len: usize)
+    -> Result<&[u8], IldaError> {
+  bytes.get(start .. start + len).ok_or(IldaError::InvalidData)
+}
+
+/// Read a 32 byte ILDA header.
+pub fn read_header(header_bytes: &[u8]) -> Result<Header, IldaError> {
+  if header_bytes.len() != 32 || header_bytes[0..4] != ILDA_HEADER {
     return Err(IldaError::InvalidHeader);
   }
 
-  let name              = read_name(&header_bytes[8..16]);
-  let company_name      = read_name(&header_bytes[16..24]);
+  let mut raw_name = [0u8; 8];
+  let mut raw_company_name = [0u8; 8];
+  raw_name.copy_from_slice(&header_bytes[8..16]);
+  raw_company_name.copy_from_slice(&header_bytes[16..24]);
+
   let number_of_records = read_u16(&header_bytes[24..26]);
   let frame_number      = read_u16(&header_bytes[26..28]);
   let total_frames      = read_u16(&header_bytes[28..30]);
-  let projector_number  = header_bytes[31];
+  let projector_number  = header_bytes[30];
 
   Ok(Header {
-    reserved: 0, // TODO: Read in.
+    reserved: [header_bytes[4], header_bytes[5], header_bytes[6]],
     format_code: header_bytes[7],
-    name: name,
-    company_name: company_name,
+    name: decode_name(&raw_name),
+    company_name: decode_name(&raw_company_name),
+    raw_name,
+    raw_company_name,
     record_count: number_of_records,
     number: frame_number,
-    total_frames: total_frames,
-    projector_number: projector_number,
-    reserved_2: 0, // TODO: Read in.
+    total_frames,
+    projector_number,
+    reserved_2: header_bytes[31],
   })
 }
 
-fn read_name(bytes: &[u8]) -> Option<String> {
-  let mut name = String::with_capacity(8);
-  for byte in bytes {
-    if *byte == 0 {
-      break;
-    } else if *byte < 31 {
-      continue; // unprintable characters
-    } else {
-      name.push(*byte as char);
-    }
-  }
-  match name.len() {
-    0 => None,
-    _ => Some(name),
-  }
-}
-
 fn read_u16(bytes: &[u8]) -> u16 {
   ((bytes[0] as u16) << 8) | (bytes[1] as u16)
 }
 
 #[cfg(test)]
 mod tests {
-  use super::read_name;
+  use super::ILDA_HEADER;
+  use super::read_bytes;
   use super::read_u16;
+  use data::IldaEntry;
+  use error::IldaError;
+
+  #[test]
+  fn test_truncated() {
+    let mut header = [0u8; 32];
+    header[0..4].copy_from_slice(&ILDA_HEADER);
+    header[7] = 5;
+    header[25] = 2;
+
+    let mut bytes = header.to_vec();
+    bytes.extend_from_slice(&[0u8; 8]);
+    assert!(matches!(read_bytes(&bytes), Err(IldaError::InvalidData)));
+
+    bytes.extend_from_slice(&[0u8; 8]);
+    bytes.extend_from_slice(&header[..16]);
+    assert!(matches!(read_bytes(&bytes), Err(IldaError::InvalidHeader)));
+
+    assert!(matches!(read_bytes(&header), Err(IldaError::InvalidData)));
+    header[25] = 0;
+    assert_eq!(1, read_bytes(&header).unwrap().len());
+  }
 
   #[test]
-  fn test_read_name() {
-    assert_eq!(read_name(&[0, 0, 0, 0]), None);
-    assert_eq!(read_name(&[0, 100, 100, 100]), None);
-    assert_eq!(read_name(&[102, 111, 111]), Some("foo".to_string()));
-    assert_eq!(read_name(&[102, 111, 111, 0, 111]),
-               Some("foo".to_string()));
+  fn test_raw_header_fields() {
+    let mut bytes = [0u8; 32];
+    bytes[0..4].copy_from_slice(&ILDA_HEADER);
+    bytes[4..8].copy_from_slice(&[1, 2, 3, 5]);
+    bytes[8..16].copy_from_slice(b"f\xf6o\x07\0bar");
+    bytes[16..24].copy_from_slice(b"Acme\0\0\0\0");
+    bytes[31] = 4;
+
+    let entries = read_bytes(&bytes).unwrap();
+    let header = match entries[0] {
+      IldaEntry::HeaderEntry(ref header) => header,
+      _ => panic!("expected a header"),
+    };
+    assert_eq!([1, 2, 3], header.reserved);
+    assert_eq!(4, header.reserved_2);
+    assert_eq!(Some("f\u{f6}o"), header.name.as_deref());
+    assert_eq!(Some("Acme"), header.company_name.as_deref());
+    assert_eq!(bytes, header.to_bytes());
   }
 
   #[test]
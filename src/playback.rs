@@ -0,0 +1,470 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Timed playback of animations. An `Animation` is only a list of frames; this
+//! module assigns each frame a duration and maps elapsed time (or a number of
+//! output samples at a given scan rate) onto the frame and point that should
+//! be sent to the projector at that moment.
+
+use animation::Animation;
+use animation::Frame;
+use point::SimplePoint;
+use std::cell::Cell;
+use std::time::Duration;
+use std::time::Instant;
+
+const NANOS_PER_SECOND : u128 = 1_000_000_000;
+
+/// Default playback rate, in frames per second.
+pub const DEFAULT_FRAME_RATE : u32 = 30;
+
+/// Default scan rate, in points per second.
+pub const DEFAULT_POINTS_PER_SECOND : u32 = 30_000;
+
+/// A source of monotonic time. Playback is driven by a clock so that it can be
+/// swapped out for a `ManualClock` in tests.
+pub trait Clock {
+  /// Time elapsed since an arbitrary, fixed origin.
+  fn now(&self) -> Duration;
+}
+
+/// Wall-clock time, measured from when the clock was created.
+#[derive(Clone, Debug)]
+pub struct SystemClock {
+  origin: Instant,
+}
+
+/// A clock that only moves when told to.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+  now: Cell<Duration>,
+}
+
+/// How playback continues once the final frame has been shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackMode {
+  /// Start over from the first frame.
+  Loop,
+  /// Play backwards to the first frame, then forwards again.
+  PingPong,
+  /// Stop after the final frame.
+  OneShot,
+}
+
+/// Plays an animation against a clock.
+pub struct Playback<C: Clock> {
+  animation: Animation,
+  clock: C,
+  start: Duration,
+  mode: PlaybackMode,
+  frame_duration: Duration,
+  holds: Vec<Option<Duration>>,
+  min_scan_time: Duration,
+  points_per_second: u32,
+  timeline: Vec<(usize, u128)>,
+}
+
+impl SystemClock {
+  /// Create a clock whose origin is the current instant.
+  pub fn new() -> SystemClock {
+    SystemClock { origin: Instant::now() }
+  }
+}
+
+impl Default for SystemClock {
+  fn default() -> SystemClock {
+    SystemClock::new()
+  }
+}
+
+impl Clock for SystemClock {
+  fn now(&self) -> Duration {
+    self.origin.elapsed()
+  }
+}
+
+impl ManualClock {
+  /// Create a clock stopped at zero.
+  pub fn new() -> ManualClock {
+    ManualClock::default()
+  }
+
+  /// Move the clock forward.
+  pub fn advance(&self, duration: Duration) {
+    self.now.set(self.now.get() + duration);
+  }
+
+  /// Set the clock to an absolute time.
+  pub fn set(&self, now: Duration) {
+    self.now.set(now);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Duration {
+    self.now.get()
+  }
+}
+
+impl<C: Clock> Playback<C> {
+  /// Create a looping playback of the animation, starting at the clock's
+  /// current time.
+  pub fn new(animation: Animation, clock: C) -> Playback<C> {
+    let holds = vec![None; animation.frame_count()];
+    let start = clock.now();
+    let mut playback = Playback {
+      animation,
+      clock,
+      start,
+      mode: PlaybackMode::Loop,
+      frame_duration: Duration::from_secs(1) / DEFAULT_FRAME_RATE,
+      holds,
+      min_scan_time: Duration::from_secs(0),
+      points_per_second: DEFAULT_POINTS_PER_SECOND,
+      timeline: Vec::new(),
+    };
+    playback.update_timeline();
+    playback
+  }
+
+  /// Get a reference to the animation being played.
+  pub fn get_animation(&self) -> &Animation {
+    &self.animation
+  }
+
+  /// Get a reference to the clock driving playback.
+  pub fn get_clock(&self) -> &C {
+    &self.clock
+  }
+
+  /// Get the playback mode.
+  pub fn get_mode(&self) -> PlaybackMode {
+    self.mode
+  }
+
+  /// Set the playback mode.
+  pub fn set_mode(&mut self, mode: PlaybackMode) {
+    self.mode = mode;
+    self.update_timeline();
+  }
+
+  /// Set the duration of every frame that has no hold time of its own from a
+  /// frame rate. A rate of zero is ignored.
+  pub fn set_frame_rate(&mut self, frames_per_second: u32) {
+    if frames_per_second > 0 {
+      self.frame_duration = Duration::from_secs(1) / frames_per_second;
+      self.update_timeline();
+    }
+  }
+
+  /// Set the duration of every frame that has no hold time of its own.
+  pub fn set_frame_duration(&mut self, duration: Duration) {
+    self.frame_duration = duration;
+    self.update_timeline();
+  }
+
+  /// Hold the frame at the given offset for a specific duration, or clear the
+  /// hold with `None`. Offsets outside the animation are ignored.
+  pub fn set_hold(&mut self, position: usize, hold: Option<Duration>) {
+    if let Some(slot) = self.holds.get_mut(position) {
+      *slot = hold;
+      self.update_timeline();
+    }
+  }
+
+  /// Repeat each frame's scan until at least this much time has passed, even
+  /// if its hold time is shorter.
+  pub fn set_min_scan_time(&mut self, duration: Duration) {
+    self.min_scan_time = duration;
+    self.update_timeline();
+  }
+
+  /// Get the scan rate in points per second.
+  pub fn get_points_per_second(&self) -> u32 {
+    self.points_per_second
+  }
+
+  /// Set the scan rate in points per second. A rate of zero is ignored.
+  pub fn set_points_per_second(&mut self, points_per_second: u32) {
+    if points_per_second > 0 {
+      self.points_per_second = points_per_second;
+      self.update_timeline();
+    }
+  }
+
+  /// Restart playback from the first frame at the clock's current time.
+  pub fn restart(&mut self) {
+    self.start = self.clock.now();
+  }
+
+  /// Time elapsed on the clock since playback started.
+  pub fn elapsed(&self) -> Duration {
+    self.clock.now().checked_sub(self.start)
+        .unwrap_or_else(|| Duration::from_secs(0))
+  }
+
+  /// How long the frame at the given offset is shown for, after repeating its
+  /// scan to satisfy the minimum scan time.
+  pub fn frame_duration(&self, position: usize) -> Option<Duration> {
+    let frame = self.animation.get_frame(position)?;
+    let hold = self.holds[position].unwrap_or(self.frame_duration);
+    let scan = self.scan_time(frame);
+
+    if self.min_scan_time <= hold || scan == 0 {
+      return Some(hold);
+    }
+
+    // Round up to a whole number of scans.
+    let min = self.min_scan_time.as_nanos();
+    let scans = (min + scan - 1) / scan;
+    Some(nanos_to_duration(scans * scan))
+  }
+
+  /// Total duration of a single pass through the animation.
+  pub fn total_duration(&self) -> Duration {
+    (0..self.animation.frame_count())
+        .filter_map(|i| self.frame_duration(i))
+        .fold(Duration::from_secs(0), |acc, d| acc + d)
+  }
+
+  /// Index of the frame that should be shown now, or `None` if playback has
+  /// finished.
+  pub fn current_frame_index(&self) -> Option<usize> {
+    self.frame_index_at(self.elapsed())
+  }
+
+  /// The frame that should be shown now, or `None` if playback has finished.
+  pub fn current_frame(&self) -> Option<&Frame> {
+    self.frame_at(self.elapsed())
+  }
+
+  /// Index of the frame to show at the given time since playback started.
+  pub fn frame_index_at(&self, elapsed: Duration) -> Option<usize> {
+    self.locate(elapsed.as_nanos()).map(|(index, _)| index)
+  }
+
+  /// The frame to show at the given time since playback started.
+  pub fn frame_at(&self, elapsed: Duration) -> Option<&Frame> {
+    self.frame_index_at(elapsed)
+        .and_then(|index| self.animation.get_frame(index))
+  }
+
+  /// The frame to show once `sample` points have been output at the current
+  /// scan rate.
+  pub fn frame_at_sample(&self, sample: u64) -> Option<&Frame> {
+    self.frame_at(self.sample_to_duration(sample))
+  }
+
+  /// The point to output once `sample` points have been output at the
+  /// current scan rate. Frames are scanned repeatedly for as long as they are
+  /// shown, starting from their first point.
+  pub fn point_at_sample(&self, sample: u64) -> Option<&SimplePoint> {
+    let elapsed = self.sample_to_duration(sample).as_nanos();
+    let (index, offset) = self.locate(elapsed)?;
+    let frame = self.animation.get_frame(index)?;
+
+    if frame.point_count() == 0 {
+      return None;
+    }
+
+    let point = offset * self.points_per_second as u128 / NANOS_PER_SECOND;
+    frame.get_point((point % frame.point_count() as u128) as usize)
+  }
+
+  // Find the frame index and offset into that frame for the elapsed time.
+  fn locate(&self, elapsed: u128) -> Option<(usize, u128)> {
+    let cycle = self.timeline.last().map_or(0, |&(_, end)| end);
+    if cycle == 0 {
+      return None;
+    }
+
+    let position = match self.mode {
+      PlaybackMode::OneShot if elapsed >= cycle => return None,
+      PlaybackMode::OneShot => elapsed,
+      PlaybackMode::Loop | PlaybackMode::PingPong => elapsed % cycle,
+    };
+
+    let step = self.timeline.partition_point(|&(_, end)| end <= position);
+    let (index, _) = *self.timeline.get(step)?;
+    let start = if step == 0 { 0 } else { self.timeline[step - 1].1 };
+    Some((index, position - start))
+  }
+
+  // Rebuild the frame indices that make up one cycle, each paired with the
+  // time its display ends. Called whenever a frame's duration or the
+  // playback mode changes, so that `locate` only needs a binary search.
+  fn update_timeline(&mut self) {
+    let durations: Vec<u128> = (0..self.animation.frame_count())
+        .filter_map(|i| self.frame_duration(i))
+        .map(|d| d.as_nanos())
+        .collect();
+
+    let len = durations.len();
+    let sequence: Vec<usize> = match self.mode {
+      PlaybackMode::PingPong if len > 2 => {
+        (0..len).chain((1..len - 1).rev()).collect()
+      },
+      _ => (0..len).collect(),
+    };
+
+    let mut end = 0;
+    self.timeline = sequence.into_iter()
+        .map(|index| {
+          end += durations[index];
+          (index, end)
+        })
+        .collect();
+  }
+
+  fn scan_time(&self, frame: &Frame) -> u128 {
+    frame.point_count() as u128 * NANOS_PER_SECOND
+        / self.points_per_second as u128
+  }
+
+  fn sample_to_duration(&self, sample: u64) -> Duration {
+    nanos_to_duration(
+        sample as u128 * NANOS_PER_SECOND / self.points_per_second as u128)
+  }
+}
+
+fn nanos_to_duration(nanos: u128) -> Duration {
+  Duration::new((nanos / NANOS_PER_SECOND) as u64,
+      (nanos % NANOS_PER_SECOND) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ms(millis: u64) -> Duration {
+    Duration::from_millis(millis)
+  }
+
+  // Create an animation where each frame's points are colored by frame index.
+  fn animation(point_counts: &[usize]) -> Animation {
+    let frames = point_counts.iter()
+        .enumerate()
+        .map(|(i, &count)| {
+          let point = SimplePoint {
+            x: 0,
+            y: 0,
+            r: i as u8,
+            g: 0,
+            b: 0,
+            is_blank: false,
+          };
+          Frame::new(vec![point; count])
+        })
+        .collect();
+    Animation::new(frames)
+  }
+
+  fn playback(mode: PlaybackMode) -> Playback<ManualClock> {
+    let mut playback = Playback::new(animation(&[1, 1, 1]), ManualClock::new());
+    playback.set_frame_duration(ms(100));
+    playback.set_mode(mode);
+    playback
+  }
+
+  #[test]
+  fn test_loop() {
+    let playback = playback(PlaybackMode::Loop);
+    let indices: Vec<_> = (0..7)
+        .map(|i| playback.frame_index_at(ms(i * 100 + 50)))
+        .collect();
+    let expected = vec![0, 1, 2, 0, 1, 2, 0];
+    assert_eq!(expected.into_iter().map(Some).collect::<Vec<_>>(), indices);
+  }
+
+  #[test]
+  fn test_ping_pong() {
+    let playback = playback(PlaybackMode::PingPong);
+    let indices: Vec<_> = (0..9)
+        .map(|i| playback.frame_index_at(ms(i * 100 + 50)))
+        .collect();
+    let expected = vec![0, 1, 2, 1, 0, 1, 2, 1, 0];
+    assert_eq!(expected.into_iter().map(Some).collect::<Vec<_>>(), indices);
+  }
+
+  #[test]
+  fn test_one_shot() {
+    let playback = playback(PlaybackMode::OneShot);
+    assert_eq!(Some(0), playback.frame_index_at(ms(0)));
+    assert_eq!(Some(2), playback.frame_index_at(ms(299)));
+    assert_eq!(None, playback.frame_index_at(ms(300)));
+  }
+
+  #[test]
+  fn test_holds() {
+    let mut playback = playback(PlaybackMode::Loop);
+    playback.set_hold(1, Some(ms(500)));
+    playback.set_hold(10, Some(ms(500))); // Ignored.
+
+    assert_eq!(ms(700), playback.total_duration());
+    assert_eq!(Some(1), playback.frame_index_at(ms(550)));
+    assert_eq!(Some(2), playback.frame_index_at(ms(650)));
+  }
+
+  #[test]
+  fn test_zero_holds_and_mode_changes() {
+    let mut playback = playback(PlaybackMode::Loop);
+    playback.set_hold(1, Some(ms(0)));
+    assert_eq!(Some(0), playback.frame_index_at(ms(99)));
+    assert_eq!(Some(2), playback.frame_index_at(ms(100)));
+    assert_eq!(Some(0), playback.frame_index_at(ms(200)));
+
+    playback.set_hold(1, None);
+    playback.set_mode(PlaybackMode::PingPong);
+    assert_eq!(Some(1), playback.frame_index_at(ms(350)));
+    assert_eq!(Some(0), playback.frame_index_at(ms(450)));
+  }
+
+  #[test]
+  fn test_min_scan_time() {
+    let mut playback = Playback::new(animation(&[300, 30]), ManualClock::new());
+    playback.set_points_per_second(1_000);
+    playback.set_frame_duration(ms(100));
+    playback.set_min_scan_time(ms(250));
+
+    // 300 points takes 300ms to scan once.
+    assert_eq!(Some(ms(300)), playback.frame_duration(0));
+    // 30 points takes 30ms; nine scans are needed to reach 250ms.
+    assert_eq!(Some(ms(270)), playback.frame_duration(1));
+  }
+
+  #[test]
+  fn test_clock() {
+    let playback = playback(PlaybackMode::OneShot);
+    assert_eq!(Some(0), playback.current_frame_index());
+
+    playback.get_clock().advance(ms(150));
+    assert_eq!(Some(1), playback.current_frame_index());
+
+    playback.get_clock().set(ms(1_000));
+    assert!(playback.current_frame().is_none());
+  }
+
+  #[test]
+  fn test_restart() {
+    let mut playback = playback(PlaybackMode::OneShot);
+    playback.get_clock().set(ms(1_000));
+    assert!(playback.current_frame().is_none());
+
+    playback.restart();
+    assert_eq!(Some(0), playback.current_frame_index());
+  }
+
+  #[test]
+  fn test_point_at_sample() {
+    let mut playback = Playback::new(animation(&[2, 3]), ManualClock::new());
+    playback.set_points_per_second(10);
+    playback.set_frame_duration(ms(500));
+
+    // Five samples per frame at 10pps.
+    let colors: Vec<_> = (0..10)
+        .map(|i| playback.point_at_sample(i).unwrap().r)
+        .collect();
+    assert_eq!(vec![0, 0, 0, 0, 0, 1, 1, 1, 1, 1], colors);
+
+    assert_eq!(1, playback.frame_at_sample(7).unwrap().get_point(0).unwrap().r);
+  }
+}
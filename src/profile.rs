@@ -0,0 +1,504 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Per-projector calibration, applied to points right before output.
+//!
+//! Colors are corrected one channel at a time: a gamma curve or lookup
+//! table, then white balance and overall power scaling, and finally a
+//! minimum-visible threshold so that dim colors still light the diode.
+//! Geometry is corrected in normalized coordinates: axis swap and inversion,
+//! scale, rotation, keystone and offset, in that order.
+//!
+//! With the `serde` feature, profiles can be serialized with any serde
+//! format. The `profile-files` feature adds reading and writing them as TOML
+//! or JSON.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use source::PointSource;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Deserializer;
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "profile-files")]
+use std::fs::File;
+#[cfg(feature = "profile-files")]
+use std::io::Read;
+#[cfg(feature = "profile-files")]
+use std::io::Write;
+#[cfg(feature = "profile-files")]
+use std::path::Path;
+
+/// Correction for one color channel.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ColorCorrection {
+  /// Exponent applied to the normalized input value. Ignored if a lookup
+  /// table is given.
+  pub gamma: f32,
+  /// Optional 256 entry table replacing the gamma curve. Tables of any
+  /// other size are refused by `set_lut` and when deserializing.
+  #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_lut"))]
+  pub lut: Option<Vec<u8>>,
+  /// White balance gain, from 0 to 1.
+  pub gain: f32,
+  /// The lowest output value at which the diode is visible. Lit points are
+  /// mapped into the range between this and 255.
+  pub min_visible: u8,
+}
+
+/// Correction for projected geometry.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct GeometryCorrection {
+  /// Exchange the X and Y axes.
+  pub swap_xy: bool,
+  /// Mirror horizontally.
+  pub invert_x: bool,
+  /// Mirror vertically.
+  pub invert_y: bool,
+  /// Horizontal scale factor.
+  pub scale_x: f32,
+  /// Vertical scale factor.
+  pub scale_y: f32,
+  /// Counter-clockwise rotation in degrees.
+  pub rotation: f32,
+  /// Horizontal keystone. Positive values widen the top of the image.
+  pub keystone_x: f32,
+  /// Vertical keystone. Positive values heighten the right of the image.
+  pub keystone_y: f32,
+  /// Horizontal offset, as a fraction of the half-width.
+  pub offset_x: f32,
+  /// Vertical offset, as a fraction of the half-height.
+  pub offset_y: f32,
+}
+
+/// Calibration for a single projector.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ProjectorProfile {
+  /// Optional name of the projector.
+  pub name: Option<String>,
+  /// Red channel correction.
+  pub red: ColorCorrection,
+  /// Green channel correction.
+  pub green: ColorCorrection,
+  /// Blue channel correction.
+  pub blue: ColorCorrection,
+  /// Overall power scaling, from 0 to 1.
+  pub max_power: f32,
+  /// Geometry correction.
+  pub geometry: GeometryCorrection,
+}
+
+/// A point source with a profile applied to its output.
+pub struct Corrected<S: PointSource> {
+  source: S,
+  profile: ProjectorProfile,
+}
+
+impl Default for ColorCorrection {
+  fn default() -> ColorCorrection {
+    ColorCorrection {
+      gamma: 1.0,
+      lut: None,
+      gain: 1.0,
+      min_visible: 0,
+    }
+  }
+}
+
+impl Default for GeometryCorrection {
+  fn default() -> GeometryCorrection {
+    GeometryCorrection {
+      swap_xy: false,
+      invert_x: false,
+      invert_y: false,
+      scale_x: 1.0,
+      scale_y: 1.0,
+      rotation: 0.0,
+      keystone_x: 0.0,
+      keystone_y: 0.0,
+      offset_x: 0.0,
+      offset_y: 0.0,
+    }
+  }
+}
+
+impl Default for ProjectorProfile {
+  fn default() -> ProjectorProfile {
+    ProjectorProfile {
+      name: None,
+      red: ColorCorrection::default(),
+      green: ColorCorrection::default(),
+      blue: ColorCorrection::default(),
+      max_power: 1.0,
+      geometry: GeometryCorrection::default(),
+    }
+  }
+}
+
+impl ColorCorrection {
+  /// Set the lookup table, failing with `IldaError::InvalidProfile` unless
+  /// it has exactly 256 entries.
+  pub fn set_lut(&mut self, lut: Option<Vec<u8>>) -> Result<(), IldaError> {
+    if let Some(ref lut) = lut {
+      check_lut(lut).map_err(|cause| IldaError::InvalidProfile { cause })?;
+    }
+    self.lut = lut;
+    Ok(())
+  }
+
+  /// Correct a single value, with `power` as the overall power scaling.
+  pub fn apply(&self, value: u8, power: f32) -> u8 {
+    if value == 0 {
+      return 0;
+    }
+
+    let curved = match self.lut {
+      Some(ref lut) => {
+        lut.get(value as usize).cloned().unwrap_or(value) as f32 / 255.0
+      },
+      None => (value as f32 / 255.0).powf(self.gamma),
+    };
+
+    let scaled = (curved * self.gain * power).clamp(0.0, 1.0);
+    if scaled <= 0.0 {
+      return 0;
+    }
+
+    let min = self.min_visible as f32;
+    (min + scaled * (255.0 - min)).round() as u8
+  }
+}
+
+impl GeometryCorrection {
+  /// Correct the position of a point.
+  pub fn apply(&self, x: i16, y: i16) -> (i16, i16) {
+    let mut x = x as f32 / i16::MAX as f32;
+    let mut y = y as f32 / i16::MAX as f32;
+
+    if self.swap_xy {
+      std::mem::swap(&mut x, &mut y);
+    }
+    if self.invert_x {
+      x = -x;
+    }
+    if self.invert_y {
+      y = -y;
+    }
+
+    x *= self.scale_x;
+    y *= self.scale_y;
+
+    let (sin, cos) = self.rotation.to_radians().sin_cos();
+    let (rx, ry) = (x * cos - y * sin, x * sin + y * cos);
+
+    let kx = rx * (1.0 + self.keystone_x * ry);
+    let ky = ry * (1.0 + self.keystone_y * rx);
+
+    (denormalize(kx + self.offset_x), denormalize(ky + self.offset_y))
+  }
+}
+
+impl ProjectorProfile {
+  /// Correct a single point.
+  pub fn apply_point(&self, point: &SimplePoint) -> SimplePoint {
+    let (x, y) = self.geometry.apply(point.x, point.y);
+    let power = self.max_power;
+
+    if point.is_blank {
+      return SimplePoint { x, y, r: 0, g: 0, b: 0, is_blank: true };
+    }
+
+    SimplePoint {
+      x,
+      y,
+      r: self.red.apply(point.r, power),
+      g: self.green.apply(point.g, power),
+      b: self.blue.apply(point.b, power),
+      is_blank: false,
+    }
+  }
+
+  /// Correct a list of points.
+  pub fn apply_points(&self, points: &[SimplePoint]) -> Vec<SimplePoint> {
+    points.iter().map(|point| self.apply_point(point)).collect()
+  }
+
+  /// Correct every point in a frame.
+  pub fn apply_frame(&self, frame: &Frame) -> Frame {
+    let mut out = frame.clone();
+    out.set_points(self.apply_points(frame.get_points()));
+    out
+  }
+
+  /// Correct every frame in an animation.
+  pub fn apply_animation(&self, animation: &Animation) -> Animation {
+    Animation::new(animation.into_frame_iter()
+        .map(|frame| self.apply_frame(frame))
+        .collect())
+  }
+
+  /// Wrap a point source so its output is corrected.
+  pub fn wrap<S: PointSource>(self, source: S) -> Corrected<S> {
+    Corrected { source, profile: self }
+  }
+}
+
+#[cfg(feature = "profile-files")]
+impl ProjectorProfile {
+  /// Parse a profile from TOML.
+  pub fn from_toml(text: &str) -> Result<ProjectorProfile, IldaError> {
+    ::toml::from_str(text).map_err(|e| invalid_profile(&e))
+  }
+
+  /// Serialize the profile as TOML.
+  pub fn to_toml(&self) -> Result<String, IldaError> {
+    ::toml::to_string(self).map_err(|e| invalid_profile(&e))
+  }
+
+  /// Parse a profile from JSON.
+  pub fn from_json(text: &str) -> Result<ProjectorProfile, IldaError> {
+    ::serde_json::from_str(text).map_err(|e| invalid_profile(&e))
+  }
+
+  /// Serialize the profile as JSON.
+  pub fn to_json(&self) -> Result<String, IldaError> {
+    ::serde_json::to_string_pretty(self).map_err(|e| invalid_profile(&e))
+  }
+
+  /// Read a profile from a `.json` file, or otherwise a TOML file.
+  pub fn read_file(filename: &str) -> Result<ProjectorProfile, IldaError> {
+    let mut text = String::new();
+    File::open(filename)?.read_to_string(&mut text)?;
+    if is_json(filename) {
+      ProjectorProfile::from_json(&text)
+    } else {
+      ProjectorProfile::from_toml(&text)
+    }
+  }
+
+  /// Write the profile to a `.json` file, or otherwise a TOML file.
+  pub fn write_file(&self, filename: &str) -> Result<(), IldaError> {
+    let text = if is_json(filename) {
+      self.to_json()?
+    } else {
+      self.to_toml()?
+    };
+    File::create(filename)?.write_all(text.as_bytes())?;
+    Ok(())
+  }
+}
+
+impl<S: PointSource> Corrected<S> {
+  /// Get a reference to the profile.
+  pub fn get_profile(&self) -> &ProjectorProfile {
+    &self.profile
+  }
+
+  /// Replace the profile, eg. while calibrating live.
+  pub fn set_profile(&mut self, profile: ProjectorProfile) {
+    self.profile = profile;
+  }
+
+  /// Unwrap the underlying source.
+  pub fn into_inner(self) -> S {
+    self.source
+  }
+}
+
+impl<S: PointSource> PointSource for Corrected<S> {
+  fn next_points(&mut self, count: usize, points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let points = self.source.next_points(count, points_per_second);
+    self.profile.apply_points(&points)
+  }
+
+  fn reset(&mut self) {
+    self.source.reset();
+  }
+}
+
+// A lookup table needs an entry for every input value.
+fn check_lut(lut: &[u8]) -> Result<(), String> {
+  if lut.len() == 256 {
+    Ok(())
+  } else {
+    Err(format!("lookup table has {} entries, expected 256", lut.len()))
+  }
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_lut<'de, D>(deserializer: D)
+    -> Result<Option<Vec<u8>>, D::Error>
+    where D: Deserializer<'de> {
+  let lut = Option::<Vec<u8>>::deserialize(deserializer)?;
+  if let Some(ref lut) = lut {
+    check_lut(lut).map_err(D::Error::custom)?;
+  }
+  Ok(lut)
+}
+
+fn denormalize(value: f32) -> i16 {
+  (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+#[cfg(feature = "profile-files")]
+fn is_json(filename: &str) -> bool {
+  Path::new(filename).extension().is_some_and(|ext| ext == "json")
+}
+
+#[cfg(feature = "profile-files")]
+fn invalid_profile<E: ::std::fmt::Display>(error: &E) -> IldaError {
+  IldaError::InvalidProfile { cause: error.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use source::FrameSource;
+
+  fn point(x: i16, y: i16, r: u8, g: u8, b: u8) -> SimplePoint {
+    SimplePoint { x, y, r, g, b, is_blank: false }
+  }
+
+  #[test]
+  fn test_default_is_identity() {
+    let profile = ProjectorProfile::default();
+    let out = profile.apply_point(&point(-1200, 32767, 10, 128, 255));
+    assert_eq!((-1200, 32767, 10, 128, 255),
+        (out.x, out.y, out.r, out.g, out.b));
+  }
+
+  #[test]
+  fn test_color_correction() {
+    let mut color = ColorCorrection {
+      gamma: 2.0,
+      ..Default::default()
+    };
+    assert_eq!(64, color.apply(128, 1.0));
+
+    color.gamma = 1.0;
+    color.gain = 0.5;
+    assert_eq!(128, color.apply(255, 1.0));
+    assert_eq!(64, color.apply(255, 0.5));
+
+    color.gain = 1.0;
+    color.min_visible = 55;
+    assert_eq!(0, color.apply(0, 1.0));
+    assert_eq!(56, color.apply(1, 1.0));
+    assert_eq!(255, color.apply(255, 1.0));
+
+    color.min_visible = 0;
+    color.lut = Some((0..256).map(|i| 255 - i as u8).collect());
+    assert_eq!(245, color.apply(10, 1.0));
+
+    assert!(color.set_lut(Some(vec![0; 255])).is_err());
+    assert_eq!(245, color.apply(10, 1.0));
+    color.set_lut(None).unwrap();
+    assert_eq!(10, color.apply(10, 1.0));
+  }
+
+  #[test]
+  fn test_blank_points_stay_dark() {
+    let mut profile = ProjectorProfile::default();
+    profile.red.min_visible = 100;
+    let mut input = point(0, 0, 255, 255, 255);
+    input.is_blank = true;
+
+    let out = profile.apply_point(&input);
+    assert!(out.is_blank);
+    assert_eq!((0, 0, 0), (out.r, out.g, out.b));
+  }
+
+  #[test]
+  fn test_geometry_correction() {
+    let geometry = GeometryCorrection {
+      swap_xy: true,
+      invert_x: true,
+      ..Default::default()
+    };
+    assert_eq!((-200, 100), geometry.apply(100, 200));
+
+    let geometry = GeometryCorrection {
+      rotation: 90.0,
+      ..Default::default()
+    };
+    assert_eq!((0, 32767), geometry.apply(32767, 0));
+
+    let geometry = GeometryCorrection {
+      scale_x: 0.5,
+      offset_y: 0.5,
+      ..Default::default()
+    };
+    assert_eq!((16384, 32767), geometry.apply(32767, 32767));
+
+    let geometry = GeometryCorrection {
+      keystone_x: 0.5,
+      ..Default::default()
+    };
+    assert_eq!((24576, 32767), geometry.apply(16384, 32767));
+    assert_eq!((8192, -32767), geometry.apply(16384, -32767));
+  }
+
+  #[test]
+  fn test_corrected_source() {
+    let mut profile = ProjectorProfile::default();
+    profile.geometry.invert_y = true;
+    profile.max_power = 0.0;
+
+    let frame = Frame::new(vec![point(10, 20, 255, 255, 255)]);
+    let mut source = profile.wrap(FrameSource::new(frame));
+    let points = source.next_points(2, 30_000);
+
+    assert_eq!(1, points.len());
+    assert_eq!(-20, points[0].y);
+    assert_eq!(0, points[0].r);
+  }
+
+  #[cfg(feature = "profile-files")]
+  #[test]
+  fn test_serialization() {
+    let profile = ProjectorProfile::from_toml(r#"
+      name = "stage left"
+      max_power = 0.8
+
+      [red]
+      gamma = 2.2
+      min_visible = 40
+
+      [geometry]
+      invert_x = true
+      keystone_y = -0.1
+    "#).unwrap();
+
+    assert_eq!(Some("stage left".to_string()), profile.name);
+    assert_eq!(0.8, profile.max_power);
+    assert_eq!(2.2, profile.red.gamma);
+    assert_eq!(40, profile.red.min_visible);
+    assert_eq!(1.0, profile.red.gain);
+    assert_eq!(ColorCorrection::default(), profile.green);
+    assert!(profile.geometry.invert_x);
+    assert_eq!(1.0, profile.geometry.scale_x);
+
+    let toml = profile.to_toml().unwrap();
+    assert_eq!(profile, ProjectorProfile::from_toml(&toml).unwrap());
+    let json = profile.to_json().unwrap();
+    assert_eq!(profile, ProjectorProfile::from_json(&json).unwrap());
+
+    assert!(ProjectorProfile::from_json("{\"max_power\": \"x\"}").is_err());
+
+    let error = ProjectorProfile::from_toml("[red]\nlut = [0, 1, 2]")
+        .unwrap_err();
+    assert!(error.to_string()
+        .contains("lookup table has 3 entries, expected 256"));
+  }
+}
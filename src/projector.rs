@@ -0,0 +1,181 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Routing of frames to several projectors using the header's projector
+//! number.
+//!
+//! Multi-projector shows interleave the frames for each projector, eg. frame
+//! 0 for projectors 0 and 1, then frame 1 for projectors 0 and 1, and so on.
+//! A run of frames with no repeated projector forms one "slot" of time. When
+//! a projector has no frame in a slot it holds its previous frame, so every
+//! split animation has the same number of frames and stays in sync.
+
+use animation::Animation;
+use animation::Frame;
+use std::collections::BTreeMap;
+
+/// Split an animation into one animation per projector, ordered by projector
+/// number. The empty frame read from a file's terminating header is ignored.
+///
+/// Time slots are found from the order of frames alone: a new slot starts
+/// whenever a projector repeats. Header frame numbers aren't kept by
+/// `Frame`, so they aren't consulted.
+pub fn split(animation: &Animation) -> Vec<(u8, Animation)> {
+  let mut frames: &[Frame] = animation.get_frames();
+  if let Some((last, rest)) = frames.split_last() {
+    if last.is_terminator() {
+      frames = rest;
+    }
+  }
+
+  // Group frames into time slots.
+  let mut slots: Vec<BTreeMap<u8, &Frame>> = Vec::new();
+  for frame in frames {
+    let projector = frame.get_projector_number();
+    let needs_slot = match slots.last() {
+      None => true,
+      Some(slot) => slot.contains_key(&projector),
+    };
+    if needs_slot {
+      slots.push(BTreeMap::new());
+    }
+    if let Some(slot) = slots.last_mut() {
+      slot.insert(projector, frame);
+    }
+  }
+
+  let mut projectors: BTreeMap<u8, Vec<Frame>> = BTreeMap::new();
+  for frame in frames {
+    projectors.entry(frame.get_projector_number()).or_default();
+  }
+
+  for (projector, out) in projectors.iter_mut() {
+    let mut held: Option<&Frame> = None;
+    for slot in slots.iter() {
+      if let Some(frame) = slot.get(projector) {
+        held = Some(*frame);
+      }
+      out.push(match held {
+        Some(frame) => frame.clone(),
+        None => {
+          let mut frame = Frame::new(Vec::new());
+          frame.set_projector_number(*projector);
+          frame
+        },
+      });
+    }
+  }
+
+  projectors.into_iter()
+      .map(|(projector, frames)| (projector, Animation::new(frames)))
+      .collect()
+}
+
+/// Combine single-projector animations into one multi-projector animation,
+/// ready to be written to a file. Frames are interleaved slot by slot, and
+/// animations that run out of frames hold their last one.
+pub fn combine(animations: &[(u8, Animation)]) -> Animation {
+  let slot_count = animations.iter()
+      .map(|(_, animation)| animation.frame_count())
+      .max()
+      .unwrap_or(0);
+
+  let mut frames = Vec::new();
+  for slot in 0..slot_count {
+    for &(projector, ref animation) in animations {
+      let frame = animation.get_frame(slot)
+          .or_else(|| animation.get_frames().last());
+      if let Some(frame) = frame {
+        let mut frame = frame.clone();
+        frame.set_projector_number(projector);
+        frames.push(frame);
+      }
+    }
+  }
+
+  Animation::new(frames)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use point::SimplePoint;
+
+  fn frame(projector: u8, x: i16) -> Frame {
+    let mut frame = Frame::new(vec![SimplePoint {
+      x,
+      ..SimplePoint::default()
+    }]);
+    frame.set_projector_number(projector);
+    frame
+  }
+
+  fn xs(animation: &Animation) -> Vec<i16> {
+    animation.into_frame_iter()
+        .map(|frame| frame.get_point(0).map(|p| p.x).unwrap_or(-1))
+        .collect()
+  }
+
+  #[test]
+  fn test_split() {
+    let animation = Animation::new(vec![
+      frame(0, 0), frame(1, 10),
+      frame(0, 1),
+      frame(0, 2), frame(1, 11),
+      frame(2, 20),
+    ]);
+
+    let split = split(&animation);
+    assert_eq!(3, split.len());
+
+    assert_eq!(0, split[0].0);
+    assert_eq!(vec![0, 1, 2], xs(&split[0].1));
+    assert_eq!(1, split[1].0);
+    assert_eq!(vec![10, 10, 11], xs(&split[1].1));
+    assert_eq!(2, split[2].0);
+    assert_eq!(vec![-1, -1, 20], xs(&split[2].1));
+    assert_eq!(2, split[2].1.get_frame(0).unwrap().get_projector_number());
+  }
+
+  #[test]
+  fn test_split_placeholders_through_file() {
+    let animation = Animation::new(vec![
+      frame(0, 0), frame(0, 1), frame(0, 2), frame(2, 20),
+    ]);
+
+    for (_, animation) in split(&animation) {
+      let bytes = animation.write_bytes().unwrap();
+      let read = Animation::read_bytes(&bytes).unwrap();
+
+      // The terminator is read back as an extra, empty frame.
+      assert_eq!(animation.frame_count() + 1, read.frame_count());
+      assert!(read.get_frames().last().unwrap().is_terminator());
+      assert_eq!(animation.frame_count(), split(&read)[0].1.frame_count());
+    }
+
+    let placeholders = &split(&animation)[1].1;
+    let read = Animation::read_bytes(&placeholders.write_bytes().unwrap())
+        .unwrap();
+    let frame = read.get_frame(0).unwrap();
+    assert_eq!(1, frame.point_count());
+    assert!(frame.get_point(0).unwrap().is_blank);
+    assert_eq!(vec![0, 0, 20, -1], xs(&read));
+  }
+
+  #[test]
+  fn test_combine_and_split_through_file() {
+    let first = Animation::new(vec![frame(0, 0), frame(0, 1), frame(0, 2)]);
+    let second = Animation::new(vec![frame(0, 10)]);
+
+    let combined = combine(&[(0, first), (3, second)]);
+    assert_eq!(vec![0, 10, 1, 10, 2, 10], xs(&combined));
+
+    let read = Animation::read_bytes(&combined.write_bytes().unwrap()).unwrap();
+    let split = split(&read);
+
+    assert_eq!(2, split.len());
+    assert_eq!(0, split[0].0);
+    assert_eq!(vec![0, 1, 2], xs(&split[0].1));
+    assert_eq!(3, split[1].0);
+    assert_eq!(vec![10, 10, 10], xs(&split[1].1));
+  }
+}
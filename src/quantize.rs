@@ -0,0 +1,219 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Reduction of true color content to indexed color, for formats 0 and 1.
+//!
+//! Colors either map onto the nearest entry of a fixed palette, or onto a
+//! palette generated for the whole animation with the median cut algorithm.
+
+use animation::Animation;
+use data::ColorPalette;
+use palette::Palette;
+use palette::MAX_COLORS;
+use std::collections::BTreeMap;
+
+/// How true colors are reduced to palette indices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Quantization {
+  /// Map each color to the nearest entry of the given palette.
+  Nearest(Palette),
+  /// Generate a palette of up to the given number of colors (at most 256)
+  /// from the colors used in the animation.
+  Optimal(usize),
+}
+
+impl Quantization {
+  /// The palette that colors in the animation will be mapped onto.
+  pub fn palette_for(&self, animation: &Animation) -> Palette {
+    match *self {
+      Quantization::Nearest(ref palette) => palette.clone(),
+      Quantization::Optimal(max_colors) => {
+        generate_palette(animation, max_colors)
+      },
+    }
+  }
+}
+
+impl Default for Quantization {
+  fn default() -> Quantization {
+    Quantization::Nearest(Palette::standard())
+  }
+}
+
+/// Generate a palette of up to `max_colors` colors (at most 256) covering
+/// the lit points of an animation. If the animation has no lit points, the
+/// palette is a single black entry.
+pub fn generate_palette(animation: &Animation, max_colors: usize) -> Palette {
+  let mut counts = BTreeMap::new();
+  for point in animation.into_point_iter() {
+    if !point.is_blank {
+      *counts.entry([point.r, point.g, point.b]).or_insert(0u64) += 1;
+    }
+  }
+
+  let colors: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+  median_cut(&colors, max_colors)
+}
+
+/// Reduce weighted colors to a palette of up to `max_colors` colors (at most
+/// 256) by median cut. The box with the widest channel range is repeatedly
+/// split at its weighted median, and each box becomes its weighted average.
+pub fn median_cut(colors: &[([u8; 3], u64)], max_colors: usize) -> Palette {
+  let max_colors = max_colors.clamp(1, MAX_COLORS);
+  let colors: Vec<([u8; 3], u64)> = colors.iter()
+      .cloned()
+      .filter(|&(_, count)| count > 0)
+      .collect();
+
+  if colors.is_empty() {
+    return Palette::new(vec![ColorPalette { r: 0, g: 0, b: 0 }])
+        .unwrap_or_else(|_| unreachable!());
+  }
+
+  let mut boxes = vec![colors];
+
+  while boxes.len() < max_colors {
+    // Find the box with the widest range on any channel.
+    let widest = boxes.iter()
+        .enumerate()
+        .filter(|&(_, colors)| colors.len() > 1)
+        .map(|(i, colors)| {
+          let (channel, range) = widest_channel(colors);
+          (range, i, channel)
+        })
+        .max_by_key(|&(range, i, _)| (range, usize::MAX - i));
+
+    let (i, channel) = match widest {
+      None => break,
+      Some((_, i, channel)) => (i, channel),
+    };
+
+    let mut colors = boxes.swap_remove(i);
+    colors.sort_by_key(|&(color, _)| (color[channel], color));
+
+    let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+    let mut seen = 0;
+    let mut split = 1;
+    for (j, &(_, count)) in colors.iter().enumerate() {
+      seen += count;
+      if seen * 2 >= total {
+        split = j + 1;
+        break;
+      }
+    }
+    let split = split.min(colors.len() - 1);
+
+    let upper = colors.split_off(split);
+    boxes.push(colors);
+    boxes.push(upper);
+  }
+
+  let mut palette: Vec<ColorPalette> = boxes.iter()
+      .map(|colors| average(colors))
+      .collect();
+  palette.sort_by_key(|color| (color.r, color.g, color.b));
+  palette.dedup();
+
+  // There's at most one color per box, and at most 256 boxes.
+  Palette::new(palette).unwrap_or_else(|_| unreachable!())
+}
+
+// The channel with the widest range of values, and that range.
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+  let mut widest = (0, 0);
+  for channel in 0..3 {
+    let min = colors.iter().map(|&(color, _)| color[channel]).min();
+    let max = colors.iter().map(|&(color, _)| color[channel]).max();
+    if let (Some(min), Some(max)) = (min, max) {
+      if max - min > widest.1 {
+        widest = (channel, max - min);
+      }
+    }
+  }
+  widest
+}
+
+fn average(colors: &[([u8; 3], u64)]) -> ColorPalette {
+  let mut sums = [0u64; 3];
+  let mut total = 0u64;
+  for &(color, count) in colors {
+    for channel in 0..3 {
+      sums[channel] += color[channel] as u64 * count;
+    }
+    total += count;
+  }
+  let channel = |i: usize| ((sums[i] + total / 2) / total.max(1)) as u8;
+  ColorPalette { r: channel(0), g: channel(1), b: channel(2) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use animation::Frame;
+  use data::IldaEntry;
+  use point::SimplePoint;
+
+  fn point(r: u8, g: u8, b: u8) -> SimplePoint {
+    SimplePoint { x: 0, y: 0, r, g, b, is_blank: false }
+  }
+
+  #[test]
+  fn test_median_cut_keeps_few_colors() {
+    let colors = [([255, 0, 0], 3), ([0, 0, 255], 1), ([0, 255, 0], 0)];
+    let palette = median_cut(&colors, 16);
+    assert_eq!(2, palette.len());
+    assert_eq!(ColorPalette { r: 0, g: 0, b: 255 }, palette.color(0));
+    assert_eq!(ColorPalette { r: 255, g: 0, b: 0 }, palette.color(1));
+  }
+
+  #[test]
+  fn test_median_cut_merges_similar_colors() {
+    let colors = [
+      ([250, 0, 0], 1), ([255, 0, 0], 1),
+      ([0, 0, 250], 1), ([0, 0, 255], 1),
+    ];
+    let palette = median_cut(&colors, 2);
+    assert_eq!(2, palette.len());
+    assert_eq!(ColorPalette { r: 0, g: 0, b: 253 }, palette.color(0));
+    assert_eq!(ColorPalette { r: 253, g: 0, b: 0 }, palette.color(1));
+  }
+
+  #[test]
+  fn test_median_cut_limits() {
+    let colors: Vec<_> = (0..1000u32)
+        .map(|i| ([(i % 256) as u8, (i / 4) as u8, 7], 1))
+        .collect();
+    assert_eq!(256, median_cut(&colors, 1000).len());
+    assert_eq!(1, median_cut(&colors, 0).len());
+    assert_eq!(1, median_cut(&[], 8).len());
+  }
+
+  #[test]
+  fn test_indexed_entries() {
+    let mut blank = point(255, 255, 255);
+    blank.is_blank = true;
+    let animation = Animation::new(vec![
+      Frame::new(vec![point(255, 0, 0), blank, point(0, 250, 0)]),
+    ]);
+
+    // The standard palette isn't written out.
+    let entries = animation.to_indexed_entries(&Quantization::default())
+        .unwrap();
+    let indices: Vec<_> = entries.iter()
+        .filter_map(|entry| match *entry {
+          IldaEntry::IdxPoint2dEntry(ref point) => Some(point.color_index),
+          _ => None,
+        })
+        .collect();
+    assert_eq!(vec![0, 0, 24], indices);
+
+    // A generated palette is written before the frames.
+    let bytes = animation.write_indexed_bytes(&Quantization::Optimal(8))
+        .unwrap();
+    let read = Animation::read_bytes(&bytes).unwrap();
+    let frame = read.get_frame(0).unwrap();
+    assert_eq!(2, frame.get_palette().unwrap().len());
+    assert_eq!(255, frame.get_point(0).unwrap().r);
+    assert!(frame.get_point(1).unwrap().is_blank);
+    assert_eq!(250, frame.get_point(2).unwrap().g);
+  }
+}
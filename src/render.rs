@@ -0,0 +1,420 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Rendering frames to images, for previews and contact sheets.
+//!
+//! The laser's path is traced as lines between consecutive points, each line
+//! taking the color of the point it travels to. Images can be saved as PNG
+//! (uncompressed, to avoid pulling in an image library) or frames written
+//! directly as SVG.
+
+use animation::Animation;
+use animation::Frame;
+use error::IldaError;
+use point::SimplePoint;
+use std::convert::TryFrom;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::Write;
+
+/// Image rendering settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+  /// Width in pixels. For contact sheets, the width of each cell.
+  pub width: u32,
+  /// Height in pixels. For contact sheets, the height of each cell.
+  pub height: u32,
+  /// Background color.
+  pub background: [u8; 3],
+  /// Whether to draw blanked moves.
+  pub show_blanking: bool,
+  /// Color of blanked moves, if drawn.
+  pub blanking_color: [u8; 3],
+}
+
+/// An RGB raster image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+  width: u32,
+  height: u32,
+  pixels: Vec<u8>,
+}
+
+impl Default for RenderOptions {
+  fn default() -> RenderOptions {
+    RenderOptions {
+      width: 512,
+      height: 512,
+      background: [0, 0, 0],
+      show_blanking: false,
+      blanking_color: [64, 64, 64],
+    }
+  }
+}
+
+impl Image {
+  /// Create an image filled with a color.
+  ///
+  /// Panics if the image has more pixels than can be addressed.
+  pub fn new(width: u32, height: u32, color: [u8; 3]) -> Image {
+    let size = pixel_bytes(width, height).expect("image too large");
+    let mut pixels = Vec::with_capacity(size);
+    for _ in 0..size / 3 {
+      pixels.extend_from_slice(&color);
+    }
+    Image { width, height, pixels }
+  }
+
+  /// Width in pixels.
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  /// Height in pixels.
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Get the color of a pixel, if it's within the image.
+  pub fn get_pixel(&self, x: u32, y: u32) -> Option<[u8; 3]> {
+    if x >= self.width || y >= self.height {
+      return None;
+    }
+    let i = (y as usize * self.width as usize + x as usize) * 3;
+    Some([self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]])
+  }
+
+  /// Set the color of a pixel. Pixels outside the image are ignored.
+  pub fn set_pixel(&mut self, x: i64, y: i64, color: [u8; 3]) {
+    if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+      return;
+    }
+    let i = (y as usize * self.width as usize + x as usize) * 3;
+    self.pixels[i..i + 3].copy_from_slice(&color);
+  }
+
+  /// Draw a line between two pixels.
+  pub fn draw_line(&mut self, from: (i64, i64), to: (i64, i64),
+      color: [u8; 3]) {
+    let (mut x, mut y) = from;
+    let dx = (to.0 - x).abs();
+    let dy = -(to.1 - y).abs();
+    let sx = if x < to.0 { 1 } else { -1 };
+    let sy = if y < to.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    loop {
+      self.set_pixel(x, y, color);
+      if (x, y) == to {
+        break;
+      }
+      let e2 = 2 * error;
+      if e2 >= dy {
+        error += dy;
+        x += sx;
+      }
+      if e2 <= dx {
+        error += dx;
+        y += sy;
+      }
+    }
+  }
+
+  /// Copy another image in with its top left corner at the given pixel.
+  pub fn blit(&mut self, image: &Image, left: u32, top: u32) {
+    for y in 0..image.height {
+      for x in 0..image.width {
+        if let Some(color) = image.get_pixel(x, y) {
+          self.set_pixel(left as i64 + x as i64, top as i64 + y as i64,
+              color);
+        }
+      }
+    }
+  }
+
+  /// Encode as an uncompressed PNG. Fails with `IldaError::TooLarge` if the
+  /// image data doesn't fit in a PNG chunk.
+  pub fn to_png(&self) -> Result<Vec<u8>, IldaError> {
+    if idat_len(self.width, self.height).is_none() {
+      return Err(IldaError::TooLarge);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&self.width.to_be_bytes());
+    ihdr.extend_from_slice(&self.height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB
+
+    // Each scanline is prefixed with filter type 0 (none).
+    let row = self.width as usize * 3;
+    let mut raw = Vec::with_capacity((row + 1) * self.height as usize);
+    for line in self.pixels.chunks(row.max(1)) {
+      raw.push(0);
+      raw.extend_from_slice(line);
+    }
+
+    let mut out = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+  }
+
+  /// Save as a PNG file. See `to_png`.
+  pub fn write_png(&self, filename: &str) -> Result<(), IldaError> {
+    let png = self.to_png()?;
+    File::create(filename)?.write_all(&png)?;
+    Ok(())
+  }
+}
+
+/// Render a frame.
+pub fn render_frame(frame: &Frame, options: &RenderOptions) -> Image {
+  let mut image = Image::new(options.width, options.height, options.background);
+  let points = frame.get_points();
+
+  for (i, point) in points.iter().enumerate() {
+    let to = pixel(point, options);
+    let from = match i {
+      0 => to,
+      _ => pixel(&points[i - 1], options),
+    };
+
+    if point.is_blank {
+      if options.show_blanking {
+        image.draw_line(from, to, options.blanking_color);
+      }
+    } else {
+      image.draw_line(from, to, [point.r, point.g, point.b]);
+    }
+  }
+
+  image
+}
+
+/// Render every frame of an animation into a grid with the given number of
+/// columns. Each cell is the size given in the options. Fails with
+/// `IldaError::TooLarge` if the sheet is too big to be saved as a PNG.
+pub fn contact_sheet(animation: &Animation, columns: u32,
+    options: &RenderOptions) -> Result<Image, IldaError> {
+  let columns = columns.max(1) as usize;
+  let count = animation.frame_count();
+  let rows = ((count + columns - 1) / columns).max(1);
+
+  let size = |cell: u32, cells: usize| {
+    (cell as usize).checked_mul(cells)
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or(IldaError::TooLarge)
+  };
+  let width = size(options.width, columns.min(count.max(1)))?;
+  let height = size(options.height, rows)?;
+  if idat_len(width, height).is_none() {
+    return Err(IldaError::TooLarge);
+  }
+
+  let mut sheet = Image::new(width, height, options.background);
+
+  for (i, frame) in animation.into_frame_iter().enumerate() {
+    let cell = render_frame(frame, options);
+    // The cell's position is within the sheet, so it fits in a u32.
+    sheet.blit(&cell, ((i % columns) * options.width as usize) as u32,
+        ((i / columns) * options.height as usize) as u32);
+  }
+
+  Ok(sheet)
+}
+
+/// Render a frame as an SVG document.
+pub fn frame_to_svg(frame: &Frame, options: &RenderOptions) -> String {
+  let mut out = String::new();
+  let [r, g, b] = options.background;
+  let _ = writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+      width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", options.width,
+      options.height, options.width, options.height);
+  let _ = writeln!(out, "  <rect width=\"100%\" height=\"100%\" \
+      fill=\"#{:02x}{:02x}{:02x}\"/>", r, g, b);
+
+  let points = frame.get_points();
+  for (i, point) in points.iter().enumerate() {
+    let color = if !point.is_blank {
+      [point.r, point.g, point.b]
+    } else if options.show_blanking {
+      options.blanking_color
+    } else {
+      continue;
+    };
+
+    let to = pixel(point, options);
+    let from = match i {
+      0 => to,
+      _ => pixel(&points[i - 1], options),
+    };
+
+    let _ = writeln!(out, "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" \
+        stroke=\"#{:02x}{:02x}{:02x}\" stroke-linecap=\"round\"/>", from.0,
+        from.1, to.0, to.1, color[0], color[1], color[2]);
+  }
+
+  out.push_str("</svg>\n");
+  out
+}
+
+// Map ILDA coordinates onto the image, with positive Y up.
+fn pixel(point: &SimplePoint, options: &RenderOptions) -> (i64, i64) {
+  let scale = |value: i64, size: u32| {
+    value * (size.max(1) as i64 - 1) / 65535
+  };
+  (scale(point.x as i64 + 32768, options.width),
+      scale(32767 - point.y as i64, options.height))
+}
+
+// Bytes needed for an image's pixels, if they can be addressed.
+fn pixel_bytes(width: u32, height: u32) -> Option<usize> {
+  (width as usize).checked_mul(height as usize)?.checked_mul(3)
+}
+
+// Length of the image data `to_png` writes, if it fits in the single IDAT
+// chunk it's written to; PNG chunks hold at most 2^31 - 1 bytes. Each
+// scanline gains a filter byte, and each stored deflate block a five byte
+// header, inside a six byte zlib wrapper.
+fn idat_len(width: u32, height: u32) -> Option<u32> {
+  let row = (width as u64).checked_mul(3)?.checked_add(1)?;
+  let raw = row.checked_mul(height as u64)?;
+  let blocks = ((raw + 65534) / 65535).max(1);
+  let len = raw.checked_add(blocks * 5 + 6)?;
+  u32::try_from(len).ok().filter(|&len| len <= i32::MAX as u32)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  let start = out.len();
+  out.extend_from_slice(kind);
+  out.extend_from_slice(data);
+  let crc = crc32(&out[start..]);
+  out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Wrap data in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  let mut out = vec![0x78, 0x01];
+  let mut blocks = data.chunks(65535).peekable();
+
+  if blocks.peek().is_none() {
+    out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+  }
+  while let Some(block) = blocks.next() {
+    let is_final = blocks.peek().is_none();
+    let len = block.len() as u16;
+    out.push(if is_final { 1 } else { 0 });
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(!len).to_le_bytes());
+    out.extend_from_slice(block);
+  }
+
+  out.extend_from_slice(&adler32(data).to_be_bytes());
+  out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xffff_ffffu32;
+  for &byte in data {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      let mask = (crc & 1).wrapping_neg();
+      crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+    }
+  }
+  !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image;
+  use image::GenericImage;
+
+  fn point(x: i16, y: i16, is_blank: bool) -> SimplePoint {
+    SimplePoint { x, y, r: 255, g: 0, b: 0, is_blank }
+  }
+
+  fn small() -> RenderOptions {
+    RenderOptions { width: 11, height: 11, ..RenderOptions::default() }
+  }
+
+  #[test]
+  fn test_render_frame() {
+    let frame = Frame::new(vec![
+      point(-32768, 32767, true),
+      point(32767, 32767, false),
+      point(32767, -32768, true),
+    ]);
+
+    let image = render_frame(&frame, &small());
+    assert_eq!(Some([255, 0, 0]), image.get_pixel(0, 0));
+    assert_eq!(Some([255, 0, 0]), image.get_pixel(10, 0));
+    assert_eq!(Some([0, 0, 0]), image.get_pixel(10, 5));
+
+    let mut options = small();
+    options.show_blanking = true;
+    let image = render_frame(&frame, &options);
+    assert_eq!(Some([64, 64, 64]), image.get_pixel(10, 5));
+  }
+
+  #[test]
+  fn test_contact_sheet() {
+    let frame = Frame::new(vec![point(0, 0, false)]);
+    let animation = Animation::new(vec![frame.clone(), frame.clone(), frame]);
+
+    let sheet = contact_sheet(&animation, 2, &small()).unwrap();
+    assert_eq!((22, 22), (sheet.width(), sheet.height()));
+    assert_eq!(Some([255, 0, 0]), sheet.get_pixel(5, 4));
+    assert_eq!(Some([255, 0, 0]), sheet.get_pixel(16, 4));
+    assert_eq!(Some([255, 0, 0]), sheet.get_pixel(5, 15));
+    assert_eq!(Some([0, 0, 0]), sheet.get_pixel(16, 15));
+
+    let huge = RenderOptions {
+      width: 1 << 20,
+      height: 1 << 20,
+      ..Default::default()
+    };
+    let result = contact_sheet(&animation, 3, &huge);
+    assert!(matches!(result, Err(IldaError::TooLarge)));
+    let wide = RenderOptions { width: u32::MAX, ..huge };
+    let result = contact_sheet(&animation, 3, &wide);
+    assert!(matches!(result, Err(IldaError::TooLarge)));
+  }
+
+  #[test]
+  fn test_png_decodes() {
+    let mut source = Image::new(300, 200, [1, 2, 3]);
+    source.draw_line((0, 0), (299, 199), [200, 100, 50]);
+
+    let decoded = image::load_from_memory(&source.to_png().unwrap()).unwrap();
+    assert_eq!((300, 200), decoded.dimensions());
+    let pixel = decoded.get_pixel(299, 199);
+    assert_eq!([200, 100, 50, 255], pixel.data);
+    let pixel = decoded.get_pixel(299, 0);
+    assert_eq!([1, 2, 3, 255], pixel.data);
+
+    // Checked before any pixels are read.
+    let huge = Image { width: 1 << 14, height: 1 << 16, pixels: Vec::new() };
+    assert!(matches!(huge.to_png(), Err(IldaError::TooLarge)));
+  }
+
+  #[test]
+  fn test_frame_to_svg() {
+    let frame = Frame::new(vec![point(0, 0, true), point(32767, 0, false)]);
+    let svg = frame_to_svg(&frame, &small());
+    assert!(svg.starts_with("<svg"));
+    assert_eq!(1, svg.matches("<line").count());
+    assert!(svg.contains("x1=\"5\" y1=\"4\" x2=\"10\" y2=\"4\" \
+        stroke=\"#ff0000\""));
+  }
+}
@@ -0,0 +1,410 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Point streams for output drivers. A DAC back-end doesn't care where its
+//! points come from; it repeatedly asks a `PointSource` for the next batch of
+//! points at its current scan rate. Frames, animations, procedural generators
+//! and playlists of any of these can all be used as sources.
+
+use animation::Animation;
+use animation::Frame;
+use point::SimplePoint;
+
+/// A stream of points that output drivers pull from.
+pub trait PointSource {
+  /// Return up to `count` points to be scanned at `points_per_second`. Fewer
+  /// points are returned only once the source is exhausted, and an empty
+  /// batch means the source has finished.
+  fn next_points(&mut self, count: usize, points_per_second: u32)
+      -> Vec<SimplePoint>;
+
+  /// Rewind the source to its beginning.
+  fn reset(&mut self) {}
+}
+
+/// Scans a single frame.
+#[derive(Clone)]
+pub struct FrameSource {
+  frame: Frame,
+  index: usize,
+  looping: bool,
+}
+
+/// Scans each frame of an animation in turn.
+#[derive(Clone)]
+pub struct AnimationSource {
+  animation: Animation,
+  frame_index: usize,
+  point_index: usize,
+  looping: bool,
+}
+
+/// Produces points from a function of the sample number and scan rate. The
+/// generator ends the stream by returning `None`.
+pub struct GeneratorSource<F> where F: FnMut(u64, u32) -> Option<SimplePoint> {
+  generator: F,
+  sample: u64,
+}
+
+/// Plays several sources back to back.
+pub struct Playlist {
+  sources: Vec<Box<dyn PointSource>>,
+  index: usize,
+  looping: bool,
+}
+
+/// Wraps a source so that it never runs dry; once the inner source is
+/// exhausted, blank points at the parking position are emitted instead.
+pub struct Parked<S: PointSource> {
+  source: S,
+  park: SimplePoint,
+}
+
+impl FrameSource {
+  /// Create a source that scans the frame once.
+  pub fn new(frame: Frame) -> FrameSource {
+    FrameSource { frame, index: 0, looping: false }
+  }
+
+  /// Whether the frame is scanned endlessly.
+  pub fn set_looping(&mut self, looping: bool) {
+    self.looping = looping;
+  }
+}
+
+impl PointSource for FrameSource {
+  fn next_points(&mut self, count: usize, _points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let points = self.frame.get_points();
+    let mut out = Vec::with_capacity(count);
+
+    while out.len() < count && !points.is_empty() {
+      if self.index >= points.len() {
+        if !self.looping {
+          break;
+        }
+        self.index = 0;
+      }
+      out.push(points[self.index]);
+      self.index += 1;
+    }
+
+    out
+  }
+
+  fn reset(&mut self) {
+    self.index = 0;
+  }
+}
+
+impl AnimationSource {
+  /// Create a source that scans every frame of the animation once.
+  pub fn new(animation: Animation) -> AnimationSource {
+    AnimationSource {
+      animation,
+      frame_index: 0,
+      point_index: 0,
+      looping: false,
+    }
+  }
+
+  /// Whether the animation starts over after the final frame.
+  pub fn set_looping(&mut self, looping: bool) {
+    self.looping = looping;
+  }
+}
+
+impl PointSource for AnimationSource {
+  fn next_points(&mut self, count: usize, _points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let mut out = Vec::with_capacity(count);
+
+    // Guard against looping forever over an animation with no points.
+    if self.animation.into_point_iter().next().is_none() {
+      return out;
+    }
+
+    while out.len() < count {
+      let frame = match self.animation.get_frame(self.frame_index) {
+        Some(frame) => frame,
+        None if self.looping => {
+          self.frame_index = 0;
+          continue;
+        },
+        None => break,
+      };
+
+      match frame.get_point(self.point_index) {
+        Some(point) => {
+          out.push(*point);
+          self.point_index += 1;
+        },
+        None => {
+          self.frame_index += 1;
+          self.point_index = 0;
+        },
+      }
+    }
+
+    out
+  }
+
+  fn reset(&mut self) {
+    self.frame_index = 0;
+    self.point_index = 0;
+  }
+}
+
+impl<F> GeneratorSource<F> where F: FnMut(u64, u32) -> Option<SimplePoint> {
+  /// Create a source from a generator function.
+  pub fn new(generator: F) -> GeneratorSource<F> {
+    GeneratorSource { generator, sample: 0 }
+  }
+}
+
+impl<F> PointSource for GeneratorSource<F>
+    where F: FnMut(u64, u32) -> Option<SimplePoint> {
+  fn next_points(&mut self, count: usize, points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let mut out = Vec::with_capacity(count);
+
+    while out.len() < count {
+      match (self.generator)(self.sample, points_per_second) {
+        Some(point) => out.push(point),
+        None => break,
+      }
+      self.sample += 1;
+    }
+
+    out
+  }
+
+  fn reset(&mut self) {
+    self.sample = 0;
+  }
+}
+
+impl Playlist {
+  /// Create an empty playlist.
+  pub fn new() -> Playlist {
+    Playlist {
+      sources: Vec::new(),
+      index: 0,
+      looping: false,
+    }
+  }
+
+  /// Append a source to the end of the playlist.
+  pub fn push<S: PointSource + 'static>(&mut self, source: S) {
+    self.sources.push(Box::new(source));
+  }
+
+  /// Return the number of sources in the playlist.
+  pub fn len(&self) -> usize {
+    self.sources.len()
+  }
+
+  /// Whether the playlist has no sources.
+  pub fn is_empty(&self) -> bool {
+    self.sources.is_empty()
+  }
+
+  /// Whether the playlist starts over after the final source.
+  pub fn set_looping(&mut self, looping: bool) {
+    self.looping = looping;
+  }
+}
+
+impl Default for Playlist {
+  fn default() -> Playlist {
+    Playlist::new()
+  }
+}
+
+impl PointSource for Playlist {
+  fn next_points(&mut self, count: usize, points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let mut out = Vec::with_capacity(count);
+    // Sources that yielded nothing since the last point; used to stop a
+    // looping playlist of exhausted sources from spinning forever.
+    let mut empty_run = 0;
+
+    while out.len() < count && empty_run <= self.sources.len() {
+      if self.index >= self.sources.len() {
+        if !self.looping || self.sources.is_empty() {
+          break;
+        }
+        self.reset();
+      }
+
+      let wanted = count - out.len();
+      let mut points = self.sources[self.index]
+          .next_points(wanted, points_per_second);
+
+      if points.len() < wanted {
+        self.index += 1;
+      }
+
+      if points.is_empty() {
+        empty_run += 1;
+      } else {
+        empty_run = 0;
+      }
+
+      out.append(&mut points);
+    }
+
+    out
+  }
+
+  fn reset(&mut self) {
+    self.index = 0;
+    for source in self.sources.iter_mut() {
+      source.reset();
+    }
+  }
+}
+
+impl<S: PointSource> Parked<S> {
+  /// Park the beam at the given position whenever the source is idle.
+  pub fn new(source: S, x: i16, y: i16) -> Parked<S> {
+    Parked {
+      source,
+      park: SimplePoint {
+        x,
+        y,
+        r: 0,
+        g: 0,
+        b: 0,
+        is_blank: true,
+      },
+    }
+  }
+
+  /// Get a mutable reference to the wrapped source.
+  pub fn get_source_mut(&mut self) -> &mut S {
+    &mut self.source
+  }
+}
+
+impl<S: PointSource> PointSource for Parked<S> {
+  fn next_points(&mut self, count: usize, points_per_second: u32)
+      -> Vec<SimplePoint> {
+    let mut out = self.source.next_points(count, points_per_second);
+    out.resize(count, self.park);
+    out
+  }
+
+  fn reset(&mut self) {
+    self.source.reset();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(color: u8) -> SimplePoint {
+    SimplePoint {
+      x: 0,
+      y: 0,
+      r: color,
+      g: color,
+      b: color,
+      is_blank: false,
+    }
+  }
+
+  fn colors(points: Vec<SimplePoint>) -> Vec<u8> {
+    points.iter().map(|point| point.r).collect()
+  }
+
+  #[test]
+  fn test_frame_source() {
+    let frame = Frame::new(vec![point(0), point(1), point(2)]);
+
+    let mut source = FrameSource::new(frame.clone());
+    assert_eq!(vec![0, 1], colors(source.next_points(2, 30_000)));
+    assert_eq!(vec![2], colors(source.next_points(2, 30_000)));
+    assert!(source.next_points(2, 30_000).is_empty());
+
+    let mut source = FrameSource::new(frame);
+    source.set_looping(true);
+    assert_eq!(vec![0, 1, 2, 0, 1, 2, 0],
+        colors(source.next_points(7, 30_000)));
+  }
+
+  #[test]
+  fn test_animation_source() {
+    let animation = Animation::new(vec![
+      Frame::new(vec![point(0), point(1)]),
+      Frame::new(Vec::new()),
+      Frame::new(vec![point(2)]),
+    ]);
+
+    let mut source = AnimationSource::new(animation.clone());
+    assert_eq!(vec![0, 1, 2], colors(source.next_points(5, 30_000)));
+    assert!(source.next_points(5, 30_000).is_empty());
+
+    let mut source = AnimationSource::new(animation);
+    source.set_looping(true);
+    assert_eq!(vec![0, 1, 2, 0, 1], colors(source.next_points(5, 30_000)));
+
+    let empty = Animation::new(vec![Frame::new(Vec::new())]);
+    let mut source = AnimationSource::new(empty);
+    source.set_looping(true);
+    assert!(source.next_points(5, 30_000).is_empty());
+  }
+
+  #[test]
+  fn test_generator_source() {
+    let mut source = GeneratorSource::new(|sample, pps| {
+      if sample < pps as u64 {
+        Some(point(sample as u8))
+      } else {
+        None
+      }
+    });
+
+    assert_eq!(vec![0, 1, 2], colors(source.next_points(3, 4)));
+    assert_eq!(vec![3], colors(source.next_points(3, 4)));
+
+    source.reset();
+    assert_eq!(vec![0], colors(source.next_points(1, 4)));
+  }
+
+  #[test]
+  fn test_playlist() {
+    let mut playlist = Playlist::new();
+    playlist.push(FrameSource::new(Frame::new(vec![point(0), point(1)])));
+    playlist.push(FrameSource::new(Frame::new(vec![point(2)])));
+    assert_eq!(2, playlist.len());
+
+    assert_eq!(vec![0, 1, 2], colors(playlist.next_points(4, 30_000)));
+    assert!(playlist.next_points(4, 30_000).is_empty());
+
+    playlist.set_looping(true);
+    assert_eq!(vec![0, 1, 2, 0, 1, 2, 0],
+        colors(playlist.next_points(7, 30_000)));
+
+    let mut empty = Playlist::new();
+    empty.set_looping(true);
+    empty.push(FrameSource::new(Frame::new(Vec::new())));
+    assert!(empty.next_points(4, 30_000).is_empty());
+  }
+
+  #[test]
+  fn test_parked() {
+    let frame = Frame::new(vec![point(1)]);
+    let mut source = Parked::new(FrameSource::new(frame), 100, -100);
+
+    let points = source.next_points(3, 30_000);
+    assert_eq!(3, points.len());
+    assert!(!points[0].is_blank);
+    assert!(points[1].is_blank);
+    assert_eq!(100, points[2].x);
+    assert_eq!(-100, points[2].y);
+
+    let points = source.next_points(2, 30_000);
+    assert!(points.iter().all(|point| point.is_blank));
+  }
+}
@@ -0,0 +1,494 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! A line oriented text form of ILDA data, for files that are diffed and
+//! edited by hand. It round trips losslessly with the binary form through
+//! `IldaEntry`.
+//!
+//! Each section starts with a header line of `key=value` fields, followed by
+//! one line per record:
+//!
+//! ```text
+//! # Lines starting with '#' are comments.
+//! header format=5 name="square" company="ilda.rs" number=0 total=1
+//! -100 -100 255 0 0 blank
+//! 100 -100 255 0 0 -
+//! -100 -100 255 0 0 last
+//! header format=5
+//! ```
+//!
+//! The columns of a record depend on the format of its header:
+//!
+//! * format 0: `x y z index flags`
+//! * format 1: `x y index flags`
+//! * format 2: `r g b`
+//! * format 4: `x y z r g b flags`
+//! * format 5: `x y r g b flags`
+//!
+//! Flags are `-`, `blank`, `last` or `blank,last`, or the raw status code as
+//! a number when other bits are set. Header fields other than `format` are
+//! optional. `records` defaults to the number of record lines that follow,
+//! so points can be added and removed without updating it, and the others
+//! default to zero and no name. The three `reserved` bytes are written in
+//! hex, as are `raw_name` and `raw_company`, which are only written when the
+//! name fields hold bytes that the names alone don't reproduce.
+
+use data::BLANK_BIT;
+use data::ColorPalette;
+use data::Header;
+use data::IldaEntry;
+use data::IndexedPoint2d;
+use data::IndexedPoint3d;
+use data::LAST_POINT_BIT;
+use data::TrueColorPoint2d;
+use data::TrueColorPoint3d;
+use data::encode_name;
+use error::IldaError;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Read ILDA data from a text file.
+pub fn read_file(filename: &str) -> Result<Vec<IldaEntry>, IldaError> {
+  let mut text = String::new();
+  File::open(filename)?.read_to_string(&mut text)?;
+  from_text(&text)
+}
+
+/// Write ILDA data to a text file.
+pub fn write_file(filename: &str, entries: &[IldaEntry])
+    -> Result<(), IldaError> {
+  let mut file = File::create(filename)?;
+  file.write_all(to_text(entries).as_bytes())?;
+  Ok(())
+}
+
+/// Parse ILDA data from text.
+pub fn from_text(text: &str) -> Result<Vec<IldaEntry>, IldaError> {
+  let mut entries = Vec::new();
+  // The current header's index in `entries`, and whether it gave a count.
+  let mut section: Option<(usize, bool)> = None;
+
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let invalid = |cause| IldaError::InvalidText { line: i + 1, cause };
+
+    if line == "header" || line.starts_with("header ") {
+      count_records(&mut entries, section);
+      let (header, counted) = parse_header(&line[6..]).map_err(invalid)?;
+      section = Some((entries.len(), counted));
+      entries.push(IldaEntry::HeaderEntry(header));
+      continue;
+    }
+
+    let format = match section {
+      Some((index, _)) => match entries[index] {
+        IldaEntry::HeaderEntry(ref header) => header.format_code,
+        _ => unreachable!(),
+      },
+      None => return Err(invalid("record before the first header".into())),
+    };
+    if let Some((index, false)) = section {
+      if entries.len() - index > u16::MAX as usize {
+        return Err(invalid("more than 65535 records in a section".into()));
+      }
+    }
+    entries.push(parse_record(line, format).map_err(invalid)?);
+  }
+
+  count_records(&mut entries, section);
+  Ok(entries)
+}
+
+/// Write ILDA data as text. Records are written in the columns of their own
+/// type, so they're read back correctly only if they match the format of
+/// their section's header.
+pub fn to_text(entries: &[IldaEntry]) -> String {
+  let mut out = String::new();
+
+  for (i, entry) in entries.iter().enumerate() {
+    match *entry {
+      IldaEntry::HeaderEntry(ref header) => {
+        let following = entries[i + 1..].iter()
+            .take_while(|entry| !matches!(**entry, IldaEntry::HeaderEntry(_)))
+            .count();
+        out.push_str(&header_line(header, following));
+      },
+      IldaEntry::IdxPoint3dEntry(ref p) => {
+        let _ = write!(out, "{} {} {} {}", p.x, p.y, p.z, p.color_index);
+        push_flags(&mut out, p.status_code);
+      },
+      IldaEntry::IdxPoint2dEntry(ref p) => {
+        let _ = write!(out, "{} {} {}", p.x, p.y, p.color_index);
+        push_flags(&mut out, p.status_code);
+      },
+      IldaEntry::ColorPaletteEntry(ref c) => {
+        let _ = writeln!(out, "{} {} {}", c.r, c.g, c.b);
+      },
+      IldaEntry::TcPoint3dEntry(ref p) => {
+        let _ = write!(out, "{} {} {} {} {} {}", p.x, p.y, p.z, p.r, p.g, p.b);
+        push_flags(&mut out, p.status_code);
+      },
+      IldaEntry::TcPoint2dEntry(ref p) => {
+        let _ = write!(out, "{} {} {} {} {}", p.x, p.y, p.r, p.g, p.b);
+        push_flags(&mut out, p.status_code);
+      },
+    }
+  }
+
+  out
+}
+
+fn header_line(header: &Header, following: usize) -> String {
+  let mut line = format!("header format={}", header.format_code);
+  if let Some(ref name) = header.name {
+    let _ = write!(line, " name={}", quote(name));
+  }
+  if let Some(ref company) = header.company_name {
+    let _ = write!(line, " company={}", quote(company));
+  }
+  if header.record_count as usize != following {
+    let _ = write!(line, " records={}", header.record_count);
+  }
+  let fields = [
+    ("number", header.number),
+    ("total", header.total_frames),
+    ("projector", header.projector_number as u16),
+    ("reserved2", header.reserved_2 as u16),
+  ];
+  for &(key, value) in fields.iter() {
+    if value != 0 {
+      let _ = write!(line, " {}={}", key, value);
+    }
+  }
+  if header.reserved != [0; 3] {
+    let _ = write!(line, " reserved={}", hex(&header.reserved));
+  }
+
+  let bytes = header.to_bytes();
+  if bytes[8..16] != encode_name(header.name.as_deref()) {
+    let _ = write!(line, " raw_name={}", hex(&bytes[8..16]));
+  }
+  if bytes[16..24] != encode_name(header.company_name.as_deref()) {
+    let _ = write!(line, " raw_company={}", hex(&bytes[16..24]));
+  }
+  line.push('\n');
+  line
+}
+
+fn push_flags(out: &mut String, status: u8) {
+  let flags = match status {
+    0 => "-".to_string(),
+    BLANK_BIT => "blank".to_string(),
+    LAST_POINT_BIT => "last".to_string(),
+    s if s == BLANK_BIT | LAST_POINT_BIT => "blank,last".to_string(),
+    s => s.to_string(),
+  };
+  out.push(' ');
+  out.push_str(&flags);
+  out.push('\n');
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Parse hex digits filling exactly the given field.
+fn parse_hex(name: &str, value: &str, field: &mut [u8]) -> Result<(), String> {
+  let invalid = || format!("invalid {} '{}'", name, value);
+  if !value.is_ascii() || value.len() != field.len() * 2 {
+    return Err(invalid());
+  }
+  for (i, byte) in field.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16)
+        .map_err(|_| invalid())?;
+  }
+  Ok(())
+}
+
+fn quote(value: &str) -> String {
+  let mut out = String::with_capacity(value.len() + 2);
+  out.push('"');
+  for c in value.chars() {
+    if c == '"' || c == '\\' {
+      out.push('\\');
+    }
+    out.push(c);
+  }
+  out.push('"');
+  out
+}
+
+// Fill in the record count of a section that didn't give one. Sections are
+// limited to 65535 records as they're read, so the count always fits.
+fn count_records(entries: &mut [IldaEntry], section: Option<(usize, bool)>) {
+  if let Some((index, false)) = section {
+    let count = (entries.len() - index - 1) as u16;
+    if let IldaEntry::HeaderEntry(ref mut header) = entries[index] {
+      header.record_count = count;
+    }
+  }
+}
+
+// Parse the fields after "header", returning whether a record count was
+// given.
+fn parse_header(fields: &str) -> Result<(Header, bool), String> {
+  let mut header = Header::new(0);
+  let mut format = None;
+  let mut counted = false;
+
+  for (key, value) in split_fields(fields)? {
+    match key.as_str() {
+      "format" => format = Some(parse_number(&key, &value)?),
+      "name" => header.name = Some(value),
+      "company" => header.company_name = Some(value),
+      "records" => {
+        header.record_count = parse_number(&key, &value)?;
+        counted = true;
+      },
+      "number" => header.number = parse_number(&key, &value)?,
+      "total" => header.total_frames = parse_number(&key, &value)?,
+      "projector" => header.projector_number = parse_number(&key, &value)?,
+      "reserved" => parse_hex(&key, &value, &mut header.reserved)?,
+      "raw_name" => parse_hex(&key, &value, &mut header.raw_name)?,
+      "raw_company" => parse_hex(&key, &value, &mut header.raw_company_name)?,
+      "reserved2" => header.reserved_2 = parse_number(&key, &value)?,
+      _ => return Err(format!("unknown header field '{}'", key)),
+    }
+  }
+
+  header.format_code = format.ok_or("header has no format")?;
+  Ok((header, counted))
+}
+
+// Split `key=value` fields, where values may be quoted.
+fn split_fields(fields: &str) -> Result<Vec<(String, String)>, String> {
+  let mut out = Vec::new();
+  let mut chars = fields.trim().chars().peekable();
+
+  while chars.peek().is_some() {
+    let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+      return Err(format!("expected key=value, found '{}'", key.trim()));
+    }
+
+    let mut value = String::new();
+    if chars.peek() == Some(&'"') {
+      chars.next();
+      loop {
+        match chars.next() {
+          Some('"') => break,
+          Some('\\') => value.extend(chars.next()),
+          Some(c) => value.push(c),
+          None => return Err(format!("unterminated value for '{}'", key)),
+        }
+      }
+      if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+        return Err(format!("expected a space after '{}'", key));
+      }
+    } else {
+      value = chars.by_ref().take_while(|c| !c.is_whitespace()).collect();
+    }
+
+    out.push((key, value));
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+      chars.next();
+    }
+  }
+
+  Ok(out)
+}
+
+fn parse_record(line: &str, format: u8) -> Result<IldaEntry, String> {
+  let columns: Vec<&str> = line.split_whitespace().collect();
+  let expected = match format {
+    0 => 5,
+    1 => 4,
+    2 => 3,
+    4 => 7,
+    5 => 6,
+    _ => return Err(format!("records can't follow a format {} header",
+        format)),
+  };
+  if columns.len() != expected {
+    return Err(format!("expected {} columns for format {}, found {}",
+        expected, format, columns.len()));
+  }
+
+  let n = |i: usize| columns[i];
+  Ok(match format {
+    0 => IldaEntry::IdxPoint3dEntry(IndexedPoint3d {
+      x: parse_number("x", n(0))?,
+      y: parse_number("y", n(1))?,
+      z: parse_number("z", n(2))?,
+      color_index: parse_number("index", n(3))?,
+      status_code: parse_flags(n(4))?,
+    }),
+    1 => IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+      x: parse_number("x", n(0))?,
+      y: parse_number("y", n(1))?,
+      color_index: parse_number("index", n(2))?,
+      status_code: parse_flags(n(3))?,
+    }),
+    2 => IldaEntry::ColorPaletteEntry(ColorPalette {
+      r: parse_number("r", n(0))?,
+      g: parse_number("g", n(1))?,
+      b: parse_number("b", n(2))?,
+    }),
+    4 => IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+      x: parse_number("x", n(0))?,
+      y: parse_number("y", n(1))?,
+      z: parse_number("z", n(2))?,
+      r: parse_number("r", n(3))?,
+      g: parse_number("g", n(4))?,
+      b: parse_number("b", n(5))?,
+      status_code: parse_flags(n(6))?,
+    }),
+    _ => IldaEntry::TcPoint2dEntry(TrueColorPoint2d {
+      x: parse_number("x", n(0))?,
+      y: parse_number("y", n(1))?,
+      r: parse_number("r", n(2))?,
+      g: parse_number("g", n(3))?,
+      b: parse_number("b", n(4))?,
+      status_code: parse_flags(n(5))?,
+    }),
+  })
+}
+
+fn parse_flags(flags: &str) -> Result<u8, String> {
+  if let Ok(status) = flags.parse() {
+    return Ok(status);
+  }
+  if flags == "-" {
+    return Ok(0);
+  }
+  let mut status = 0;
+  for flag in flags.split(',') {
+    status |= match flag {
+      "blank" => BLANK_BIT,
+      "last" => LAST_POINT_BIT,
+      _ => return Err(format!("unknown flag '{}'", flag)),
+    };
+  }
+  Ok(status)
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+  value.parse().map_err(|_| format!("invalid {} '{}'", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser;
+  use writer;
+
+  #[test]
+  fn test_from_text() {
+    let text = "\
+# A palette, then a frame.
+header format=2 name=\"colors\" number=3
+255 0 0
+0 0 255
+
+header format=1 name=\"say \\\"hi\\\"\" company=pal projector=2
+-10 20 1 blank
+30 -40 0 last
+header format=5
+";
+    let entries = from_text(text).unwrap();
+    assert_eq!(7, entries.len());
+
+    match entries[0] {
+      IldaEntry::HeaderEntry(ref header) => {
+        assert_eq!(2, header.format_code);
+        assert_eq!(Some("colors"), header.name.as_deref());
+        assert_eq!(3, header.number);
+        assert_eq!(2, header.record_count);
+      },
+      _ => panic!("expected a header"),
+    }
+    match entries[3] {
+      IldaEntry::HeaderEntry(ref header) => {
+        assert_eq!(Some("say \"hi\""), header.name.as_deref());
+        assert_eq!(Some("pal"), header.company_name.as_deref());
+        assert_eq!(2, header.projector_number);
+      },
+      _ => panic!("expected a header"),
+    }
+    match entries[4] {
+      IldaEntry::IdxPoint2dEntry(ref point) => {
+        assert_eq!((-10, 20, 1, 64),
+            (point.x, point.y, point.color_index, point.status_code));
+      },
+      _ => panic!("expected a point"),
+    }
+    match entries[6] {
+      IldaEntry::HeaderEntry(ref header) => assert_eq!(0, header.record_count),
+      _ => panic!("expected a header"),
+    }
+  }
+
+  #[test]
+  fn test_binary_round_trip() {
+    let bytes = include_bytes!("../examples/files/ildatest.ild");
+    let entries = parser::read_bytes(bytes).unwrap();
+
+    let text = to_text(&entries);
+    let read = from_text(&text).unwrap();
+
+    assert_eq!(writer::write_bytes(&entries), writer::write_bytes(&read));
+    assert_eq!(text, to_text(&read));
+  }
+
+  #[test]
+  fn test_to_text_keeps_odd_values() {
+    let mut header = Header::new(4);
+    header.record_count = 5;
+    header.reserved_2 = 7;
+    header.reserved = [0, 1, 0xff];
+    header.name = Some("ab".to_string());
+    header.raw_name = *b"ab\0cdefg";
+    let point = TrueColorPoint3d {
+      x: 1, y: 2, z: -3, r: 4, g: 5, b: 6, status_code: 3,
+    };
+    let entries = vec![IldaEntry::HeaderEntry(header),
+        IldaEntry::TcPoint3dEntry(point)];
+
+    let text = to_text(&entries);
+    assert_eq!("header format=4 name=\"ab\" records=5 reserved2=7 \
+        reserved=0001ff raw_name=6162006364656667\n1 2 -3 4 5 6 3\n", text);
+    assert_eq!(writer::write_bytes(&entries),
+        writer::write_bytes(&from_text(&text).unwrap()));
+  }
+
+  #[test]
+  fn test_errors() {
+    let line = |text: &str| match from_text(text) {
+      Err(IldaError::InvalidText { line, .. }) => line,
+      other => panic!("unexpected result: {:?}", other.map(|e| e.len())),
+    };
+
+    assert_eq!(1, line("1 2 3 4 5 -"));
+    assert_eq!(2, line("header format=5\n1 2 3 4 -"));
+    assert_eq!(2, line("header format=5\n1 2 3 4 5 dim"));
+    assert_eq!(3, line("#\nheader format=3\n1 2 3"));
+    assert_eq!(1, line("header name=\"x\""));
+    assert_eq!(1, line("header format=5 name=\"x"));
+    assert_eq!(1, line("header format=5 colour=1"));
+    assert_eq!(1, line("header format=5 reserved=1"));
+    assert_eq!(1, line("header format=5 raw_name=00000000000000zz"));
+
+    let records = "0 0 0\n".repeat(u16::MAX as usize);
+    let text = format!("header format=2\n{}", records);
+    assert_eq!(65535, match from_text(&text).unwrap()[0] {
+      IldaEntry::HeaderEntry(ref header) => header.record_count,
+      _ => 0,
+    });
+    assert_eq!(65537, line(&format!("{}0 0 0\n", text)));
+  }
+}
@@ -0,0 +1,496 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Checking parsed ILDA data against the ILDA Image Data Transfer Format
+//! specification, revision 011.
+//!
+//! The parser accepts anything it can make sense of. Validation reports where
+//! a file strays from the specification, from structural problems that other
+//! software may read differently (errors) down to conventions that are often
+//! ignored in practice (warnings) and things that are merely unusual (info).
+
+use data::BLANK_BIT;
+use data::Header;
+use data::IldaEntry;
+use data::LAST_POINT_BIT;
+use palette::MAX_COLORS;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+const STANDARD_PALETTE_SIZE: usize = 64;
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// Legal, but unusual.
+  Info,
+  /// Breaks a convention of the specification that readers usually
+  /// tolerate.
+  Warning,
+  /// Breaks the structure of the file, so that readers may disagree about
+  /// its contents.
+  Error,
+}
+
+/// The check that produced a diagnostic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+  /// Frames are numbered consecutively from zero for each projector.
+  FrameNumber,
+  /// Every frame of a projector gives the projector's frame count as its
+  /// total.
+  TotalFrames,
+  /// The last point bit is set on the final record of a frame, and only
+  /// there.
+  LastPoint,
+  /// The file ends with a header with no records.
+  Terminator,
+  /// Records match the format of their header.
+  Format,
+  /// Headers give the number of records that follow them.
+  RecordCount,
+  /// Palettes have at most 256 colors, and indexed colors are within the
+  /// palette.
+  Palette,
+  /// Reserved header fields and status bits are zero.
+  Reserved,
+  /// Names are printable ASCII of at most 8 characters.
+  NameEncoding,
+  /// Frames have a reasonable number of points.
+  PointCount,
+}
+
+/// A problem found while validating.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+  /// How serious the problem is.
+  pub severity: Severity,
+  /// The check that failed.
+  pub check: Check,
+  /// Index of the section (header) it concerns, counting from zero.
+  pub section: Option<usize>,
+  /// Index of the record within the section, counting from zero.
+  pub record: Option<usize>,
+  /// Description of the problem.
+  pub message: String,
+}
+
+/// Validation settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidateOptions {
+  /// Frames with more points than this are reported, as few scanners can
+  /// draw them without flicker.
+  pub max_points: usize,
+}
+
+impl Default for ValidateOptions {
+  fn default() -> ValidateOptions {
+    ValidateOptions {
+      max_points: 4000,
+    }
+  }
+}
+
+impl Severity {
+  /// Lowercase name, eg. "warning".
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Severity::Info => "info",
+      Severity::Warning => "warning",
+      Severity::Error => "error",
+    }
+  }
+}
+
+impl Check {
+  /// Stable name for the check, eg. "frame-number".
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Check::FrameNumber => "frame-number",
+      Check::TotalFrames => "total-frames",
+      Check::LastPoint => "last-point",
+      Check::Terminator => "terminator",
+      Check::Format => "format",
+      Check::RecordCount => "record-count",
+      Check::Palette => "palette",
+      Check::Reserved => "reserved",
+      Check::NameEncoding => "name-encoding",
+      Check::PointCount => "point-count",
+    }
+  }
+}
+
+impl Display for Diagnostic {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{}: ", self.severity.name())?;
+    match (self.section, self.record) {
+      (Some(section), Some(record)) => {
+        write!(f, "section {}, record {}: ", section, record)?
+      },
+      (Some(section), None) => write!(f, "section {}: ", section)?,
+      _ => {},
+    }
+    write!(f, "{} [{}]", self.message, self.check.name())
+  }
+}
+
+/// Validate entries, returning diagnostics in file order.
+pub fn validate(entries: &[IldaEntry], options: &ValidateOptions)
+    -> Vec<Diagnostic> {
+  let mut validator = Validator {
+    options,
+    out: Vec::new(),
+    section: None,
+    header: None,
+    records: 0,
+    last_points: 0,
+    lit_points: 0,
+    palette_size: STANDARD_PALETTE_SIZE,
+    palette_colors: None,
+    frames: Vec::new(),
+    ended: false,
+  };
+
+  for entry in entries {
+    match *entry {
+      IldaEntry::HeaderEntry(ref header) => validator.header(header),
+      ref record => validator.record(record),
+    }
+  }
+  validator.finish();
+
+  validator.out
+}
+
+// Validation state while walking through the entries.
+struct Validator<'a> {
+  options: &'a ValidateOptions,
+  out: Vec<Diagnostic>,
+  // Index and header of the current section.
+  section: Option<usize>,
+  header: Option<Header>,
+  // Records seen in the current section, how many had the last bit set and
+  // how many were lit.
+  records: usize,
+  last_points: usize,
+  lit_points: usize,
+  // Colors in the palette in effect, and in a palette being read.
+  palette_size: usize,
+  palette_colors: Option<usize>,
+  // Section index and header of every frame.
+  frames: Vec<(usize, Header)>,
+  // Whether a terminating header has been seen.
+  ended: bool,
+}
+
+impl<'a> Validator<'a> {
+  fn header(&mut self, header: &Header) {
+    self.end_section();
+    let section = self.section.map_or(0, |i| i + 1);
+    self.section = Some(section);
+    self.header = Some(header.clone());
+    self.records = 0;
+    self.last_points = 0;
+    self.lit_points = 0;
+
+    if self.ended {
+      self.report(Severity::Error, Check::Terminator, None,
+          "data follows the terminating header".to_string());
+      self.ended = false;
+    }
+    if header.record_count == 0 {
+      self.ended = true;
+    }
+
+    match header.format_code {
+      code if is_frame_format(code) => {
+        if header.record_count > 0 {
+          self.frames.push((section, header.clone()));
+        }
+        if header.record_count as usize > self.options.max_points {
+          self.report(Severity::Warning, Check::PointCount, None,
+              format!("{} points, more than {}", header.record_count,
+              self.options.max_points));
+        }
+      },
+      2 => {
+        if header.record_count as usize > MAX_COLORS {
+          self.report(Severity::Error, Check::Palette, None,
+              format!("{} colors, more than {}", header.record_count,
+              MAX_COLORS));
+        }
+        if header.record_count > 0 {
+          self.palette_colors = Some(0);
+        }
+      },
+      code => {
+        self.report(Severity::Error, Check::Format, None,
+            format!("unknown format {}", code));
+      },
+    }
+
+    if header.reserved != [0; 3] || header.reserved_2 != 0 {
+      self.report(Severity::Warning, Check::Reserved, None,
+          "reserved header bytes aren't zero".to_string());
+    }
+    let bytes = header.to_bytes();
+    self.check_name("name", &header.name, &bytes[8..16]);
+    self.check_name("company name", &header.company_name, &bytes[16..24]);
+  }
+
+  fn record(&mut self, record: &IldaEntry) {
+    let header = match self.header {
+      Some(ref header) => header.clone(),
+      None => {
+        self.report(Severity::Error, Check::Format, None,
+            "record before the first header".to_string());
+        return;
+      },
+    };
+    let index = self.records;
+    self.records += 1;
+
+    let (format_code, status, color_index) = match *record {
+      IldaEntry::IdxPoint3dEntry(ref p) => {
+        (0, p.status_code, Some(p.color_index))
+      },
+      IldaEntry::IdxPoint2dEntry(ref p) => {
+        (1, p.status_code, Some(p.color_index))
+      },
+      IldaEntry::ColorPaletteEntry(_) => {
+        if let Some(ref mut colors) = self.palette_colors {
+          *colors += 1;
+        }
+        (2, 0, None)
+      },
+      IldaEntry::TcPoint3dEntry(ref p) => (4, p.status_code, None),
+      IldaEntry::TcPoint2dEntry(ref p) => (5, p.status_code, None),
+      IldaEntry::HeaderEntry(_) => return,
+    };
+
+    if format_code != header.format_code {
+      self.report(Severity::Error, Check::Format, Some(index),
+          format!("format {} record in a format {} section", format_code,
+          header.format_code));
+    }
+    if format_code == 2 {
+      return;
+    }
+
+    if status & LAST_POINT_BIT != 0 {
+      self.last_points += 1;
+      if index + 1 != header.record_count as usize {
+        self.report(Severity::Warning, Check::LastPoint, Some(index),
+            "last point bit set before the final record".to_string());
+      }
+    }
+    if status & BLANK_BIT == 0 {
+      self.lit_points += 1;
+    }
+    if status & !(BLANK_BIT | LAST_POINT_BIT) != 0 {
+      self.report(Severity::Warning, Check::Reserved, Some(index),
+          format!("reserved status bits set ({:#04x})", status));
+    }
+    if let Some(color) = color_index {
+      if color as usize >= self.palette_size {
+        self.report(Severity::Warning, Check::Palette, Some(index),
+            format!("color index {} outside the {} color palette", color,
+            self.palette_size));
+      }
+    }
+  }
+
+  // Checks made once all of a section's records are seen.
+  fn end_section(&mut self) {
+    let header = match self.header.take() {
+      Some(header) => header,
+      None => return,
+    };
+
+    if self.records != header.record_count as usize {
+      self.report(Severity::Error, Check::RecordCount, None,
+          format!("header gives {} records, but {} follow",
+          header.record_count, self.records));
+    }
+
+    if let Some(colors) = self.palette_colors.take() {
+      self.palette_size = colors.min(MAX_COLORS);
+    } else if is_frame_format(header.format_code) && self.records > 0 {
+      if self.last_points == 0 {
+        self.report(Severity::Warning, Check::LastPoint, None,
+            "last point bit not set on the final record".to_string());
+      }
+      if self.lit_points == 0 {
+        self.report(Severity::Info, Check::PointCount, None,
+            "every point is blanked".to_string());
+      }
+    }
+  }
+
+  fn finish(&mut self) {
+    self.end_section();
+
+    if !self.ended {
+      self.push(Severity::Warning, Check::Terminator, None, None,
+          "missing terminating header".to_string());
+    }
+
+    let mut counts = BTreeMap::new();
+    for (_, header) in self.frames.iter() {
+      *counts.entry(header.projector_number).or_insert(0usize) += 1;
+    }
+
+    let mut numbers = BTreeMap::new();
+    let frames = ::std::mem::take(&mut self.frames);
+    for (section, header) in frames {
+      let expected = numbers.entry(header.projector_number).or_insert(0usize);
+      if header.number as usize != *expected {
+        self.push(Severity::Warning, Check::FrameNumber, Some(section), None,
+            format!("frame number {}, expected {}", header.number, expected));
+      }
+      *expected += 1;
+
+      let count = counts[&header.projector_number];
+      if header.total_frames as usize != count {
+        self.push(Severity::Warning, Check::TotalFrames, Some(section), None,
+            format!("total frames {}, but projector {} has {} frames",
+            header.total_frames, header.projector_number, count));
+      }
+    }
+
+    // Keep diagnostics in file order, with file-level ones last.
+    self.out.sort_by_key(|d| (d.section.is_none(), d.section, d.record));
+  }
+
+  // Check a name and the field it will be written as.
+  fn check_name(&mut self, field: &str, name: &Option<String>, bytes: &[u8]) {
+    if let Some(ref name) = *name {
+      if name.chars().count() > 8 {
+        self.report(Severity::Warning, Check::NameEncoding, None,
+            format!("{} {:?} is longer than 8 characters", field, name));
+      }
+    }
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    if !bytes[..end].iter().all(|byte| (0x20..0x7f).contains(byte)) {
+      self.report(Severity::Warning, Check::NameEncoding, None,
+          format!("{} isn't printable ASCII", field));
+    }
+    if bytes[end..].iter().any(|&byte| byte != 0) {
+      self.report(Severity::Warning, Check::NameEncoding, None,
+          format!("{} has data after its terminating NUL", field));
+    }
+  }
+
+  // Report a problem in the current section.
+  fn report(&mut self, severity: Severity, check: Check,
+      record: Option<usize>, message: String) {
+    let section = self.section;
+    self.push(severity, check, section, record, message);
+  }
+
+  fn push(&mut self, severity: Severity, check: Check, section: Option<usize>,
+      record: Option<usize>, message: String) {
+    self.out.push(Diagnostic { severity, check, section, record, message });
+  }
+}
+
+fn is_frame_format(format_code: u8) -> bool {
+  matches!(format_code, 0 | 1 | 4 | 5)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use data::ColorPalette;
+  use data::IndexedPoint2d;
+  use data::TrueColorPoint2d;
+
+  fn header(format_code: u8, record_count: u16, number: u16, total: u16)
+      -> IldaEntry {
+    let mut header = Header::new(format_code);
+    header.record_count = record_count;
+    header.number = number;
+    header.total_frames = total;
+    IldaEntry::HeaderEntry(header)
+  }
+
+  fn point(status_code: u8) -> IldaEntry {
+    let point = TrueColorPoint2d {
+      status_code,
+      ..Default::default()
+    };
+    IldaEntry::TcPoint2dEntry(point)
+  }
+
+  fn checks(entries: &[IldaEntry]) -> Vec<(Severity, Check, Option<usize>)> {
+    validate(entries, &ValidateOptions::default()).into_iter()
+        .map(|d| (d.severity, d.check, d.section))
+        .collect()
+  }
+
+  #[test]
+  fn test_valid() {
+    let entries = vec![
+      header(5, 2, 0, 2), point(64), point(128),
+      header(5, 1, 1, 2), point(128),
+      header(5, 0, 0, 0),
+    ];
+    assert!(checks(&entries).is_empty());
+  }
+
+  #[test]
+  fn test_structure() {
+    let mut named = Header::new(5);
+    named.name = Some("café".to_string());
+    named.reserved_2 = 1;
+    let entries = vec![
+      header(5, 3, 1, 1), point(128), point(0),
+      IldaEntry::HeaderEntry(named),
+      header(3, 0, 0, 0),
+      point(0),
+    ];
+
+    use self::Check::*;
+    use self::Severity::*;
+    assert_eq!(vec![
+      (Error, RecordCount, Some(0)),
+      (Warning, FrameNumber, Some(0)),
+      (Warning, LastPoint, Some(0)),
+      (Warning, Reserved, Some(1)),
+      (Warning, NameEncoding, Some(1)),
+      (Error, Terminator, Some(2)),
+      (Error, Format, Some(2)),
+      (Error, RecordCount, Some(2)),
+      (Error, Format, Some(2)),
+    ], checks(&entries));
+    assert_eq!(vec![(Warning, Terminator, None)],
+        checks(&[header(5, 1, 0, 1), point(128)]));
+  }
+
+  #[test]
+  fn test_palette_and_points() {
+    let mut entries = vec![header(2, 2, 0, 0)];
+    entries.extend((0..2).map(|_| {
+      IldaEntry::ColorPaletteEntry(ColorPalette { r: 0, g: 0, b: 0 })
+    }));
+    let point = IndexedPoint2d {
+      color_index: 2,
+      status_code: 128 | 1,
+      ..Default::default()
+    };
+    entries.push(header(1, 1, 0, 1));
+    entries.push(IldaEntry::IdxPoint2dEntry(point));
+    entries.push(header(5, 0, 0, 0));
+
+    let diagnostics = validate(&entries, &ValidateOptions::default());
+    assert_eq!(2, diagnostics.len());
+    assert_eq!("warning: section 1, record 0: reserved status bits set \
+        (0x81) [reserved]", diagnostics[0].to_string());
+    assert_eq!(Check::Palette, diagnostics[1].check);
+
+    let options = ValidateOptions { max_points: 0 };
+    let diagnostics = validate(&entries, &options);
+    assert_eq!(Check::PointCount, diagnostics[0].check);
+  }
+}
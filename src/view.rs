@@ -0,0 +1,452 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Zero-copy access to ILDA data.
+//!
+//! Views read headers and records straight out of a byte buffer as they're
+//! accessed, so large files can be scanned, indexed and sampled without
+//! allocating anything per point. With the `mmap` feature, `MappedFile`
+//! provides the buffer by memory mapping a file.
+//!
+//! ```
+//! # use ilda::view::IldaView;
+//! let bytes = ilda::writer::write_bytes(&[]);
+//! for section in IldaView::new(&bytes).sections() {
+//!   let section = section.unwrap();
+//!   println!("{} points", section.len());
+//! }
+//! ```
+
+use data::BLANK_BIT;
+use data::ColorPalette;
+use data::Format;
+use data::HEADER_SIZE;
+use data::Header;
+use data::IldaEntry;
+use data::IndexedPoint2d;
+use data::IndexedPoint3d;
+use data::LAST_POINT_BIT;
+use data::TrueColorPoint2d;
+use data::TrueColorPoint3d;
+use data::decode_name;
+use error::IldaError;
+use parser::read_header;
+
+/// A view over raw ILDA data.
+#[derive(Clone, Copy, Debug)]
+pub struct IldaView<'a> {
+  bytes: &'a [u8],
+}
+
+/// A header and its records, borrowed from the underlying buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct SectionView<'a> {
+  offset: usize,
+  header: &'a [u8],
+  records: &'a [u8],
+  format: Format,
+}
+
+/// A single point or color record, borrowed from the underlying buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct RecordView<'a> {
+  bytes: &'a [u8],
+  format: Format,
+}
+
+/// Iterator over the sections of a view. After an error, iteration ends.
+pub struct SectionIter<'a> {
+  bytes: &'a [u8],
+  offset: usize,
+  failed: bool,
+}
+
+/// Iterator over the records of a section.
+pub struct RecordIter<'a> {
+  section: SectionView<'a>,
+  index: usize,
+}
+
+impl<'a> IldaView<'a> {
+  /// Create a view over ILDA bytes. Nothing is read until it's accessed.
+  pub fn new(bytes: &'a [u8]) -> IldaView<'a> {
+    IldaView { bytes }
+  }
+
+  /// The underlying bytes.
+  pub fn as_bytes(&self) -> &'a [u8] {
+    self.bytes
+  }
+
+  /// Iterate over the sections in order.
+  pub fn sections(&self) -> SectionIter<'a> {
+    SectionIter { bytes: self.bytes, offset: 0, failed: false }
+  }
+
+  /// Read the section starting at the given byte offset, eg. one found
+  /// earlier with `SectionView::offset`.
+  pub fn section_at(&self, offset: usize)
+      -> Result<SectionView<'a>, IldaError> {
+    SectionView::read(self.bytes, offset)
+  }
+}
+
+impl<'a> SectionView<'a> {
+  fn read(bytes: &'a [u8], offset: usize)
+      -> Result<SectionView<'a>, IldaError> {
+    let start = offset.checked_add(HEADER_SIZE)
+        .ok_or(IldaError::InvalidHeader)?;
+    let header = bytes.get(offset .. start)
+        .filter(|header| &header[0..4] == b"ILDA")
+        .ok_or(IldaError::InvalidHeader)?;
+
+    let format = Header::new(header[7]).get_format();
+    let record_size = format.record_size().ok_or(IldaError::InvalidHeader)?;
+    let count = read_u16(&header[24..26]) as usize;
+    let end = count.checked_mul(record_size)
+        .and_then(|len| start.checked_add(len))
+        .ok_or(IldaError::InvalidData)?;
+    let records = bytes.get(start .. end).ok_or(IldaError::InvalidData)?;
+
+    Ok(SectionView { offset, header, records, format })
+  }
+
+  /// Byte offset of the section's header.
+  pub fn offset(&self) -> usize {
+    self.offset
+  }
+
+  /// Size of the section in bytes, including its header.
+  pub fn byte_len(&self) -> usize {
+    HEADER_SIZE + self.records.len()
+  }
+
+  /// The raw 32 byte header.
+  pub fn header_bytes(&self) -> &'a [u8] {
+    self.header
+  }
+
+  /// The raw bytes of every record.
+  pub fn record_bytes(&self) -> &'a [u8] {
+    self.records
+  }
+
+  /// Decode the header.
+  pub fn header(&self) -> Header {
+    // The header was checked when the view was created.
+    read_header(self.header).unwrap_or_else(|_| unreachable!())
+  }
+
+  /// The format of the section's records.
+  pub fn get_format(&self) -> Format {
+    self.format
+  }
+
+  /// The format code.
+  pub fn format_code(&self) -> u8 {
+    self.header[7]
+  }
+
+  /// The raw name field.
+  pub fn raw_name(&self) -> &'a [u8] {
+    &self.header[8..16]
+  }
+
+  /// The name of the frame or palette, decoded with `decode_name`.
+  pub fn name(&self) -> Option<String> {
+    decode_name(self.raw_name())
+  }
+
+  /// The raw company name field.
+  pub fn raw_company_name(&self) -> &'a [u8] {
+    &self.header[16..24]
+  }
+
+  /// The company name, decoded with `decode_name`.
+  pub fn company_name(&self) -> Option<String> {
+    decode_name(self.raw_company_name())
+  }
+
+  /// The number of records. Zero marks the end of the file.
+  pub fn len(&self) -> usize {
+    read_u16(&self.header[24..26]) as usize
+  }
+
+  /// Whether the section has no records, which marks the end of the file.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// The frame or palette number.
+  pub fn number(&self) -> u16 {
+    read_u16(&self.header[26..28])
+  }
+
+  /// The total number of frames.
+  pub fn total_frames(&self) -> u16 {
+    read_u16(&self.header[28..30])
+  }
+
+  /// The projector number.
+  pub fn projector_number(&self) -> u8 {
+    self.header[30]
+  }
+
+  /// The record at the given index, if it exists.
+  pub fn record(&self, index: usize) -> Option<RecordView<'a>> {
+    let size = self.format.record_size()?;
+    self.records.get(index * size .. (index + 1) * size)
+        .map(|bytes| RecordView { bytes, format: self.format })
+  }
+
+  /// Iterate over the records.
+  pub fn records(&self) -> RecordIter<'a> {
+    RecordIter { section: *self, index: 0 }
+  }
+
+  /// Decode the header and records into entries.
+  pub fn to_entries(&self) -> Vec<IldaEntry> {
+    let mut entries = Vec::with_capacity(self.len() + 1);
+    entries.push(IldaEntry::HeaderEntry(self.header()));
+    entries.extend(self.records().map(|record| record.to_entry()));
+    entries
+  }
+}
+
+impl<'a> RecordView<'a> {
+  /// The raw bytes of the record.
+  pub fn as_bytes(&self) -> &'a [u8] {
+    self.bytes
+  }
+
+  /// The format of the record.
+  pub fn get_format(&self) -> Format {
+    self.format
+  }
+
+  /// X coordinate, or zero for colors.
+  pub fn x(&self) -> i16 {
+    match self.format {
+      Format::ColorPalette => 0,
+      _ => read_i16(self.bytes, 0),
+    }
+  }
+
+  /// Y coordinate, or zero for colors.
+  pub fn y(&self) -> i16 {
+    match self.format {
+      Format::ColorPalette => 0,
+      _ => read_i16(self.bytes, 2),
+    }
+  }
+
+  /// Z coordinate, or zero for 2D points and colors.
+  pub fn z(&self) -> i16 {
+    if self.format.is_3d() { read_i16(self.bytes, 4) } else { 0 }
+  }
+
+  /// The status code, or zero for colors.
+  pub fn status_code(&self) -> u8 {
+    match self.format {
+      Format::Indexed3d | Format::TrueColor3d => self.bytes[6],
+      Format::Indexed2d | Format::TrueColor2d => self.bytes[4],
+      _ => 0,
+    }
+  }
+
+  /// Whether the point is a blanking point.
+  pub fn is_blank(&self) -> bool {
+    self.status_code() & BLANK_BIT == BLANK_BIT
+  }
+
+  /// Whether the point is marked as the last point of its frame.
+  pub fn is_last_point(&self) -> bool {
+    self.status_code() & LAST_POINT_BIT == LAST_POINT_BIT
+  }
+
+  /// The color index of an indexed point.
+  pub fn color_index(&self) -> Option<u8> {
+    match self.format {
+      Format::Indexed3d => Some(self.bytes[7]),
+      Format::Indexed2d => Some(self.bytes[5]),
+      _ => None,
+    }
+  }
+
+  /// The color of a true color point or palette color, as `(r, g, b)`.
+  pub fn rgb(&self) -> Option<(u8, u8, u8)> {
+    let b = self.bytes;
+    match self.format {
+      Format::TrueColor3d => Some((b[9], b[8], b[7])),
+      Format::TrueColor2d => Some((b[7], b[6], b[5])),
+      Format::ColorPalette => Some((b[0], b[1], b[2])),
+      _ => None,
+    }
+  }
+
+  /// Decode the record into an entry.
+  pub fn to_entry(&self) -> IldaEntry {
+    let (r, g, b) = self.rgb().unwrap_or((0, 0, 0));
+    let (x, y, z) = (self.x(), self.y(), self.z());
+    let status_code = self.status_code();
+    let color_index = self.color_index().unwrap_or(0);
+    match self.format {
+      Format::Indexed3d => IldaEntry::IdxPoint3dEntry(IndexedPoint3d {
+        x, y, z, status_code, color_index,
+      }),
+      Format::Indexed2d => IldaEntry::IdxPoint2dEntry(IndexedPoint2d {
+        x, y, status_code, color_index,
+      }),
+      Format::TrueColor3d => IldaEntry::TcPoint3dEntry(TrueColorPoint3d {
+        x, y, z, status_code, r, g, b,
+      }),
+      Format::TrueColor2d => IldaEntry::TcPoint2dEntry(TrueColorPoint2d {
+        x, y, status_code, r, g, b,
+      }),
+      _ => IldaEntry::ColorPaletteEntry(ColorPalette { r, g, b }),
+    }
+  }
+}
+
+impl<'a> Iterator for SectionIter<'a> {
+  type Item = Result<SectionView<'a>, IldaError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed || self.offset >= self.bytes.len() {
+      return None;
+    }
+    match SectionView::read(self.bytes, self.offset) {
+      Ok(section) => {
+        self.offset += section.byte_len();
+        Some(Ok(section))
+      },
+      Err(error) => {
+        self.failed = true;
+        Some(Err(error))
+      },
+    }
+  }
+}
+
+impl<'a> Iterator for RecordIter<'a> {
+  type Item = RecordView<'a>;
+
+  fn next(&mut self) -> Option<RecordView<'a>> {
+    let record = self.section.record(self.index)?;
+    self.index += 1;
+    Some(record)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.section.len().saturating_sub(self.index);
+    (remaining, Some(remaining))
+  }
+}
+
+impl<'a> ExactSizeIterator for RecordIter<'a> {}
+
+/// A memory mapped ILDA file.
+///
+/// The file must not be modified while it's mapped, or the view of it may
+/// change underneath any borrowed sections.
+#[cfg(feature = "mmap")]
+pub struct MappedFile {
+  map: ::memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+  /// Memory map a file.
+  pub fn open(filename: &str) -> Result<MappedFile, IldaError> {
+    let file = ::std::fs::File::open(filename)?;
+    // Safety: the mapping is read only, and callers are told not to modify
+    // the file while it's open.
+    let map = unsafe { ::memmap2::Mmap::map(&file)? };
+    Ok(MappedFile { map })
+  }
+
+  /// A view over the whole file.
+  pub fn view(&self) -> IldaView<'_> {
+    IldaView::new(&self.map)
+  }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+  u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn read_i16(bytes: &[u8], at: usize) -> i16 {
+  i16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser;
+  use writer;
+
+  #[test]
+  fn test_matches_parser() {
+    let bytes = include_bytes!("../examples/files/ildatest.ild");
+    let view = IldaView::new(bytes);
+
+    let entries: Vec<IldaEntry> = view.sections()
+        .flat_map(|section| section.unwrap().to_entries())
+        .collect();
+    assert_eq!(&bytes[..], &writer::write_bytes(&entries)[..]);
+    assert_eq!(parser::read_bytes(bytes).unwrap().len(), entries.len());
+  }
+
+  #[test]
+  fn test_accessors() {
+    let mut header = Header::new(4);
+    header.record_count = 2;
+    header.name = Some("frame".to_string());
+    header.number = 3;
+    header.projector_number = 1;
+    let point = TrueColorPoint3d {
+      x: -2, y: 300, z: 7, status_code: 128, r: 10, g: 20, b: 30,
+    };
+    let bytes = writer::write_bytes(&[
+      IldaEntry::HeaderEntry(header),
+      IldaEntry::TcPoint3dEntry(TrueColorPoint3d::default()),
+      IldaEntry::TcPoint3dEntry(point),
+      IldaEntry::HeaderEntry(Header::new(4)),
+    ]);
+
+    let view = IldaView::new(&bytes);
+    let sections: Vec<_> = view.sections().map(|s| s.unwrap()).collect();
+    assert_eq!(2, sections.len());
+
+    let section = sections[0];
+    assert_eq!(Format::TrueColor3d, section.get_format());
+    assert_eq!(Some("frame".to_string()), section.name());
+    assert_eq!((2, 3, 1), (section.len(), section.number(),
+        section.projector_number()));
+    assert_eq!(52, sections[1].offset());
+    assert!(sections[1].is_empty());
+
+    let record = section.record(1).unwrap();
+    assert_eq!((-2, 300, 7), (record.x(), record.y(), record.z()));
+    assert_eq!(Some((10, 20, 30)), record.rgb());
+    assert!(record.is_last_point() && !record.is_blank());
+    assert!(section.record(2).is_none());
+    assert_eq!(2, section.records().len());
+
+    let again = view.section_at(52).unwrap();
+    assert_eq!(0, again.len());
+    assert!(view.section_at(10).is_err());
+    assert!(view.section_at(usize::MAX - 4).is_err());
+  }
+
+  #[test]
+  fn test_truncated() {
+    let mut header = Header::new(5);
+    header.record_count = 3;
+    let mut bytes = header.to_bytes().to_vec();
+    bytes.extend_from_slice(&[0; 8]);
+
+    let results: Vec<_> = IldaView::new(&bytes).sections().collect();
+    assert_eq!(1, results.len());
+    assert!(matches!(results[0], Err(IldaError::InvalidData)));
+  }
+}
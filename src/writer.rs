@@ -0,0 +1,81 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Low level serialization of headers and data fields back into binary ILDA
+//! data. This is the counterpart of the `parser` module; entries are written
+//! exactly as given, so it's up to the caller to keep record counts and
+//! formats consistent.
+
+use data::IldaEntry;
+use error::IldaError;
+use std::fs::File;
+use std::io::Write;
+
+/// Write ILDA data to a file.
+pub fn write_file(filename: &str, entries: &[IldaEntry])
+    -> Result<(), IldaError> {
+  let mut file = File::create(filename)?;
+  file.write_all(&write_bytes(entries))?;
+  Ok(())
+}
+
+/// Write ILDA data to raw bytes.
+pub fn write_bytes(entries: &[IldaEntry]) -> Vec<u8> {
+  let mut out = Vec::new();
+  for entry in entries {
+    out.extend_from_slice(&entry.to_bytes());
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::write_bytes;
+  use data::Header;
+  use data::IldaEntry;
+  use data::TrueColorPoint2d;
+  use parser::read_bytes;
+
+  #[test]
+  fn test_write_bytes_round_trip() {
+    let mut header = Header::new(5);
+    header.name = Some("test".to_string());
+    header.record_count = 2;
+    header.projector_number = 3;
+
+    let point = TrueColorPoint2d {
+      x: -100,
+      r: 200,
+      ..Default::default()
+    };
+
+    let entries = vec![
+      IldaEntry::HeaderEntry(header),
+      IldaEntry::TcPoint2dEntry(point.clone()),
+      IldaEntry::TcPoint2dEntry(point),
+      IldaEntry::HeaderEntry(Header::new(5)),
+    ];
+
+    let bytes = write_bytes(&entries);
+    assert_eq!(32 + 8 + 8 + 32, bytes.len());
+
+    let read = read_bytes(&bytes).unwrap();
+    assert_eq!(4, read.len());
+
+    match read[0] {
+      IldaEntry::HeaderEntry(ref header) => {
+        assert_eq!(Some("test".to_string()), header.name);
+        assert_eq!(2, header.record_count);
+        assert_eq!(3, header.projector_number);
+      },
+      _ => panic!("expected header"),
+    }
+
+    match read[2] {
+      IldaEntry::TcPoint2dEntry(ref point) => {
+        assert_eq!(-100, point.x);
+        assert_eq!(200, point.r);
+      },
+      _ => panic!("expected point"),
+    }
+  }
+}